@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use moleculer::{config::Channel, fuzzing};
+
+fuzz_target!(|data: &[u8]| fuzzing::parse_packet(&Channel::Info, data));
@@ -1,32 +1,54 @@
+mod discoverer;
+mod internal;
 mod registry;
-
-use std::{collections::HashMap, sync::Arc};
+mod strategy;
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
 use act_zero::*;
 use async_trait::async_trait;
-use log::{error, warn};
-use serde_json::Value;
-use tokio::sync::oneshot::Sender;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    oneshot::{self, Sender},
+    Notify,
+};
 
 use crate::{
     channels::messages::{
-        incoming::{
-            DisconnectMessage, EventMessage, HeartbeatMessage, InfoMessage, RequestMessage,
-        },
+        incoming::{PacketDisconnect, PacketEvent, PacketHeartbeat, PacketInfo, PacketRequest},
         outgoing::{self},
+        PacketType,
     },
-    service::Action,
+    errors::MoleculerError,
+    service::{Action, ActionDescription, Endpoint, Execution, ServiceInfo},
 };
 
 use crate::{
-    channels::{self, ChannelSupervisor},
-    config::{self, Channel, DeserializeError, Serializer},
-    service::{Context, Event, Service},
+    channels::{self, BalancedSubject, ChannelSupervisor},
+    clock::{Clock, Sleep},
+    config::{
+        self, Channel, HandlerPanics, PacketDirection, ReconnectGiveUp, Serializer, Strategy,
+        UnhandledEvents,
+    },
+    service::{event_expiry, Context, Event, Service},
+    stream::{Chunk, RequestStream, RequestStreamSender, RequestStreamWriter},
+    CallOptions, ConnectionState, PendingInfo, RegistryChange,
 };
 
 use thiserror::Error;
 
-use self::registry::Registry;
+use self::{internal::ActionListOptions, registry::Registry};
 
 #[derive(Error, Debug)]
 pub(crate) enum Error {
@@ -56,6 +78,15 @@ pub(crate) enum Error {
 
     #[error("Node not found for ('{0}') event or action")]
     NodeNotFound(String),
+
+    #[error("Service '{0}' failed to start: {1}")]
+    ServiceStartFailed(String, String),
+
+    #[error("Call to '{0}' exceeds the max call level ({1})")]
+    MaxCallLevel(String, u32),
+
+    #[error("Service broker stopped")]
+    Stopped,
 }
 
 #[allow(dead_code)]
@@ -71,22 +102,196 @@ pub(crate) struct ServiceBroker {
 
     pub(crate) registry: Registry,
 
+    transporter_connected: bool,
+    discovered: bool,
+    draining: bool,
+    connection: Arc<ConnectionStatus>,
+
+    in_flight: Arc<InFlight>,
+    request_streams: HashMap<String, RequestStreamWriter>,
+    registry_subscribers: Vec<UnboundedSender<RegistryChange>>,
+    // by node id, see `liveness_checks` in the config
+    liveness: HashMap<String, NodeLiveness>,
+    // by event name, see `event_replay` in the config
+    kept_events: HashMap<String, VecDeque<KeptEvent>>,
+
     pid: Addr<Self>,
     channel_supervisor: Addr<ChannelSupervisor>,
     config: Arc<config::Config>,
 }
 
+/// The meta of an event and its place in the request chain, inherited from the emitting context
+#[derive(Debug, Clone)]
+pub(crate) struct EventHeaders {
+    pub(crate) meta: Value,
+    pub(crate) level: i32,
+    pub(crate) caller: Option<String>,
+    pub(crate) parent_id: Option<String>,
+    pub(crate) request_id: Option<String>,
+}
+
+impl Default for EventHeaders {
+    // a top-level event
+    fn default() -> Self {
+        Self {
+            meta: json!({}),
+            level: 1,
+            caller: None,
+            parent_id: None,
+            request_id: None,
+        }
+    }
+}
+
+/// An event emitted or broadcast while this node had no listener for it, sent to the next one
+struct KeptEvent {
+    params: Value,
+    headers: EventHeaders,
+    broadcast: bool,
+}
+
+/// Result of the liveness checks of a node
+#[derive(Default)]
+struct NodeLiveness {
+    // id of the PING waiting for its PONG
+    pending_ping: Option<String>,
+    missed: u32,
+    degraded: bool,
+}
+
+/// The [ConnectionState] of the broker, shared with its handles so it is read without a
+/// message to the broker
+pub(crate) struct ConnectionStatus {
+    state: AtomicU8,
+    subscribers: Mutex<Vec<UnboundedSender<ConnectionState>>>,
+}
+
+impl ConnectionStatus {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: AtomicU8::new(ConnectionState::Connecting as u8),
+            subscribers: Mutex::default(),
+        }
+    }
+
+    pub(crate) fn get(&self) -> ConnectionState {
+        ConnectionState::from_u8(self.state.load(Ordering::SeqCst))
+    }
+
+    // subscribers are only told about actual changes
+    fn set(&self, state: ConnectionState) {
+        if self.state.swap(state as u8, Ordering::SeqCst) == state as u8 {
+            return;
+        }
+
+        info!("Connection state: {:?}", state);
+
+        self.lock()
+            .retain(|subscriber| subscriber.send(state).is_ok());
+    }
+
+    pub(crate) fn subscribe(&self) -> UnboundedReceiver<ConnectionState> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.lock().push(tx);
+        rx
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<UnboundedSender<ConnectionState>>> {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The requests whose callbacks are running, lets a drain or a stop wait for them to finish
+#[derive(Default)]
+pub(crate) struct InFlight {
+    requests: Mutex<HashMap<u64, RunningRequest>>,
+    next_id: AtomicU64,
+    idle: Notify,
+}
+
+// a request whose callback is running, with what's needed to answer it if it is abandoned
+struct RunningRequest {
+    action: String,
+    node_id: String,
+    request_id: String,
+}
+
+impl InFlight {
+    fn start(self: &Arc<Self>, request: RunningRequest) -> InFlightGuard {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.lock().insert(id, request);
+
+        InFlightGuard {
+            in_flight: Arc::clone(self),
+            id,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<u64, RunningRequest>> {
+        self.requests
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Completes once no request callbacks are running
+    pub(crate) async fn idle(&self) {
+        loop {
+            // registered before checking the requests so one finishing in between isn't missed
+            let idle = self.idle.notified();
+
+            if self.lock().is_empty() {
+                return;
+            }
+
+            idle.await;
+        }
+    }
+
+    // stops waiting for the running callbacks, they are no longer counted when they finish
+    fn abandon(&self) -> Vec<RunningRequest> {
+        let requests = self.lock().drain().map(|(_, request)| request).collect();
+        self.idle.notify_waiters();
+
+        requests
+    }
+}
+
+// removes the request on drop so panicking callbacks are counted as finished too
+struct InFlightGuard {
+    in_flight: Arc<InFlight>,
+    id: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut requests = self.in_flight.lock();
+
+        if requests.remove(&self.id).is_some() && requests.is_empty() {
+            self.in_flight.idle.notify_waiters();
+        }
+    }
+}
+
+// ids of a request sent by this node
+pub(crate) struct SentRequest {
+    id: String,
+    request_id: String,
+}
+
 pub(crate) struct Events(HashMap<String, Event>);
 pub(crate) struct Actions(HashMap<String, Action>);
 
 impl Events {
-    fn new() -> Self {
-        Events(HashMap::new())
-    }
-
     fn get(&self, key: &str) -> Option<&Event> {
         self.0.get(key)
     }
+
+    // the group of the listener on this node
+    fn group(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|event| event.group.as_deref())
+    }
 }
 
 impl From<&Vec<Service>> for Events {
@@ -94,20 +299,26 @@ impl From<&Vec<Service>> for Events {
         Events(
             services
                 .iter()
-                .flat_map(|service| service.events.clone())
+                .flat_map(|service| {
+                    service.events.iter().map(move |(name, event)| {
+                        let mut event = event.clone();
+                        event.group.get_or_insert_with(|| service.name.clone());
+                        (name.clone(), event)
+                    })
+                })
                 .collect(),
         )
     }
 }
 
 impl Actions {
-    fn new() -> Self {
-        Actions(HashMap::new())
-    }
-
     fn get(&self, key: &str) -> Option<&Action> {
         self.0.get(key)
     }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
 }
 
 impl From<&Vec<Service>> for Actions {
@@ -126,13 +337,31 @@ impl Actor for ServiceBroker {
     async fn started(&mut self, pid: Addr<Self>) -> ActorResult<()> {
         self.pid = pid.clone();
 
-        let channel_supervisor = channels::start_supervisor(pid, Arc::clone(&self.config))
-            .await
-            .map_err(Error::Channel)?;
+        if self.config.log_config {
+            info!(
+                "Effective config: {}",
+                serde_json::to_string(&*self.config)?
+            );
+        }
 
+        let channel_supervisor =
+            match channels::start_supervisor(pid, Arc::clone(&self.config)).await {
+                Ok(channel_supervisor) => channel_supervisor,
+                Err(err) => {
+                    self.connection.set(ConnectionState::Failed);
+                    return Err(Error::Channel(err).into());
+                }
+            };
+        self.connection.set(ConnectionState::Connected);
+
+        self.registry.discoverer().start(self.pid.downgrade());
         send!(self.pid.broadcast_info());
         send!(channel_supervisor.broadcast_discover());
 
+        self.transporter_connected = true;
+        self.discovered = true;
+        self.connection.set(ConnectionState::Discovered);
+
         self.channel_supervisor = channel_supervisor.clone();
 
         self.pid
@@ -142,63 +371,391 @@ impl Actor for ServiceBroker {
     }
 
     async fn error(&mut self, error: ActorError) -> bool {
+        // returned by `stop()` to stop the actor
+        if let Some(Error::Stopped) = error.downcast_ref::<Error>() {
+            return true;
+        }
+
         log::error!("ServiceBroker Actor Error: {:?}", error);
         // do not stop on actor error
         false
     }
 }
 impl ServiceBroker {
-    pub(crate) fn new(config: config::Config) -> Self {
+    pub(crate) fn new(config: config::Config, connection: Arc<ConnectionStatus>) -> Self {
+        let services = if config.internal_services {
+            vec![internal::node_service()]
+        } else {
+            vec![]
+        };
+        let config = Arc::new(config);
+
         Self {
             namespace: config.namespace.clone(),
             node_id: config.node_id.clone(),
             instance_id: config.instance_id.clone(),
             serializer: config.serializer.clone(),
 
-            services: vec![],
-
-            registry: Registry::new(),
-            events: Events::new(),
-            actions: Actions::new(),
+            registry: Registry::new(
+                config.strategy.clone(),
+                config.metadata_merge,
+                discoverer::from_config(&config),
+            ),
+            events: (&services).into(),
+            actions: (&services).into(),
+            services,
+
+            transporter_connected: false,
+            discovered: false,
+            draining: false,
+            connection,
+
+            in_flight: Arc::default(),
+            request_streams: HashMap::new(),
+            registry_subscribers: vec![],
+            liveness: HashMap::new(),
+            kept_events: HashMap::new(),
 
             pid: Addr::detached(),
             channel_supervisor: Addr::detached(),
-            config: Arc::new(config),
+            config,
         }
     }
 
     // exposed publicly via crate::ServiceBroker
-    pub(crate) async fn emit(&mut self, event_name: String, params: Value) -> ActorResult<()> {
-        let node_name = self
-            .registry
-            .get_node_name_for_event(&event_name)
-            .ok_or_else(|| Error::NodeNotFound(event_name.clone()))?;
+    pub(crate) async fn emit(
+        &mut self,
+        event_name: String,
+        params: Value,
+        mut headers: EventHeaders,
+        tx: Option<Sender<Result<(), crate::Error>>>,
+    ) -> ActorResult<()> {
+        headers.meta = self.config.with_default_meta(headers.meta);
+        self.store_durable_event(&event_name, &params, &headers, false)?;
+
+        // without a caller waiting for the result, errors are logged
+        let log_errors = tx.is_none();
+        let reply = |result| {
+            if let Some(tx) = tx {
+                let _ = tx.send(result);
+            }
+        };
+
+        // an emitted event is handled by one node of each group listening to it, the group of a
+        // listener on this node is handled here without a network round trip, the other groups
+        // are sent to one of their nodes
+        let mut local_group = self.events.group(&event_name).map(str::to_string);
+        let mut balanced_groups = vec![];
+        let mut node_names = vec![];
+
+        if self.config.disable_balancer {
+            // NATS picks the member of each group, this node included
+            balanced_groups = self.registry.get_event_groups(&event_name);
+
+            if let Some(local_group) = local_group.take() {
+                if !balanced_groups.contains(&local_group) {
+                    balanced_groups.push(local_group);
+                }
+            }
+        } else {
+            node_names = self.registry.get_node_names_for_event(
+                &event_name,
+                &params,
+                local_group.as_deref(),
+            );
+        }
+
+        if local_group.is_none() && node_names.is_empty() && balanced_groups.is_empty() {
+            if self.keep_event(&event_name, params, headers, false) {
+                reply(Ok(()));
+                return Produces::ok(());
+            }
+
+            match self.unhandled_event(&event_name) {
+                UnhandledEvents::Error => {
+                    let error = crate::Error::NoEventListeners(event_name);
+                    if log_errors {
+                        error!("{}", error);
+                    }
+                    reply(Err(error));
+                }
+                _ => reply(Ok(())),
+            }
+
+            return Produces::ok(());
+        }
+
+        let message =
+            outgoing::PacketEvent::new_for_emit(&self.config, &event_name, params.clone())
+                .with_headers(&headers);
+
+        // the members of a group may use different serializers, the default one is used
+        for group in balanced_groups {
+            let subject = config::balanced_event_subject(&self.config, &group, &event_name);
+            let message = outgoing::PacketEvent {
+                groups: Some(vec![group]),
+                ..message.clone()
+            };
+            config::log_packet(
+                &self.config,
+                PacketDirection::Outbound,
+                PacketType::Event,
+                &message,
+            );
+
+            send!(self.channel_supervisor.publish_buffered(
+                subject,
+                self.config.serialize_packet(&Channel::Event, &message)?,
+                None,
+                None
+            ));
+        }
+
+        for (node_name, groups) in node_names {
+            let node_event_channel = Channel::Event.external_channel(&self.config, &node_name);
+            let message = outgoing::PacketEvent {
+                groups: Some(groups),
+                ..message.clone()
+            };
+
+            send!(self.channel_supervisor.publish_buffered(
+                node_event_channel,
+                self.serialize_for_node(&Channel::Event, &node_name, message)?,
+                None,
+                None
+            ));
+        }
+
+        reply(Ok(()));
+
+        match local_group {
+            Some(local_group) => {
+                let event_message = PacketEvent {
+                    groups: Some(vec![local_group]),
+                    ..PacketEvent::new_local(&self.config, &event_name, params, false)
+                        .with_headers(headers)
+                };
+                self.handle_incoming_event(event_message).await
+            }
+            None => Produces::ok(()),
+        }
+    }
 
-        let node_event_channel = Channel::Event.external_channel(&self.config, node_name);
+    // keeps the event for a service added later, returns `false` if it isn't kept,
+    // see `event_replay` in the config
+    fn keep_event(
+        &mut self,
+        event_name: &str,
+        params: Value,
+        headers: EventHeaders,
+        broadcast: bool,
+    ) -> bool {
+        let size = match self.config.event_replay.get(event_name) {
+            Some(size) if *size > 0 => *size,
+            _ => return false,
+        };
+
+        let kept_events = self.kept_events.entry(event_name.to_string()).or_default();
+
+        if kept_events.len() >= size {
+            kept_events.pop_front();
+            debug!(
+                "Dropped the oldest kept '{}' event, {} are kept",
+                event_name, size
+            );
+        }
+
+        kept_events.push_back(KeptEvent {
+            params,
+            headers,
+            broadcast,
+        });
+
+        true
+    }
+
+    // sends the events kept for the events the service listens to, oldest first
+    async fn replay_kept_events(&mut self, service: &str) {
+        let event_names: Vec<String> = self
+            .services
+            .iter()
+            .filter(|added| added.name == service)
+            .flat_map(|added| added.events.keys().cloned())
+            .collect();
+
+        for event_name in event_names {
+            let kept_events = match self.kept_events.remove(&event_name) {
+                Some(kept_events) => kept_events,
+                None => continue,
+            };
+
+            debug!(
+                "Replaying {} kept '{}' events to service '{}'",
+                kept_events.len(),
+                event_name,
+                service
+            );
+
+            for kept_event in kept_events {
+                let event_message = PacketEvent::new_local(
+                    &self.config,
+                    &event_name,
+                    kept_event.params,
+                    kept_event.broadcast,
+                )
+                .with_headers(kept_event.headers);
+
+                if let Err(err) = self.handle_incoming_event(event_message).await {
+                    error!("Unable to replay kept event '{}': {}", event_name, err);
+                }
+            }
+        }
+    }
+
+    // how to handle an emitted event no node listens to,
+    // durable events wait in their stream for a consumer
+    fn unhandled_event(&self, event_name: &str) -> UnhandledEvents {
+        let durable = self
+            .config
+            .durable_events
+            .as_ref()
+            .is_some_and(|durable_events| durable_events.includes(event_name));
+
+        if durable {
+            return UnhandledEvents::Silent;
+        }
+
+        if self.config.unhandled_events == UnhandledEvents::Warn {
+            warn!(
+                "No listener for emitted event '{}', it was dropped",
+                event_name
+            );
+        }
+
+        self.config.unhandled_events
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn emit_to_node(
+        &self,
+        node_name: String,
+        event_name: String,
+        params: Value,
+        mut headers: EventHeaders,
+        broadcast: bool,
+        tx: Sender<Result<(), crate::Error>>,
+    ) -> ActorResult<()> {
+        headers.meta = self.config.with_default_meta(headers.meta);
+
+        // the event is explicitly addressed, the balancer isn't involved
+        if node_name == self.node_id {
+            let event_message =
+                PacketEvent::new_local(&self.config, &event_name, params, broadcast)
+                    .with_headers(headers);
+            let _ = tx.send(Ok(()));
+            return self.handle_incoming_event(event_message).await;
+        }
+
+        if !self.registry.has_node(&node_name) {
+            let _ = tx.send(Err(crate::Error::NodeNotFound(node_name.clone())));
+            return Err(Error::NodeNotFound(node_name).into());
+        }
+
+        let message = if broadcast {
+            outgoing::PacketEvent::new_for_broadcast(&self.config, &event_name, params)
+        } else {
+            outgoing::PacketEvent::new_for_emit(&self.config, &event_name, params)
+        };
+        let message = message.with_headers(&headers);
 
-        let message = outgoing::EventMessage::new_for_emit(&self.config, &event_name, params);
+        let node_event_channel = Channel::Event.external_channel(&self.config, &node_name);
 
-        send!(self
-            .channel_supervisor
-            .publish_to_channel(node_event_channel, serde_json::to_vec(&message)?));
+        send!(self.channel_supervisor.publish_buffered(
+            node_event_channel,
+            self.serialize_for_node(&Channel::Event, &node_name, message)?,
+            None,
+            None
+        ));
 
+        let _ = tx.send(Ok(()));
         Produces::ok(())
     }
 
-    pub(crate) async fn broadcast(&self, event_name: String, params: Value) -> ActorResult<()> {
-        let node_names = self
-            .registry
-            .get_all_nodes_for_event(&event_name)
-            .ok_or_else(|| Error::NodeNotFound(event_name.clone()))?;
+    pub(crate) async fn broadcast(
+        &mut self,
+        event_name: String,
+        params: Value,
+        mut headers: EventHeaders,
+    ) -> ActorResult<()> {
+        headers.meta = self.config.with_default_meta(headers.meta);
+        self.store_durable_event(&event_name, &params, &headers, true)?;
+
+        // this node is never in the registry, so its listeners only get the local copy
+        let local_listener = self.events.get(&event_name).is_some();
+
+        if !local_listener {
+            self.keep_event(&event_name, params.clone(), headers.clone(), true);
+        }
+
+        let node_names = match self.registry.get_all_nodes_for_event(&event_name) {
+            Some(node_names) => node_names,
+            None if local_listener => vec![],
+            // a broadcast doesn't expect listeners, see `UnhandledEvents`
+            None => return Produces::ok(()),
+        };
+
+        if local_listener {
+            let event_message =
+                PacketEvent::new_local(&self.config, &event_name, params.clone(), true)
+                    .with_headers(headers.clone());
+
+            if let Err(err) = self.handle_incoming_event(event_message).await {
+                error!("Unable to handle local event '{}': {}", event_name, err);
+            }
+        }
+
+        if node_names.is_empty() {
+            return Produces::ok(());
+        }
+
+        let message = outgoing::PacketEvent::new_for_broadcast(&self.config, &event_name, params)
+            .with_headers(&headers);
 
-        let message = outgoing::EventMessage::new_for_broadcast(&self.config, &event_name, params);
+        config::log_packet(
+            &self.config,
+            PacketDirection::Outbound,
+            PacketType::Event,
+            &message,
+        );
+
+        // serialize once per serializer used by the receiving nodes
+        let mut serialized_messages: HashMap<&Serializer, Vec<u8>> = HashMap::new();
 
         for node_name in node_names {
+            let serializer = self.config.serializer_for_node(
+                &Channel::Event,
+                self.registry.get_node_serializers(&node_name),
+            );
+
+            let serialized_message = match serialized_messages.get(serializer) {
+                Some(serialized_message) => serialized_message.clone(),
+                None => {
+                    let serialized_message =
+                        self.config
+                            .serialize_with(serializer, PacketType::Event, &message)?;
+                    serialized_messages.insert(serializer, serialized_message.clone());
+                    serialized_message
+                }
+            };
+
             let node_event_channel = Channel::Event.external_channel(&self.config, node_name);
 
-            send!(self
-                .channel_supervisor
-                .publish_to_channel(node_event_channel, serde_json::to_vec(&message)?));
+            send!(self.channel_supervisor.publish_buffered(
+                node_event_channel,
+                serialized_message,
+                None,
+                None
+            ));
         }
 
         Produces::ok(())
@@ -208,53 +765,351 @@ impl ServiceBroker {
         &mut self,
         action: String,
         params: Value,
-        tx: Sender<Value>,
+        options: CallOptions,
+        tx: Sender<Result<Value, crate::Error>>,
     ) -> ActorResult<()> {
-        let node_name = self
-            .registry
-            .get_node_name_for_action(&action)
-            .ok_or_else(|| Error::NodeNotFound(action.clone()))?;
+        let action = self.action_name(action);
+
+        if !options.fallback_nodes.is_empty() {
+            let timeout = options.timeout.unwrap_or(self.config.request_timeout);
+            let deadline = self.config.clock.now() + Duration::from_millis(timeout.max(0) as u64);
+
+            tokio::spawn(call_fallback_nodes(
+                self.pid.clone(),
+                Arc::clone(&self.config.clock),
+                deadline,
+                action,
+                params,
+                options,
+                tx,
+            ));
+
+            return Produces::ok(());
+        }
+
+        let retries = self.call_retries(&action, &options);
+
+        if retries > 0 {
+            tokio::spawn(call_with_retries(
+                self.pid.clone(),
+                Arc::clone(&self.config),
+                retries,
+                action,
+                params,
+                options,
+                tx,
+            ));
+
+            return Produces::ok(());
+        }
+
+        self.send_request(&action, params, &options, tx).await?;
+
+        Produces::ok(())
+    }
+
+    // the call's own retries, then the action's, then the global retry policy's
+    fn call_retries(&self, action_name: &str, options: &CallOptions) -> u32 {
+        options
+            .retries
+            .or_else(|| self.action_retries(action_name))
+            .unwrap_or_else(|| self.config.retry_policy.default_retries())
+    }
+
+    // the retries in the `retryPolicy` of the action's schema, the local one first
+    fn action_retries(&self, action_name: &str) -> Option<u32> {
+        let local = self.actions.get(action_name);
+        let remote = || {
+            self.registry
+                .get_node_services()
+                .find_map(|(_, service)| service.actions.get(action_name))
+        };
+
+        local
+            .or_else(remote)?
+            .retry_policy
+            .map(|policy| policy.retries())
+    }
+
+    // an attempt of a call with fallback nodes or retries
+    async fn call_node(
+        &mut self,
+        action: String,
+        params: Value,
+        options: CallOptions,
+        tx: Sender<Result<Value, crate::Error>>,
+    ) -> ActorResult<()> {
+        self.send_request(&action, params, &options, tx).await?;
+
+        Produces::ok(())
+    }
 
-        let node_request_channel = Channel::Request.external_channel(&self.config, &node_name);
-        let message = outgoing::RequestMessage::new(&self.config, &action, params);
-        let serialized_message = serde_json::to_vec(&message)?;
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn call_stream(
+        &mut self,
+        action: String,
+        options: CallOptions,
+        tx: Sender<Result<Value, crate::Error>>,
+    ) -> ActorResult<RequestStreamSender> {
+        let action = self.action_name(action);
+
+        // the chunks follow in packets with the same ids, the first one has no params
+        let (node_name, message) = self
+            .send_request(&action, Value::Null, &options, tx)
+            .await?;
+
+        let serializer = self
+            .config
+            .serializer_for_node(
+                &Channel::Request,
+                self.registry.get_node_serializers(&node_name),
+            )
+            .clone();
+
+        Produces::ok(RequestStreamSender {
+            config: Arc::clone(&self.config),
+            channel_supervisor: self.channel_supervisor.clone(),
+            channel: Channel::Request.external_channel(&self.config, &node_name),
+            serializer,
+
+            id: message.id,
+            request_id: message.request_id,
+            level: options.level.saturating_add(1),
+            caller: options.caller,
+            parent_id: options.parent_id,
+            action,
+
+            seq: 0,
+            finished: false,
+        })
+    }
+
+    // sends the REQ packet and starts waiting for its response,
+    // returns the node it was sent to and the packet's ids
+    async fn send_request(
+        &mut self,
+        action: &str,
+        params: Value,
+        options: &CallOptions,
+        tx: Sender<Result<Value, crate::Error>>,
+    ) -> Result<(String, SentRequest), ActorError> {
+        let max_call_level = self.config.max_call_level;
+
+        // `0` disables the limit
+        if max_call_level > 0 && options.level >= max_call_level as i32 {
+            let error =
+                MoleculerError::max_call_level(options.level.saturating_add(1), &self.node_id);
+            let _ = tx.send(Err(error.into()));
+            return Err(Error::MaxCallLevel(action.to_string(), max_call_level).into());
+        }
 
-        call!(self
-            .channel_supervisor
-            .start_response_waiter(node_name, message.request_id, tx))
+        let node_name = match &options.node_id {
+            Some(node_id) if self.node_has_action(node_id, action) => node_id.clone(),
+            Some(node_id) => {
+                let error = MoleculerError::service_not_found(action, Some(node_id));
+                let _ = tx.send(Err(error.into()));
+                return Err(Error::NodeNotFound(action.to_string()).into());
+            }
+            None => match self.registry.get_node_name_for_action(action, &params) {
+                Some(node_name) => node_name,
+                None => {
+                    let _ = tx.send(Err(MoleculerError::service_not_found(action, None).into()));
+                    return Err(Error::NodeNotFound(action.to_string()).into());
+                }
+            },
+        };
+        let timeout = options.timeout.unwrap_or(self.config.request_timeout);
+
+        let mut message = outgoing::PacketRequest::new(&self.config, action, params, options);
+        if options.stream {
+            message.stream = Some(true);
+            message.seq = Some(0);
+        }
+
+        // NATS picks one of the nodes with the action, `node_name` only confirmed there is one.
+        // The chunks of a stream follow its first packet to the same node
+        let balanced = self.config.disable_balancer && options.node_id.is_none() && !options.stream;
+
+        let (node_request_channel, serialized_message) = if balanced {
+            config::log_packet(
+                &self.config,
+                PacketDirection::Outbound,
+                PacketType::Request,
+                &message,
+            );
+            (
+                config::balanced_request_subject(&self.config, action),
+                self.config.serialize_packet(&Channel::Request, &message)?,
+            )
+        } else {
+            (
+                Channel::Request.external_channel(&self.config, &node_name),
+                self.serialize_for_node(&Channel::Request, &node_name, &message)?,
+            )
+        };
+        let sent_request = SentRequest {
+            id: message.id.clone(),
+            request_id: message.request_id.clone(),
+        };
+
+        call!(self.channel_supervisor.start_response_waiter(
+            timeout,
+            node_name.clone(),
+            action.to_string(),
+            message.id,
+            tx
+        ))
         .await?;
 
-        send!(self
-            .channel_supervisor
-            .publish_to_channel(node_request_channel, serialized_message));
+        // the caller stops waiting after the timeout, a later send would be wasted
+        let deadline = self.config.clock.now() + Duration::from_millis(timeout.max(0) as u64);
+
+        send!(self.channel_supervisor.publish_buffered(
+            node_request_channel,
+            serialized_message,
+            Some(deadline),
+            Some(sent_request.id.clone())
+        ));
+
+        Ok((node_name, sent_request))
+    }
+
+    // a copy of a durable event is stored in its stream, whether or not a listener is reachable
+    fn store_durable_event(
+        &self,
+        event_name: &str,
+        params: &Value,
+        headers: &EventHeaders,
+        broadcast: bool,
+    ) -> Result<(), config::SerializeError> {
+        let durable_events = match &self.config.durable_events {
+            Some(durable_events) if durable_events.includes(event_name) => durable_events,
+            _ => return Ok(()),
+        };
+
+        let message = if broadcast {
+            outgoing::PacketEvent::new_for_broadcast(&self.config, event_name, params.clone())
+        } else {
+            outgoing::PacketEvent::new_for_emit(&self.config, event_name, params.clone())
+        };
+        let message = message.with_headers(headers);
+
+        send!(self.channel_supervisor.publish_durable(
+            durable_events.subject(&self.config, event_name),
+            message.id.clone(),
+            self.config.serialize_packet(&Channel::Event, &message)?
+        ));
+
+        Ok(())
+    }
+
+    // requests to this node are sent through the transporter like to any other node
+    fn start_durable_consumer(&self, service: &Service) {
+        let (durable_events, options) =
+            match (&self.config.durable_events, &service.durable_consumer) {
+                (Some(durable_events), Some(options)) => (durable_events, options),
+                _ => return,
+            };
+
+        let events: Vec<String> = service
+            .events
+            .keys()
+            .filter(|event_name| durable_events.includes(event_name))
+            .cloned()
+            .collect();
+
+        if !events.is_empty() {
+            send!(self.channel_supervisor.start_durable_consumer(
+                service.name.clone(),
+                options.clone(),
+                events
+            ));
+        }
+    }
+
+    // a local service consumes the event from its stream
+    fn has_durable_consumer(&self, event_name: &str) -> bool {
+        match &self.config.durable_events {
+            Some(durable_events) if durable_events.includes(event_name) => {
+                self.services.iter().any(|service| {
+                    service.durable_consumer.is_some() && service.events.contains_key(event_name)
+                })
+            }
+            _ => false,
+        }
+    }
+
+    // the registered name of an action called with another case, see `case_insensitive_actions`
+    fn action_name(&self, action: String) -> String {
+        if !self.config.case_insensitive_actions
+            || self.actions.contains_key(&action)
+            || self.registry.has_action(&action)
+        {
+            return action;
+        }
+
+        self.actions
+            .0
+            .keys()
+            .map(String::as_str)
+            .chain(self.registry.get_action_names())
+            .find(|name| name.eq_ignore_ascii_case(&action))
+            .map_or(action, String::from)
+    }
+
+    fn node_has_action(&self, node_id: &str, action: &str) -> bool {
+        if node_id == self.node_id {
+            self.actions.contains_key(action)
+        } else {
+            self.registry.node_has_action(node_id, action)
+        }
+    }
+
+    pub(crate) async fn reply_error(
+        &self,
+        node: String,
+        id: String,
+        error: MoleculerError,
+    ) -> ActorResult<()> {
+        let message = outgoing::PacketResponse::new_error(&self.config, &id, error);
+
+        let reply_channel = Channel::Response.external_channel(&self.config, &node);
+
+        send!(self.channel_supervisor.publish_to_channel(
+            reply_channel,
+            self.serialize_for_node(&Channel::Response, &node, message)?
+        ));
 
         Produces::ok(())
     }
 
     pub(crate) async fn reply(&self, node: String, id: String, reply: Value) -> ActorResult<()> {
-        let message = outgoing::ResponseMessage::new(&self.config, &id, reply);
+        let message = outgoing::PacketResponse::new(&self.config, &id, reply);
 
-        let reply_channel = Channel::Response.external_channel(&self.config, node);
+        let reply_channel = Channel::Response.external_channel(&self.config, &node);
 
-        send!(self
-            .channel_supervisor
-            .publish_to_channel(reply_channel, serde_json::to_vec(&message)?));
+        send!(self.channel_supervisor.publish_to_channel(
+            reply_channel,
+            self.serialize_for_node(&Channel::Response, &node, message)?
+        ));
 
         Produces::ok(())
     }
 
     // private
 
-    pub(crate) async fn handle_info_message(&mut self, info: InfoMessage) {
-        if self.node_id != info.sender {
-            self.registry
-                .add_or_update_node(self.pid.clone(), self.config.heartbeat_timeout, info);
+    pub(crate) async fn handle_info_message(&mut self, info: PacketInfo) {
+        if self.node_id != info.envelope.sender {
+            let changes = self.registry.add_or_update_node(self.pid.clone(), info);
+
+            self.publish_registry_changes(changes).await;
         }
     }
 
-    pub(crate) async fn handle_disconnect_message(&mut self, disconnect: DisconnectMessage) {
-        if self.node_id != disconnect.sender {
-            self.registry.remove_node(disconnect.sender);
+    pub(crate) async fn handle_disconnect_message(&mut self, disconnect: PacketDisconnect) {
+        if self.node_id != disconnect.envelope.sender {
+            let changes = self.registry.remove_node(disconnect.envelope.sender, false);
+            self.publish_registry_changes(changes).await;
         }
     }
 
@@ -263,82 +1118,1968 @@ impl ServiceBroker {
             "Node {} expectedly disconnected (missed heartbeat)",
             &node_name
         );
-        self.registry.remove_node(node_name);
+        let changes = self.registry.remove_node(node_name, true);
+        self.publish_registry_changes(changes).await;
     }
 
-    pub(crate) async fn handle_heartbeat_message(&mut self, heartbeat: HeartbeatMessage) {
-        if self.node_id != heartbeat.sender {
+    pub(crate) async fn handle_heartbeat_message(&mut self, heartbeat: PacketHeartbeat) {
+        if self.node_id != heartbeat.envelope.sender {
+            // the node restarted and its INFO packet was missed (or hasn't arrived yet), forget
+            // its previous instance and ask for the new one's INFO
+            if let Some(instance_id) = &heartbeat.instance_id {
+                if self
+                    .registry
+                    .is_new_instance(&heartbeat.envelope.sender, instance_id)
+                {
+                    let changes = self
+                        .registry
+                        .reset_restarted_node(&heartbeat.envelope.sender);
+                    self.publish_registry_changes(changes).await;
+
+                    if let Err(err) = self.send_discover_to_node(&heartbeat.envelope.sender) {
+                        warn!("Unable to request INFO from restarted node: {}", err);
+                    }
+
+                    return;
+                }
+            }
+
+            // the node's services changed but its INFO packet was missed, ask for it again
+            if let Some(seq) = self.registry.get_node_seq(&heartbeat.envelope.sender) {
+                if heartbeat.seq > seq {
+                    if let Err(err) = self.send_discover_to_node(&heartbeat.envelope.sender) {
+                        warn!("Unable to request INFO from outdated node: {}", err);
+                    }
+                }
+            }
+
             self.registry.update_node(heartbeat);
         }
     }
 
-    pub(crate) async fn add_service(&mut self, service: Service) {
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn add_service(&mut self, service: Service) -> ActorResult<()> {
+        if let Some(started) = service.started {
+            let handler_panics = self.config.handler_panics;
+            run_callback(started, self.handle(), handler_panics, || {
+                format!("started hook of service '{}'", service.name)
+            })
+            .map_err(|err| Error::ServiceStartFailed(service.name.clone(), err.to_string()))?;
+        }
+
+        self.start_durable_consumer(&service);
+
+        let service_name = service.name.clone();
+
+        self.config.seq.fetch_add(1, Ordering::SeqCst);
         self.services.push(service);
         self.events = (&self.services).into();
         self.actions = (&self.services).into();
+
+        self.replay_kept_events(&service_name).await;
+
+        // services added before the broker connected are in its first INFO
+        if self.transporter_connected {
+            self.broadcast_info().await?;
+        }
+
+        Produces::ok(())
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn remove_service(&mut self, name: String) -> ActorResult<bool> {
+        let services_count = self.services.len();
+        self.services.retain(|service| service.name != name);
+
+        if self.services.len() == services_count {
+            return Produces::ok(false);
+        }
+
+        send!(self.channel_supervisor.stop_durable_consumer(name));
+
+        // callbacks already running keep their context, only new requests get not found
+        self.config.seq.fetch_add(1, Ordering::SeqCst);
+        self.events = (&self.services).into();
+        self.actions = (&self.services).into();
+
+        if self.transporter_connected {
+            self.broadcast_info().await?;
+        }
+
+        Produces::ok(true)
     }
 
     pub(crate) async fn add_services(&mut self, services: Vec<Service>) {
         for service in services {
-            self.add_service(service).await;
+            if let Err(err) = self.add_service(service).await {
+                error!("{}", err);
+            }
         }
     }
 
     pub(crate) async fn publish_info_to_channel(&self, channel: String) -> ActorResult<()> {
-        let info = outgoing::InfoMessage::new(&self.config, &self.services);
-        send!(self
-            .channel_supervisor
-            .publish_to_channel(channel, self.serializer.serialize(info)?));
+        // a draining node keeps its services (and events) but stops advertising their actions
+        let draining_services: Vec<Service>;
+        let services = if self.draining {
+            draining_services = self
+                .services
+                .iter()
+                .cloned()
+                .map(|mut service| {
+                    service.actions.clear();
+                    service
+                })
+                .collect();
+
+            &draining_services
+        } else {
+            &self.services
+        };
+
+        let info = outgoing::PacketInfo::new(&self.config, services);
+        let info = self.config.serialize_packet(&Channel::Info, info)?;
+
+        self.registry.discoverer().local_info(&info);
+        send!(self.channel_supervisor.publish_to_channel(channel, info));
 
         Produces::ok(())
     }
 
     pub(crate) async fn handle_incoming_event(
         &self,
-        event_message: Result<EventMessage, DeserializeError>,
+        event_message: PacketEvent,
     ) -> ActorResult<()> {
-        let event_message = event_message?;
-
         let event = self
             .events
             .get(&event_message.event)
             .ok_or_else(|| Error::EventNotFound(event_message.event.clone()))?;
 
+        // an emitted event is sent with the groups this node was picked for
+        if let (Some(groups), Some(group)) = (&event_message.groups, &event.group) {
+            if !groups.contains(group) {
+                debug!(
+                    "Event '{}' skipped, it was emitted to groups {:?} and not to '{}'",
+                    event_message.event, groups, group
+                );
+                return Produces::ok(());
+            }
+        }
+
+        // delivered from the stream instead, see `handle_durable_event`
+        if self.has_durable_consumer(&event_message.event) {
+            return Produces::ok(());
+        }
+
+        if let Some(seen_ids) = &event.seen_ids {
+            if !event_message.id.is_empty()
+                && !seen_ids.insert(&event_message.id, self.config.clock.now())
+            {
+                self.skip_duplicate_event(&event_message);
+                return Produces::ok(());
+            }
+        }
+
         let callback = event
             .callback
             .ok_or_else(|| Error::EventCallbackNotFound(event_message.event.clone()))?;
 
-        let event_context = Context::<Event>::new(event_message, self.pid.clone().into());
-
-        callback(event_context).map_err(|err| Error::EventCallbackFailed(err.to_string()))?;
+        let event_name = event_message.event.clone();
+        let event_context = self.event_context(event_message);
+        let handler_panics = self.config.handler_panics;
+
+        let queue = match &event.queue {
+            Some(queue) => queue,
+            None => {
+                run_callback(callback, event_context, handler_panics, || {
+                    format!("event '{}'", event_name)
+                })
+                .map_err(|err| Error::EventCallbackFailed(err.to_string()))?;
+
+                return Produces::ok(());
+            }
+        };
+
+        let failed_event = event_name.clone();
+        let submitted = queue.submit(Execution::Task, move || {
+            let describe = || format!("event '{}'", failed_event);
+
+            if let Err(err) = run_callback(callback, event_context, handler_panics, describe) {
+                error!("Callback of event '{}' failed: {}", failed_event, err);
+            }
+        });
+
+        if !submitted {
+            warn!(
+                "Event '{}' dropped, its listener reached its concurrency limit",
+                event_name
+            );
+        }
 
         Produces::ok(())
     }
 
-    pub(crate) async fn handle_incoming_request(
+    /// Handles a durable event delivered by the JetStream consumer of a service, the event is
+    /// acknowledged only if its callback succeeded
+    pub(crate) async fn handle_durable_event(
         &self,
-        request_message: Result<RequestMessage, DeserializeError>,
+        service_name: String,
+        event_message: PacketEvent,
     ) -> ActorResult<()> {
-        let request_message = request_message?;
-
-        let request = self
-            .actions
-            .get(&request_message.action)
-            .ok_or_else(|| Error::ActionNotFound(request_message.action.clone()))?;
+        let event = self
+            .services
+            .iter()
+            .find(|service| service.name == service_name)
+            .and_then(|service| service.events.get(&event_message.event))
+            .ok_or_else(|| Error::EventNotFound(event_message.event.clone()))?;
 
-        let callback = request
+        let callback = event
             .callback
-            .ok_or_else(|| Error::ActionCallbackNotFound(request_message.action.clone()))?;
+            .ok_or_else(|| Error::EventCallbackNotFound(event_message.event.clone()))?;
+
+        // only remembered once handled, a failed event is delivered again on purpose
+        let seen_ids = event
+            .seen_ids
+            .as_ref()
+            .filter(|_| !event_message.id.is_empty());
+
+        if seen_ids
+            .is_some_and(|seen_ids| seen_ids.contains(&event_message.id, self.config.clock.now()))
+        {
+            self.skip_duplicate_event(&event_message);
+            return Produces::ok(());
+        }
 
-        let request_context = Context::<Action>::new(request_message, self.pid.clone().into());
+        let event_name = event_message.event.clone();
+        let event_id = event_message.id.clone();
+        let event_context = self.event_context(event_message);
 
-        callback(request_context).map_err(|err| Error::ActionCallbackFailed(err.to_string()))?;
+        run_callback(callback, event_context, self.config.handler_panics, || {
+            format!("durable event '{}'", event_name)
+        })
+        .map_err(|err| Error::EventCallbackFailed(err.to_string()))?;
+
+        if let Some(seen_ids) = seen_ids {
+            seen_ids.insert(&event_id, self.config.clock.now());
+        }
 
         Produces::ok(())
     }
 
-    async fn broadcast_info(&self) -> ActorResult<()> {
-        self.publish_info_to_channel(Channel::Info.channel_to_string(&self.config))
+    fn skip_duplicate_event(&self, event_message: &PacketEvent) {
+        debug!(
+            "Skipped event '{}' ({}) from {}, it was already handled",
+            event_message.event, event_message.id, event_message.envelope.sender
+        );
+
+        self.config
+            .metrics_recorder
+            .record_duplicate_event(&event_message.event);
+    }
+
+    pub(crate) async fn handle_incoming_request(
+        &mut self,
+        mut request_message: PacketRequest,
+    ) -> ActorResult<()> {
+        // packets after the first one of a streamed call carry its chunks
+        if let Some(seq) = request_message.seq.filter(|seq| *seq > 0) {
+            self.handle_request_stream_chunk(seq, request_message);
+            return Produces::ok(());
+        }
+
+        let node_id = request_message.envelope.sender.clone();
+        let request_id = request_message.id.clone();
+        let streamed = request_message.stream == Some(true);
+        request_message.action = self.action_name(request_message.action);
+
+        // the caller hasn't seen the INFO without our actions yet, it can retry on another node
+        if self.draining {
+            let error =
+                MoleculerError::service_not_available(&request_message.action, Some(&self.node_id));
+            send!(self.pid.reply_error(node_id, request_id, error));
+
+            return Produces::ok(());
+        }
+
+        // the caller routed here from a stale registry (or the action was just removed),
+        // answer right away so it doesn't wait for the request timeout. Protected actions
+        // aren't in the registries of other nodes, only their own requests are handled
+        let action = self
+            .actions
+            .get(&request_message.action)
+            .filter(|action| action.visibility.is_remote() || node_id == self.node_id);
+
+        let (callback, execution, queue, hooks) = match action {
+            Some(request) => {
+                request.apply_default_params(&mut request_message.params);
+
+                (
+                    request.callback.ok_or_else(|| {
+                        Error::ActionCallbackNotFound(request_message.action.clone())
+                    })?,
+                    request.execution,
+                    request.queue.clone(),
+                    request.hooks,
+                )
+            }
+
+            None => {
+                let error =
+                    MoleculerError::service_not_found(&request_message.action, Some(&self.node_id));
+                send!(self.pid.reply_error(node_id, request_id, error));
+
+                return Err(Error::ActionNotFound(request_message.action).into());
+            }
+        };
+
+        let mut timeout = request_message.timeout;
+        if self.config.cap_request_timeouts && self.config.request_timeout > 0 {
+            timeout = timeout.min(self.config.request_timeout as f32);
+        }
+
+        let mut request_context = Context::<Action>::new(request_message, self.handle());
+        request_context.clock = Arc::clone(&self.config.clock);
+        request_context.hooks = hooks;
+        // a timeout too large to be a deadline is no deadline
+        request_context.deadline = (timeout > 0.0)
+            .then(|| {
+                self.config
+                    .clock
+                    .now()
+                    .checked_add(Duration::from_millis(timeout as u64))
+            })
+            .flatten();
+        request_context.response_transformer = self.config.response_transformer;
+
+        // chunks of a stream rejected below are dropped as they don't match an open stream
+        let mut stream_writer = None;
+        if streamed {
+            let (stream, writer) = RequestStream::new(node_id.clone());
+            request_context.stream = Some(stream);
+            stream_writer = Some(writer);
+        }
+
+        for interceptor in &self.config.interceptors {
+            if let Err(error) = interceptor(&mut request_context) {
+                debug!(
+                    "Request {} for '{}' rejected by interceptor: {}",
+                    request_id,
+                    request_context.action.as_deref().unwrap_or_default(),
+                    error
+                );
+
+                send!(self.pid.reply_error(node_id, request_id, error));
+                return Produces::ok(());
+            }
+        }
+
+        // the action's own hooks run after the node's interceptors
+        if let Some(before) = hooks.before {
+            if let Err(error) = before(&mut request_context) {
+                request_context.reply_error(error);
+                return Produces::ok(());
+            }
+        }
+
+        if let Some(writer) = stream_writer {
+            self.request_streams.insert(request_id.clone(), writer);
+        }
+
+        let pid = self.pid.clone();
+        let action = request_context.action.clone().unwrap_or_default();
+
+        let in_flight = self.in_flight.start(RunningRequest {
+            action: action.clone(),
+            node_id: node_id.clone(),
+            request_id: request_id.clone(),
+        });
+        let rejected = (node_id.clone(), request_id.clone());
+
+        let slow_request_threshold = self.config.slow_request_threshold();
+        let clock = Arc::clone(&self.config.clock);
+        let metrics_recorder = Arc::clone(&self.config.metrics_recorder);
+        let chain_request_id = request_context.request_id.clone().unwrap_or_default();
+        let slow_action = action.clone();
+        let error_context = request_context.error_hook_context();
+        let deadline = request_context.deadline;
+        let node_name = self.node_id.clone();
+        let handler_panics = self.config.handler_panics;
+
+        // replies are sent through the broker with the caller's node id and request id,
+        // so they are routed the same way whichever pool ran the callback
+        let execute = move || {
+            let _in_flight = in_flight;
+
+            // the caller already gave up, ex: the request waited too long in a worker pool
+            if deadline.is_some_and(|deadline| clock.now() >= deadline) {
+                debug!(
+                    "Request {} for '{}' skipped, its timeout was reached before it ran",
+                    chain_request_id, slow_action
+                );
+
+                let error = MoleculerError::request_skipped(&slow_action, &node_name);
+                send!(pid.reply_error(node_id, request_id, error));
+
+                return Ok(());
+            }
+
+            let started_at = clock.now();
+            let result = run_callback(callback, request_context, handler_panics, || {
+                format!("action '{}' (request id {})", slow_action, chain_request_id)
+            });
+            let duration = clock.now().duration_since(started_at);
+            metrics_recorder.record_request(&slow_action, duration, result.is_ok());
+
+            if slow_request_threshold.is_some_and(|threshold| duration > threshold) {
+                warn!(
+                    "Slow request: '{}' took {}ms to handle (request id {})",
+                    slow_action,
+                    duration.as_millis(),
+                    chain_request_id
+                );
+            }
+
+            match result {
+                Ok(()) => Ok(()),
+                Err(err) => {
+                    let error = MoleculerError::from_callback_error(err.as_ref());
+                    match error_context {
+                        Some(context) => context.reply_error(error),
+                        None => send!(pid.reply_error(node_id, request_id, error)),
+                    }
+
+                    Err(Error::ActionCallbackFailed(err.to_string()))
+                }
+            }
+        };
+
+        if let Some(queue) = queue {
+            let accepted = queue.submit(execution, move || {
+                if let Err(err) = execute() {
+                    error!("{}", err);
+                }
+            });
+
+            if !accepted {
+                let (node_id, request_id) = rejected;
+                let error = MoleculerError::queue_full(&action, &self.node_id);
+                send!(self.pid.reply_error(node_id, request_id, error));
+            }
+
+            return Produces::ok(());
+        }
+
+        match execution {
+            Execution::Inline => execute()?,
+
+            Execution::Task => {
+                tokio::spawn(async move {
+                    if let Err(err) = execute() {
+                        error!("{}", err);
+                    }
+                });
+            }
+
+            Execution::Blocking => {
+                tokio::task::spawn_blocking(move || {
+                    if let Err(err) = execute() {
+                        error!("{}", err);
+                    }
+                });
+            }
+        }
+
+        Produces::ok(())
+    }
+
+    fn handle_request_stream_chunk(&mut self, seq: i32, request_message: PacketRequest) {
+        let id = request_message.id;
+
+        let writer = match self.request_streams.get_mut(&id) {
+            Some(writer) => writer,
+            None => {
+                debug!("Dropping chunk {} of unknown request stream {}", seq, id);
+                return;
+            }
+        };
+
+        let chunk = Chunk::new(
+            request_message.stream,
+            request_message.params,
+            &request_message.meta,
+        );
+
+        if !writer.push(seq, chunk) {
+            self.request_streams.remove(&id);
+        }
+    }
+
+    // the node won't send the rest of its streams
+    fn abort_request_streams(&mut self, node_name: &str) {
+        self.request_streams.retain(|_, writer| {
+            if writer.sender != node_name {
+                return true;
+            }
+
+            writer.abort(&format!("the caller node '{}' disconnected", node_name));
+            false
+        });
+    }
+
+    pub(crate) async fn broadcast_info(&self) -> ActorResult<()> {
+        // sent with each change to the services, like the INFO packet
+        if self.config.disable_balancer {
+            send!(self
+                .channel_supervisor
+                .subscribe_balanced(self.balanced_subjects()));
+        }
+
+        self.publish_info_to_channel(Channel::Info.channel_to_string(&self.config))
+            .await
+    }
+
+    // the subjects this node listens to when the balancer is disabled, the actions of a
+    // draining node are left to the other nodes
+    fn balanced_subjects(&self) -> Vec<BalancedSubject> {
+        let events = self.events.0.iter().filter_map(|(event_name, event)| {
+            let group = event.group.as_deref()?;
+
+            Some(BalancedSubject {
+                subject: config::balanced_event_subject(&self.config, group, event_name),
+                queue: config::subject_token(group),
+                channel: Channel::Event,
+            })
+        });
+
+        let actions = self
+            .actions
+            .0
+            .iter()
+            .filter(|(_, action)| !self.draining && action.visibility.is_remote())
+            .map(|(action_name, _)| BalancedSubject {
+                subject: config::balanced_request_subject(&self.config, action_name),
+                queue: config::subject_levels(action_name),
+                channel: Channel::Request,
+            });
+
+        events.chain(actions).collect()
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn drain(&mut self) -> ActorResult<Arc<InFlight>> {
+        if !self.draining {
+            self.draining = true;
+            self.broadcast_info().await?;
+        }
+
+        Produces::ok(Arc::clone(&self.in_flight))
+    }
+
+    // exposed publicly via crate::ServiceBroker, drains the broker when tracking is enabled
+    // and returns the running requests with the grace period they have to finish
+    pub(crate) async fn start_shutdown(&mut self) -> ActorResult<Option<(Arc<InFlight>, Sleep)>> {
+        let shutdown_timeout = match self.config.tracking.grace_period() {
+            Some(shutdown_timeout) => shutdown_timeout,
+            None => return Produces::ok(None),
+        };
+
+        if !self.draining {
+            self.draining = true;
+            self.broadcast_info().await?;
+        }
+
+        let grace_period = self.config.clock.sleep(shutdown_timeout);
+
+        Produces::ok(Some((Arc::clone(&self.in_flight), grace_period)))
+    }
+
+    // exposed publicly via crate::ServiceBroker, answers the requests still running once the
+    // shutdown timeout passed, their callbacks' own replies are dropped
+    pub(crate) async fn abort_in_flight(&mut self) -> ActorResult<()> {
+        for request in self.in_flight.abandon() {
+            warn!(
+                "Action '{}' still running after the shutdown timeout, aborting request {}",
+                request.action, request.request_id
+            );
+
+            let error = MoleculerError::node_shutting_down(&request.action, &self.node_id);
+            self.reply_error(request.node_id, request.request_id, error)
+                .await?;
+        }
+
+        Produces::ok(())
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn stop(&mut self) -> ActorResult<()> {
+        self.registry.discoverer().stop().await;
+        call!(self.channel_supervisor.stop()).await?;
+
+        self.transporter_connected = false;
+        self.connection.set(ConnectionState::Stopped);
+        Err(Error::Stopped.into())
+    }
+
+    /// Set by the channel supervisor as the transporter disconnects and reconnects, packets
+    /// are sent again once this node announced itself
+    pub(crate) async fn set_connection_state(&mut self, state: ConnectionState) {
+        self.transporter_connected = state == ConnectionState::Discovered;
+        self.connection.set(state);
+    }
+
+    // a handle to this broker for callbacks and contexts
+    fn event_context(&self, event_message: PacketEvent) -> Context<Event> {
+        let mut event_context = Context::<Event>::new(event_message, self.handle());
+        event_context.clock = Arc::clone(&self.config.clock);
+        event_context.expires_at = event_expiry(
+            &event_context.meta,
+            self.config.clock.now(),
+            SystemTime::now(),
+            self.config.event_ttl_skew_tolerance(),
+        );
+
+        event_context
+    }
+
+    fn handle(&self) -> crate::ServiceBroker {
+        crate::ServiceBroker {
+            addr: self.pid.clone(),
+            connection: Arc::clone(&self.connection),
+        }
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn is_ready(&self) -> ActorResult<bool> {
+        Produces::ok(self.transporter_connected && self.discovered)
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn active_subscriptions(&self) -> ActorResult<Vec<String>> {
+        let subscriptions = call!(self.channel_supervisor.active_subscriptions()).await?;
+
+        Produces::ok(subscriptions)
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn pending_requests(&self) -> ActorResult<Vec<PendingInfo>> {
+        let pending_requests = call!(self.channel_supervisor.pending_requests()).await?;
+
+        Produces::ok(pending_requests)
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn flush(&self, tx: Sender<Result<(), crate::Error>>) {
+        send!(self.channel_supervisor.flush(tx));
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn event_queue_depths(&self) -> ActorResult<HashMap<String, usize>> {
+        let depths = self
+            .events
+            .0
+            .iter()
+            .filter_map(|(name, event)| Some((name.clone(), event.queue.as_ref()?.depth())))
+            .collect();
+
+        Produces::ok(depths)
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn action_queue_depths(&self) -> ActorResult<HashMap<String, usize>> {
+        let depths = self
+            .actions
+            .0
+            .iter()
+            .filter_map(|(name, action)| Some((name.clone(), action.queue.as_ref()?.depth())))
+            .collect();
+
+        Produces::ok(depths)
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn send_buffer_depth(&self) -> ActorResult<usize> {
+        let send_buffer_depth = call!(self.channel_supervisor.send_buffer_depth()).await?;
+
+        Produces::ok(send_buffer_depth)
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn malformed_packets(&self) -> ActorResult<HashMap<Channel, u64>> {
+        Produces::ok(self.config.malformed_packets.counts())
+    }
+
+    pub(crate) async fn record_latency(&mut self, node_name: String, latency: Duration) {
+        if let Strategy::Latency(options) = &self.config.strategy {
+            self.registry
+                .record_latency(&node_name, latency, options.smoothing());
+        }
+    }
+
+    /// Pings the nodes of the liveness checks, a node that didn't answer the previous PING
+    /// missed it
+    pub(crate) async fn check_liveness(&mut self) {
+        let options = match &self.config.liveness_checks {
+            Some(options) => options,
+            None => return,
+        };
+
+        let mut pings = vec![];
+        let mut degraded = vec![];
+
+        for node_id in &options.nodes {
+            let liveness = self.liveness.entry(node_id.clone()).or_default();
+
+            if liveness.pending_ping.take().is_some() {
+                liveness.missed += 1;
+
+                if liveness.missed >= options.max_missed.max(1) && !liveness.degraded {
+                    liveness.degraded = true;
+                    degraded.push((node_id.clone(), liveness.missed));
+                }
+            }
+
+            let ping = outgoing::PacketPing::new(&self.config);
+            liveness.pending_ping = Some(ping.id.clone());
+            pings.push((node_id, ping));
+        }
+
+        for (node_id, ping) in pings {
+            let channel = Channel::PingTargeted.external_channel(&self.config, node_id);
+
+            match self.serialize_for_node(&Channel::PingTargeted, node_id, ping) {
+                Ok(message) => send!(self.channel_supervisor.publish_to_channel(channel, message)),
+                Err(err) => error!("Unable to ping {}: {}", node_id, err),
+            }
+        }
+
+        for (node_id, missed) in degraded {
+            warn!("Node {} is degraded, it missed {} PINGs", node_id, missed);
+            self.emit_local_event(
+                "$node.degraded",
+                json!({ "nodeID": node_id, "missed": missed }),
+            )
+            .await;
+        }
+    }
+
+    pub(crate) async fn handle_liveness_pong(
+        &mut self,
+        node_id: String,
+        ping_id: String,
+        round_trip: Duration,
+    ) {
+        let rtt_threshold = match &self.config.liveness_checks {
+            Some(options) => options.rtt_threshold,
+            None => return,
+        };
+
+        // PONGs to the latency PINGs, or too late to count
+        let liveness = match self.liveness.get_mut(&node_id) {
+            Some(liveness) if liveness.pending_ping.as_ref() == Some(&ping_id) => liveness,
+            _ => return,
+        };
+
+        liveness.pending_ping = None;
+        liveness.missed = 0;
+
+        if std::mem::take(&mut liveness.degraded) {
+            info!("Node {} recovered", node_id);
+            self.emit_local_event("$node.recovered", json!({ "nodeID": node_id }))
+                .await;
+        }
+
+        let rtt = round_trip.as_millis() as u64;
+        if let Some(threshold) = rtt_threshold.filter(|threshold| rtt > *threshold as u64) {
+            warn!("PING to {} took {}ms", node_id, rtt);
+            self.emit_local_event(
+                "$node.slowPing",
+                json!({ "nodeID": node_id, "rtt": rtt, "threshold": threshold }),
+            )
+            .await;
+        }
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn degraded_nodes(&self) -> ActorResult<Vec<String>> {
+        let mut nodes: Vec<String> = self
+            .liveness
+            .iter()
+            .filter(|(_, liveness)| liveness.degraded)
+            .map(|(node_id, _)| node_id.clone())
+            .collect();
+        nodes.sort();
+
+        Produces::ok(nodes)
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn node_latencies(&self) -> ActorResult<HashMap<String, Duration>> {
+        Produces::ok(self.registry.get_node_latencies())
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn describe_actions(
+        &self,
+        include_remote: bool,
+    ) -> ActorResult<Vec<ActionDescription>> {
+        let local_services = self.services.iter().map(|service| (&self.node_id, service));
+
+        let services: Vec<(&String, &Service)> = if include_remote {
+            local_services
+                .chain(self.registry.get_node_services())
+                .collect()
+        } else {
+            local_services.collect()
+        };
+
+        // sorted by name so the output can be diffed, local params win over remote ones
+        let mut descriptions: BTreeMap<&str, ActionDescription> = BTreeMap::new();
+
+        for (node_id, service) in services {
+            let local = *node_id == self.node_id;
+
+            for (action_name, action) in &service.actions {
+                if !local && !action.visibility.is_remote() {
+                    continue;
+                }
+
+                descriptions
+                    .entry(action_name)
+                    .or_insert_with(|| ActionDescription::new(service, action))
+                    .nodes
+                    .push(node_id.clone());
+            }
+        }
+
+        let mut descriptions: Vec<ActionDescription> = descriptions.into_values().collect();
+        for description in &mut descriptions {
+            description.nodes.sort();
+            description.nodes.dedup();
+        }
+
+        Produces::ok(descriptions)
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn action_endpoints(&self, action: String) -> ActorResult<Vec<Endpoint>> {
+        let mut endpoints: Vec<Endpoint> = self
+            .all_services()
+            .filter_map(|(node_id, local, service)| {
+                let visibility = service.actions.get(&action)?.visibility;
+                (local || visibility.is_remote()).then_some((node_id, local, service, visibility))
+            })
+            .map(|(node_id, local, service, visibility)| Endpoint {
+                node_id: node_id.clone(),
+                action: action.clone(),
+                service: service.name.clone(),
+                version: service.version,
+                visibility,
+                local,
+                available: self.is_available(node_id, local),
+                node_metadata: self.node_metadata(node_id, local),
+            })
+            .collect();
+
+        endpoints.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        endpoints.dedup_by(|a, b| a.node_id == b.node_id);
+
+        Produces::ok(endpoints)
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn service_list(&self) -> ActorResult<Vec<ServiceInfo>> {
+        let mut services: Vec<ServiceInfo> = self
+            .all_services()
+            .map(|(node_id, local, service)| {
+                ServiceInfo::new(node_id, local, self.is_available(node_id, local), service)
+            })
+            .collect();
+
+        services.sort_by(|a, b| (&a.name, &a.node_id).cmp(&(&b.name, &b.node_id)));
+
+        Produces::ok(services)
+    }
+
+    // services of this node then of the remote nodes, with their node and whether it's local
+    fn all_services(&self) -> impl Iterator<Item = (&String, bool, &Service)> {
+        let local_services = self
+            .services
+            .iter()
+            .map(move |service| (&self.node_id, true, service));
+        let remote_services = self
+            .registry
+            .get_node_services()
+            .map(|(node_id, service)| (node_id, false, service));
+
+        local_services.chain(remote_services)
+    }
+
+    // nodes that missed their heartbeats are already removed from the registry
+    fn is_available(&self, node_id: &str, local: bool) -> bool {
+        if local {
+            return !self.draining;
+        }
+
+        !self
+            .liveness
+            .get(node_id)
+            .is_some_and(|liveness| liveness.degraded)
+    }
+
+    fn node_metadata(&self, node_id: &str, local: bool) -> Value {
+        if local {
+            return json!(self.config.meta_data);
+        }
+
+        self.registry
+            .get_node(node_id)
+            .map(|node| node.metadata.clone())
+            .unwrap_or(Value::Null)
+    }
+
+    /// The nodes known to this one, including itself, in the shape of `$node.list` in
+    /// Moleculer JS
+    pub(crate) async fn node_list(&self, with_services: bool) -> ActorResult<Value> {
+        let mut local = json!({
+            "id": self.node_id,
+            "instanceID": self.instance_id,
+            "available": true,
+            "local": true,
+            "client": outgoing::Client::new(),
+            "ipList": self.config.ip_list,
+            "hostname": self.config.hostname,
+            "metadata": self.config.meta_data,
+            "seq": self.config.seq.load(Ordering::SeqCst),
+        });
+
+        if with_services {
+            local["services"] = json!(self.services);
+        }
+
+        let nodes: Vec<Value> = std::iter::once(local)
+            .chain(self.registry.get_nodes().map(|node| {
+                let mut node_json = node.to_json(with_services);
+                if self
+                    .liveness
+                    .get(&node.name)
+                    .is_some_and(|liveness| liveness.degraded)
+                {
+                    node_json["available"] = json!(false);
+                }
+
+                node_json
+            }))
+            .collect();
+
+        Produces::ok(Value::Array(nodes))
+    }
+
+    /// The actions of the cluster for `$node.actions`, sorted by name, in the shape of
+    /// `registry.getActionList()` in Moleculer JS
+    pub(crate) async fn node_actions(&self, options: ActionListOptions) -> ActorResult<Value> {
+        struct Endpoint<'a> {
+            node_id: &'a str,
+            local: bool,
+            available: bool,
+        }
+
+        let local_services = self
+            .services
+            .iter()
+            .map(|service| (&self.node_id, service, true));
+        let remote_services = self
+            .registry
+            .get_node_services()
+            .map(|(node_id, service)| (node_id, service, false));
+
+        // local endpoints come first, their definition is the one returned
+        let mut actions: BTreeMap<&str, (&Action, Vec<Endpoint>)> = BTreeMap::new();
+
+        for (node_id, service, local) in local_services.chain(remote_services) {
+            let available = !self
+                .liveness
+                .get(node_id)
+                .is_some_and(|liveness| liveness.degraded);
+
+            for (action_name, action) in &service.actions {
+                if !local && !action.visibility.is_remote() {
+                    continue;
+                }
+
+                actions
+                    .entry(action_name)
+                    .or_insert_with(|| (action, vec![]))
+                    .1
+                    .push(Endpoint {
+                        node_id,
+                        local,
+                        available,
+                    });
+            }
+        }
+
+        let list = actions
+            .into_iter()
+            .filter(|(name, _)| !(options.skip_internal && name.starts_with('$')))
+            .filter(|(_, (action, _))| !options.only_published || action.visibility.is_published())
+            .filter_map(|(name, (action, endpoints))| {
+                let has_local = endpoints.iter().any(|endpoint| endpoint.local);
+                let available = endpoints.iter().any(|endpoint| endpoint.available);
+
+                if (options.only_local && !has_local) || (options.only_available && !available) {
+                    return None;
+                }
+
+                let mut item = json!({
+                    "name": name,
+                    "count": endpoints.len(),
+                    "hasLocal": has_local,
+                    "available": available,
+                    "action": action,
+                });
+
+                if options.with_endpoints {
+                    item["endpoints"] = endpoints
+                        .iter()
+                        .map(|endpoint| {
+                            json!({
+                                "nodeID": endpoint.node_id,
+                                "state": true,
+                                "available": endpoint.available,
+                            })
+                        })
+                        .collect();
+                }
+
+                Some(item)
+            })
+            .collect();
+
+        Produces::ok(Value::Array(list))
+    }
+
+    /// The config of this node for `$node.options`, secrets are redacted when it is serialized
+    pub(crate) async fn node_options(&self) -> ActorResult<Value> {
+        Produces::ok(serde_json::to_value(&*self.config)?)
+    }
+
+    // exposed publicly via crate::ServiceBroker
+    pub(crate) async fn subscribe_registry_events(
+        &mut self,
+        subscriber: UnboundedSender<RegistryChange>,
+    ) {
+        self.registry_subscribers.push(subscriber);
+    }
+
+    pub(crate) async fn handle_reconnect(&self, downtime: Duration) {
+        if let Some(on_reconnect) = self.config.on_reconnect {
+            on_reconnect(self.handle(), downtime);
+        }
+
+        self.emit_local_event(
+            "$transporter.reconnected",
+            json!({ "downtime": downtime.as_millis() as u64 }),
+        )
+        .await;
+    }
+
+    pub(crate) async fn handle_connect_failed(&self, attempts: usize) {
+        self.connection.set(ConnectionState::Failed);
+
+        self.emit_local_event(
+            "$transporter.connect.failed",
+            json!({ "attempts": attempts }),
+        )
+        .await;
+
+        if self.config.reconnect_policy.give_up == ReconnectGiveUp::Exit {
+            error!("Exiting, the transporter is unreachable");
+            std::process::exit(1);
+        }
+    }
+
+    // packets sent to a single node use a serializer it advertised support for
+    fn serialize_for_node<T: Serialize>(
+        &self,
+        channel: &Channel,
+        node_name: &str,
+        message: T,
+    ) -> Result<Vec<u8>, config::SerializeError> {
+        let node_serializers = self.registry.get_node_serializers(node_name);
+        config::log_packet(
+            &self.config,
+            PacketDirection::Outbound,
+            channel.packet_type(),
+            &message,
+        );
+
+        self.config.serialize_with(
+            self.config.serializer_for_node(channel, node_serializers),
+            channel.packet_type(),
+            message,
+        )
+    }
+
+    fn send_discover_to_node(&self, node_name: &str) -> Result<(), config::SerializeError> {
+        let channel = Channel::DiscoverTargeted.external_channel(&self.config, node_name);
+        let message = outgoing::PacketDiscover::new(&self.config);
+
+        send!(self.channel_supervisor.publish_to_channel(
+            channel,
+            self.serialize_for_node(&Channel::DiscoverTargeted, node_name, message)?
+        ));
+
+        Ok(())
+    }
+
+    // changes are sent to registry_events() subscribers and as internal events
+    async fn publish_registry_changes(&mut self, changes: Vec<RegistryChange>) {
+        let mut services_changed = false;
+
+        for change in &changes {
+            match change {
+                RegistryChange::NodeConnected { node_id } => {
+                    self.emit_local_event("$node.connected", json!({ "nodeID": node_id }))
+                        .await
+                }
+
+                RegistryChange::NodeUpdated { node_id, diff } => {
+                    self.emit_local_event(
+                        "$node.updated",
+                        json!({ "nodeID": node_id, "diff": diff }),
+                    )
+                    .await
+                }
+
+                RegistryChange::NodeDisconnected {
+                    node_id,
+                    unexpected,
+                } => {
+                    self.abort_request_streams(node_id);
+                    self.emit_local_event(
+                        "$node.disconnected",
+                        json!({ "nodeID": node_id, "unexpected": unexpected }),
+                    )
+                    .await
+                }
+
+                RegistryChange::ServiceAdded { .. } | RegistryChange::ServiceRemoved { .. } => {
+                    services_changed = true
+                }
+            }
+        }
+
+        if services_changed {
+            self.emit_local_event("$services.changed", json!({ "localService": false }))
+                .await;
+        }
+
+        // drop the subscribers that stopped listening
+        self.registry_subscribers.retain(|subscriber| {
+            changes
+                .iter()
+                .all(|change| subscriber.send(change.clone()).is_ok())
+        });
+    }
+
+    // internal events are only delivered to listeners on this node
+    async fn emit_local_event(&self, event_name: &str, params: Value) {
+        if self.events.get(event_name).is_none() {
+            return;
+        }
+
+        let event_message = PacketEvent::new_local(&self.config, event_name, params, false);
+
+        if let Err(err) = self.handle_incoming_event(event_message).await {
+            error!("Unable to handle local event '{}': {}", event_name, err);
+        }
+    }
+}
+
+// tries the nodes in order until one answers or fails with an error that isn't retryable, the
+// attempts share the deadline of the whole call
+async fn call_fallback_nodes(
+    broker: Addr<ServiceBroker>,
+    clock: Arc<dyn Clock>,
+    deadline: Instant,
+    action: String,
+    params: Value,
+    mut options: CallOptions,
+    tx: Sender<Result<Value, crate::Error>>,
+) {
+    let nodes = std::mem::take(&mut options.fallback_nodes);
+    let mut result = Err(MoleculerError::service_not_found(&action, None).into());
+
+    for node_id in nodes {
+        let remaining = deadline.saturating_duration_since(clock.now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        let attempt = CallOptions {
+            node_id: Some(node_id),
+            timeout: Some(remaining.as_millis() as i32),
+            ..options.clone()
+        };
+
+        let (attempt_tx, attempt_rx) = oneshot::channel();
+        send!(broker.call_node(action.clone(), params.clone(), attempt, attempt_tx));
+
+        result = match attempt_rx.await {
+            Ok(result) => result,
+            Err(err) => Err(err.into()),
+        };
+
+        match &result {
+            Err(crate::Error::ActionError(error)) if error.retryable => {
+                debug!(
+                    "Call to '{}' failed, trying the next node: {}",
+                    action, error
+                );
+            }
+            _ => break,
+        }
+    }
+
+    let _ = tx.send(result);
+}
+
+// balances the call again after each retryable error, until it succeeds or runs out of retries
+async fn call_with_retries(
+    broker: Addr<ServiceBroker>,
+    config: Arc<config::Config>,
+    retries: u32,
+    action: String,
+    params: Value,
+    options: CallOptions,
+    tx: Sender<Result<Value, crate::Error>>,
+) {
+    let mut retry = 0;
+
+    let result = loop {
+        let (attempt_tx, attempt_rx) = oneshot::channel();
+        send!(broker.call_node(action.clone(), params.clone(), options.clone(), attempt_tx));
+
+        let result = match attempt_rx.await {
+            Ok(result) => result,
+            Err(err) => Err(err.into()),
+        };
+
+        match retried_error(&result, retry, retries) {
+            Some(error) => {
+                retry += 1;
+                config.metrics_recorder.record_retry(&action);
+
+                let delay = config
+                    .retry_policy
+                    .next_delay(retry, &mut rand::thread_rng());
+
+                debug!(
+                    "Call to '{}' failed, retry {}/{} in {:?}: {}",
+                    action, retry, retries, delay, error
+                );
+                config.clock.sleep(delay).await;
+            }
+            None => break result,
+        }
+    };
+
+    let _ = tx.send(result);
+}
+
+// the error of an attempt that is tried again, errors that aren't retryable never are
+fn retried_error(
+    result: &Result<Value, crate::Error>,
+    retry: u32,
+    retries: u32,
+) -> Option<&MoleculerError> {
+    match result {
+        Err(crate::Error::ActionError(error)) if error.retryable && retry < retries => Some(error),
+        _ => None,
+    }
+}
+
+// a panicking callback is reported the same way as one returning an error,
+// instead of taking down the broker, unless `handler_panics` aborts.
+// `describe` names the callback in the log
+fn run_callback<T>(
+    callback: fn(T) -> Result<(), Box<dyn std::error::Error>>,
+    arg: T,
+    handler_panics: HandlerPanics,
+    describe: impl FnOnce() -> String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    panic::catch_unwind(AssertUnwindSafe(|| callback(arg))).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+
+        error!("Callback of {} panicked: {}", describe(), message);
+
+        if handler_panics == HandlerPanics::Abort {
+            error!("Aborting the process, see `handler_panics` in the config");
+            std::process::abort();
+        }
+
+        Err(format!("callback panicked: {}", message).into())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{Clock, ManualClock},
+        config::{ConfigBuilder, RetryPolicy},
+        service::{ActionBuilder, EventBuilder, Execution, Visibility, WorkerPool},
+        EventOptions,
+    };
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Mutex,
+    };
+
+    fn broker() -> ServiceBroker {
+        let config = ConfigBuilder::default().node_id("node-1").build();
+        ServiceBroker::new(config, Arc::new(ConnectionStatus::new()))
+    }
+
+    fn noop(_ctx: Context<Action>) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    fn action(name: &str) -> Action {
+        ActionBuilder::new(name).add_callback(noop).build()
+    }
+
+    // a remote node with the `users.get` and `users.list` actions
+    fn add_remote_node(broker: &mut ServiceBroker, node_id: &str) {
+        let info = serde_json::from_value(json!({
+            "ver": "4",
+            "sender": node_id,
+            "instanceID": node_id,
+            "services": [{
+                "name": "users",
+                "actions": {
+                    "users.get": { "name": "users.get" },
+                    "users.list": { "name": "users.list" },
+                },
+                "events": {},
+            }],
+        }))
+        .unwrap();
+
+        broker.registry.add_or_update_node(Addr::detached(), info);
+    }
+
+    fn value<T>(result: ActorResult<T>) -> T {
+        match result.unwrap() {
+            Produces::Value(value) => value,
+            _ => panic!("expected a value"),
+        }
+    }
+
+    async fn action_names(broker: &ServiceBroker, options: Value) -> Vec<String> {
+        let options = serde_json::from_value(options).unwrap();
+
+        value(broker.node_actions(options).await)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    // `users.get` on this node and on node-2, `users.list` on node-2 only
+    async fn cluster() -> ServiceBroker {
+        let mut broker = broker();
+        let users = Service::new("users").add_action(action("users.get"));
+        broker.add_service(users).await.unwrap();
+        add_remote_node(&mut broker, "node-2");
+        broker
+    }
+
+    #[tokio::test]
+    async fn node_actions_lists_the_actions_of_the_cluster() {
+        let broker = cluster().await;
+
+        let names = action_names(&broker, json!({})).await;
+        assert!(names.contains(&"users.get".to_string()));
+        assert!(names.contains(&"users.list".to_string()));
+        assert!(names.contains(&"$node.actions".to_string()));
+
+        let actions = value(broker.node_actions(ActionListOptions::default()).await);
+        let get = actions
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|item| item["name"] == "users.get")
+            .unwrap();
+        assert_eq!(get["count"], 2);
+        assert_eq!(get["hasLocal"], true);
+        assert!(get.get("endpoints").is_none());
+    }
+
+    #[tokio::test]
+    async fn node_actions_only_local() {
+        let broker = cluster().await;
+
+        let names = action_names(&broker, json!({ "onlyLocal": true, "skipInternal": true })).await;
+        assert_eq!(names, vec!["users.get"]);
+    }
+
+    #[tokio::test]
+    async fn node_actions_skip_internal() {
+        let broker = cluster().await;
+
+        let names = action_names(&broker, json!({ "skipInternal": true })).await;
+        assert_eq!(names, vec!["users.get", "users.list"]);
+    }
+
+    #[tokio::test]
+    async fn node_actions_only_available() {
+        let mut broker = cluster().await;
+        broker.liveness.insert(
+            "node-2".to_string(),
+            NodeLiveness {
+                degraded: true,
+                ..NodeLiveness::default()
+            },
+        );
+
+        let names = action_names(
+            &broker,
+            json!({ "onlyAvailable": true, "skipInternal": true }),
+        )
+        .await;
+        assert_eq!(names, vec!["users.get"]);
+
+        let names = action_names(&broker, json!({ "skipInternal": true })).await;
+        assert_eq!(names, vec!["users.get", "users.list"]);
+    }
+
+    #[tokio::test]
+    async fn node_actions_with_endpoints() {
+        let broker = cluster().await;
+        let options = serde_json::from_value(json!({ "withEndpoints": true })).unwrap();
+
+        let actions = value(broker.node_actions(options).await);
+        let get = actions
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|item| item["name"] == "users.get")
+            .unwrap();
+
+        assert_eq!(
+            get["endpoints"],
+            json!([
+                { "nodeID": "node-1", "state": true, "available": true },
+                { "nodeID": "node-2", "state": true, "available": true },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn node_actions_lists_the_protected_actions_of_this_node() {
+        let mut broker = broker();
+        let protected = ActionBuilder::new("users.purge")
+            .add_callback(noop)
+            .visibility(Visibility::Protected)
+            .build();
+        broker
+            .add_service(Service::new("users").add_action(protected))
+            .await
+            .unwrap();
+
+        let names = action_names(&broker, json!({ "skipInternal": true })).await;
+        assert_eq!(names, vec!["users.purge"]);
+    }
+
+    #[tokio::test]
+    async fn call_retries_resolve_from_the_call_then_the_action_then_the_config() {
+        let config = ConfigBuilder::default()
+            .node_id("node-1")
+            .retry_policy(RetryPolicy::default().enabled(true).retries(2))
+            .build();
+        let mut broker = ServiceBroker::new(config, Arc::new(ConnectionStatus::new()));
+
+        let reads = ActionBuilder::new("users.get")
+            .add_callback(noop)
+            .retries(5)
+            .build();
+        let users = Service::new("users")
+            .add_action(reads)
+            .add_action(action("users.list"));
+        broker.add_service(users).await.unwrap();
+
+        let info = serde_json::from_value(json!({
+            "ver": "4",
+            "sender": "node-2",
+            "services": [{
+                "name": "billing",
+                "actions": {
+                    "billing.charge": {
+                        "name": "billing.charge",
+                        "retryPolicy": { "enabled": true, "retries": 0 },
+                    },
+                },
+                "events": {},
+            }],
+        }))
+        .unwrap();
+        broker.registry.add_or_update_node(Addr::detached(), info);
+
+        let default = CallOptions::default();
+        let overridden = CallOptions::default().retries(1);
+
+        // the global policy
+        assert_eq!(broker.call_retries("users.list", &default), 2);
+        // the schema of a local action, then of a remote one
+        assert_eq!(broker.call_retries("users.get", &default), 5);
+        assert_eq!(broker.call_retries("billing.charge", &default), 0);
+        // the call
+        assert_eq!(broker.call_retries("users.list", &overridden), 1);
+        assert_eq!(broker.call_retries("users.get", &overridden), 1);
+        assert_eq!(broker.call_retries("billing.charge", &overridden), 1);
+    }
+
+    #[test]
+    fn call_retries_default_to_none_when_the_policy_is_disabled() {
+        let broker = broker();
+
+        assert_eq!(
+            broker.call_retries("users.list", &CallOptions::default()),
+            0
+        );
+    }
+
+    #[test]
+    fn only_retryable_errors_are_retried() {
+        let retryable: Result<Value, crate::Error> =
+            Err(MoleculerError::request_timeout("users.get", "node-2").into());
+        let not_retryable: Result<Value, crate::Error> =
+            Err(
+                MoleculerError::new("ValidationError", "invalid params", 422, "VALIDATION_ERROR")
+                    .retryable(false)
+                    .into(),
+            );
+
+        assert!(retried_error(&retryable, 0, 5).is_some());
+        assert!(retried_error(&retryable, 4, 5).is_some());
+        // out of retries
+        assert!(retried_error(&retryable, 5, 5).is_none());
+
+        assert!(retried_error(&not_retryable, 0, 5).is_none());
+        assert!(retried_error(&Ok(json!({})), 0, 5).is_none());
+    }
+
+    static SLOW_RUNS: AtomicUsize = AtomicUsize::new(0);
+    static SLOW_RELEASED: AtomicBool = AtomicBool::new(false);
+
+    // keeps its worker busy until released
+    fn slow(_ctx: Context<Action>) -> Result<(), Box<dyn std::error::Error>> {
+        SLOW_RUNS.fetch_add(1, Ordering::SeqCst);
+
+        while !SLOW_RELEASED.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        Ok(())
+    }
+
+    fn request(id: &str, action: &str, timeout: f32) -> PacketRequest {
+        serde_json::from_value(json!({
+            "ver": "4",
+            "sender": "node-2",
+            "id": id,
+            "action": action,
+            "params": {},
+            "meta": {},
+            "timeout": timeout,
+            "level": 1,
+            "requestID": id,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_still_waiting_at_the_callers_deadline_are_skipped() {
+        let clock = Arc::new(ManualClock::new());
+        let config = ConfigBuilder::default()
+            .node_id("node-1")
+            .clock(Arc::clone(&clock) as Arc<dyn Clock>)
+            .build();
+        let mut broker = ServiceBroker::new(config, Arc::new(ConnectionStatus::new()));
+
+        let jobs = ActionBuilder::new("jobs.run")
+            .add_callback(slow)
+            .worker_pool(WorkerPool::default().concurrency(1))
+            .build();
+        broker
+            .add_service(Service::new("jobs").add_action(jobs))
+            .await
+            .unwrap();
+
+        // the first request takes the only worker, the second one waits for it
+        value(
+            broker
+                .handle_incoming_request(request("req-1", "jobs.run", 0.0))
+                .await,
+        );
+        value(
+            broker
+                .handle_incoming_request(request("req-2", "jobs.run", 2000.0))
+                .await,
+        );
+
+        clock.advance(Duration::from_millis(2000));
+        SLOW_RELEASED.store(true, Ordering::SeqCst);
+
+        tokio::time::timeout(Duration::from_secs(5), broker.in_flight.idle())
+            .await
+            .expect("both requests finish");
+
+        assert_eq!(SLOW_RUNS.load(Ordering::SeqCst), 1);
+    }
+
+    static RECORDED_DEADLINE: Mutex<Option<Instant>> = Mutex::new(None);
+
+    fn record_deadline(ctx: Context<Action>) -> Result<(), Box<dyn std::error::Error>> {
+        *RECORDED_DEADLINE.lock().unwrap() = ctx.deadline;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn received_timeouts_are_capped_at_the_request_timeout_when_enabled() {
+        // requests without a timeout still have no deadline
+        for (cap_request_timeouts, timeout, expected) in [
+            (true, 60_000.0, Some(1000)),
+            (false, 60_000.0, Some(60_000)),
+            (true, 0.0, None),
+        ] {
+            let clock = Arc::new(ManualClock::new());
+            let config = ConfigBuilder::default()
+                .node_id("node-1")
+                .clock(Arc::clone(&clock) as Arc<dyn Clock>)
+                .request_timeout(1000)
+                .cap_request_timeouts(cap_request_timeouts)
+                .build();
+            let mut broker = ServiceBroker::new(config, Arc::new(ConnectionStatus::new()));
+
+            let reports = ActionBuilder::new("reports.build")
+                .add_callback(record_deadline)
+                .execution(Execution::Inline)
+                .build();
+            broker
+                .add_service(Service::new("reports").add_action(reports))
+                .await
+                .unwrap();
+
+            *RECORDED_DEADLINE.lock().unwrap() = Some(clock.now());
+            value(
+                broker
+                    .handle_incoming_request(request("req-1", "reports.build", timeout))
+                    .await,
+            );
+
+            let deadline = RECORDED_DEADLINE.lock().unwrap().take();
+            assert_eq!(
+                deadline,
+                expected.map(|expected| clock.now() + Duration::from_millis(expected)),
+                "cap_request_timeouts: {}, timeout: {}",
+                cap_request_timeouts,
+                timeout
+            );
+        }
+    }
+
+    static HEARD_EVENT: Mutex<Option<Context<Event>>> = Mutex::new(None);
+
+    fn hear_event(ctx: Context<Event>) -> Result<(), Box<dyn std::error::Error>> {
+        *HEARD_EVENT.lock().unwrap() = Some(ctx);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn events_emitted_from_a_context_inherit_its_meta() {
+        let config = ConfigBuilder::default()
+            .node_id("node-1")
+            .default_meta(HashMap::from([
+                ("region".to_string(), json!("eu-west-1")),
+                ("tenant".to_string(), json!("default")),
+            ]))
+            .build();
+        let mut broker = ServiceBroker::new(config, Arc::new(ConnectionStatus::new()));
+        let audit = Service::new("audit").add_event(
+            EventBuilder::new("order.created")
+                .add_callback(hear_event)
+                .build(),
+        );
+        broker.add_service(audit).await.unwrap();
+
+        let mut parent_request = request("req-1", "orders.create", 0.0);
+        parent_request.meta = json!({ "tenant": "acme" });
+        let parent = Context::<Action>::new(parent_request, broker.handle());
+
+        // `Context::emit()` sends these headers to the broker
+        let options = parent.event_options(EventOptions::default());
+        value(
+            broker
+                .emit(
+                    "order.created".to_string(),
+                    json!({}),
+                    options.event_headers(),
+                    None,
+                )
+                .await,
+        );
+
+        let heard = HEARD_EVENT
+            .lock()
+            .unwrap()
+            .take()
+            .expect("the listener ran");
+        assert_eq!(
+            heard.meta,
+            json!({ "tenant": "acme", "region": "eu-west-1" })
+        );
+        assert_eq!(heard.level, 2);
+        assert_eq!(heard.parent_id.as_deref(), Some("req-1"));
+        assert_eq!(heard.request_id.as_deref(), Some("req-1"));
+        assert_eq!(heard.caller.as_deref(), Some("orders.create"));
+    }
+
+    static HEARD_PRICE: Mutex<Option<Context<Event>>> = Mutex::new(None);
+
+    fn hear_price(ctx: Context<Event>) -> Result<(), Box<dyn std::error::Error>> {
+        *HEARD_PRICE.lock().unwrap() = Some(ctx);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn events_expire_on_the_clock_of_the_broker() {
+        let clock = Arc::new(ManualClock::new());
+        let config = ConfigBuilder::default()
+            .node_id("node-1")
+            .clock(Arc::clone(&clock) as Arc<dyn Clock>)
+            .build();
+        let mut broker = ServiceBroker::new(config, Arc::new(ConnectionStatus::new()));
+        let prices = Service::new("prices").add_event(
+            EventBuilder::new("price.updated")
+                .add_callback(hear_price)
+                .build(),
+        );
+        broker.add_service(prices).await.unwrap();
+
+        let emitted_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let event_message = PacketEvent {
+            meta: json!({ "$ttl": 5000, "$emittedAt": emitted_at.as_millis() as u64 }),
+            ..PacketEvent::new_local(&broker.config, "price.updated", json!({}), false)
+        };
+        value(broker.handle_incoming_event(event_message).await);
+
+        let heard = HEARD_PRICE
+            .lock()
+            .unwrap()
+            .take()
+            .expect("the listener ran");
+        assert!(!heard.is_expired());
+
+        // only the clock of the broker moves
+        clock.advance(Duration::from_secs(6));
+        assert!(heard.is_expired());
+    }
+
+    static RECORDED_CALL_DEADLINE: Mutex<Option<Instant>> = Mutex::new(None);
+
+    fn record_call_deadline(ctx: Context<Action>) -> Result<(), Box<dyn std::error::Error>> {
+        *RECORDED_CALL_DEADLINE.lock().unwrap() = ctx.deadline;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn the_timeout_of_the_call_options_is_the_deadline_of_the_handler() {
+        let clock = Arc::new(ManualClock::new());
+        let config = ConfigBuilder::default()
+            .node_id("node-1")
+            .clock(Arc::clone(&clock) as Arc<dyn Clock>)
+            .request_timeout(60_000)
+            .build();
+        let mut broker = ServiceBroker::new(config, Arc::new(ConnectionStatus::new()));
+
+        let quotes = ActionBuilder::new("pricing.quote")
+            .add_callback(record_call_deadline)
+            .execution(Execution::Inline)
+            .build();
+        broker
+            .add_service(Service::new("pricing").add_action(quotes))
+            .await
+            .unwrap();
+
+        // the request as sent by the caller
+        let options = CallOptions::default().timeout(500);
+        let sent =
+            outgoing::PacketRequest::new(&broker.config, "pricing.quote", json!({}), &options);
+        let received = serde_json::from_value(serde_json::to_value(&sent).unwrap()).unwrap();
+
+        value(broker.handle_incoming_request(received).await);
+
+        let deadline = RECORDED_CALL_DEADLINE.lock().unwrap().take();
+        assert_eq!(deadline, Some(clock.now() + Duration::from_millis(500)));
+    }
+
+    fn dummy_context() -> Context<Action> {
+        Context::<Action>::new(request("req-1", "buggy.crash", 0.0), broker().handle())
+    }
+
+    fn panicking(_ctx: Context<Action>) -> Result<(), Box<dyn std::error::Error>> {
+        panic!("boom")
+    }
+
+    #[tokio::test]
+    async fn panicking_handlers_reply_an_error_and_the_broker_keeps_serving() {
+        let mut broker = broker();
+        let buggy = Service::new("buggy")
+            .add_action(
+                ActionBuilder::new("buggy.crash")
+                    .add_callback(panicking)
+                    .execution(Execution::Inline)
+                    .build(),
+            )
+            .add_action(
+                ActionBuilder::new("buggy.ping")
+                    .add_callback(noop)
+                    .execution(Execution::Inline)
+                    .build(),
+            );
+        broker.add_service(buggy).await.unwrap();
+
+        let result = broker
+            .handle_incoming_request(request("req-1", "buggy.crash", 0.0))
+            .await;
+        assert!(matches!(
+            result.map(|_| ()).unwrap_err().downcast::<Error>().map(|err| *err),
+            Ok(Error::ActionCallbackFailed(message)) if message == "callback panicked: boom"
+        ));
+
+        value(
+            broker
+                .handle_incoming_request(request("req-2", "buggy.ping", 0.0))
+                .await,
+        );
+    }
+
+    #[test]
+    fn panics_are_returned_as_500_errors_with_handler_panics_reply() {
+        let err = run_callback(panicking, dummy_context(), HandlerPanics::Reply, || {
+            "action 'buggy.crash'".to_string()
+        })
+        .unwrap_err();
+
+        let error = MoleculerError::from_callback_error(err.as_ref());
+        assert_eq!(error.code, 500);
+        assert_eq!(error.message, "callback panicked: boom");
+    }
+
+    #[test]
+    fn panics_abort_the_process_with_handler_panics_abort() {
+        const CHILD: &str = "MOLECULER_HANDLER_PANICS_ABORT_CHILD";
+
+        if std::env::var_os(CHILD).is_some() {
+            let _ = run_callback(panicking, dummy_context(), HandlerPanics::Abort, || {
+                "action 'buggy.crash'".to_string()
+            });
+            unreachable!("the process is aborted");
+        }
+
+        // the test aborts, it runs in a child process
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "broker::tests::panics_abort_the_process_with_handler_panics_abort",
+                "--exact",
+                "--test-threads=1",
+            ])
+            .env(CHILD, "1")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+
+        assert!(!status.success());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            assert_eq!(status.signal(), Some(6), "killed by SIGABRT");
+        }
+    }
+
+    fn with_visibility(name: &str, visibility: Visibility) -> Action {
+        ActionBuilder::new(name)
+            .add_callback(noop)
+            .execution(Execution::Inline)
+            .visibility(visibility)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn node_actions_only_published_leaves_out_other_visibilities() {
+        let mut broker = broker();
+        let admin = Service::new("admin")
+            .add_action(with_visibility("admin.stats", Visibility::Published))
+            .add_action(with_visibility("admin.audit", Visibility::Public))
+            .add_action(with_visibility("admin.purge", Visibility::Protected))
+            .add_action(with_visibility("admin.reset", Visibility::Private));
+        broker.add_service(admin).await.unwrap();
+
+        let info = serde_json::from_value(json!({
+            "ver": "4",
+            "sender": "node-2",
+            "services": [{
+                "name": "billing",
+                "actions": {
+                    "billing.charge": { "name": "billing.charge" },
+                    "billing.refund": { "name": "billing.refund", "visibility": "public" },
+                    "billing.wipe": { "name": "billing.wipe", "visibility": "protected" },
+                },
+                "events": {},
+            }],
+        }))
+        .unwrap();
+        broker.registry.add_or_update_node(Addr::detached(), info);
+
+        let gateway = action_names(
+            &broker,
+            json!({ "skipInternal": true, "onlyPublished": true }),
+        )
+        .await;
+        assert_eq!(gateway, vec!["admin.stats", "billing.charge"]);
+
+        // the protected actions of other nodes are never listed
+        let internal = action_names(&broker, json!({ "skipInternal": true })).await;
+        assert_eq!(
+            internal,
+            vec![
+                "admin.audit",
+                "admin.purge",
+                "admin.reset",
+                "admin.stats",
+                "billing.charge",
+                "billing.refund",
+            ]
+        );
+
+        // a protected action is still callable from its own node
+        let mut local_request = request("req-1", "admin.purge", 0.0);
+        local_request.envelope.sender = "node-1".to_string();
+        value(broker.handle_incoming_request(local_request).await);
+
+        let remote_request = request("req-2", "admin.purge", 0.0);
+        assert!(broker
+            .handle_incoming_request(remote_request)
             .await
+            .is_err());
     }
 }
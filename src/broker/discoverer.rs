@@ -0,0 +1,242 @@
+//! Detects the remote nodes that left without a `DISCONNECT` packet.
+
+#[cfg(feature = "etcd")]
+mod etcd;
+#[cfg(any(feature = "redis", feature = "etcd"))]
+mod keyspace;
+#[cfg(feature = "redis")]
+mod redis;
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use act_zero::runtimes::tokio::spawn_actor;
+use act_zero::timer::Tick;
+use act_zero::*;
+use async_trait::async_trait;
+
+use crate::{
+    clock::{Clock, Timer},
+    config::{self, Config},
+};
+
+use super::{registry::NodeName, ServiceBroker};
+
+/// Called with the name of a watched node once it is considered gone
+pub(crate) type Expired = Box<dyn Fn(NodeName) + Send + Sync>;
+
+/// Tracks the liveness of the remote nodes of the registry, see [config::Registry].
+///
+/// The registry has each node that connects watched, and passes on its heartbeats.
+/// [LocalDiscoverer] relies on the `HEARTBEAT` packets, the discoverers of the registries keeping
+/// the heartbeats in a store on the keys the nodes write there.
+#[async_trait]
+pub(crate) trait Discoverer: Send + Sync {
+    /// Starts watching a node that just connected
+    fn watch(&self, node_name: &NodeName, expired: Expired) -> Arc<dyn WatchedNode>;
+
+    /// The broker started, the nodes found by the discoverer are sent to it
+    fn start(&self, _broker: WeakAddr<ServiceBroker>) {}
+
+    /// This node sent its `INFO` packet
+    fn local_info(&self, _info: &[u8]) {}
+
+    /// The broker is stopping, the other nodes can forget this one
+    async fn stop(&self) {}
+}
+
+/// A node watched by a [Discoverer], it is no longer watched once dropped
+pub(crate) trait WatchedNode: fmt::Debug + Send + Sync {
+    /// The node sent a `HEARTBEAT` packet
+    fn heartbeat(&self);
+}
+
+/// The discoverer of the `registry` option
+pub(crate) fn from_config(config: &Arc<Config>) -> Box<dyn Discoverer> {
+    match &config.registry {
+        config::Registry::Local => Box::new(LocalDiscoverer::new(
+            config.heartbeat_expiry(),
+            &config.clock,
+        )),
+        #[cfg(feature = "redis")]
+        config::Registry::Redis(redis) => Box::new(keyspace::KeyspaceDiscoverer::new(
+            config,
+            redis::RedisKeyspace::new(config, redis),
+        )),
+        #[cfg(feature = "etcd")]
+        config::Registry::Etcd(etcd) => Box::new(keyspace::KeyspaceDiscoverer::new(
+            config,
+            etcd::EtcdKeyspace::new(config, etcd),
+        )),
+    }
+}
+
+/// [`Registry::Local`][config::Registry::Local]: a node is gone once it hasn't sent a
+/// `HEARTBEAT` packet through the transporter for `heartbeat_expiry`
+pub(crate) struct LocalDiscoverer {
+    heartbeat_expiry: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl LocalDiscoverer {
+    pub(crate) fn new(heartbeat_expiry: Duration, clock: &Arc<dyn Clock>) -> Self {
+        Self {
+            heartbeat_expiry,
+            clock: Arc::clone(clock),
+        }
+    }
+}
+
+impl Discoverer for LocalDiscoverer {
+    fn watch(&self, node_name: &NodeName, expired: Expired) -> Arc<dyn WatchedNode> {
+        let node_watcher = NodeWatcher::new(
+            node_name.clone(),
+            self.heartbeat_expiry,
+            &self.clock,
+            expired,
+        );
+
+        Arc::new(spawn_actor(node_watcher))
+    }
+}
+
+impl WatchedNode for Addr<NodeWatcher> {
+    fn heartbeat(&self) {
+        send!(self.received_heartbeat());
+    }
+}
+
+#[async_trait]
+impl Actor for NodeWatcher {
+    async fn started(&mut self, pid: Addr<Self>) -> ActorResult<()> {
+        self.pid = pid.downgrade();
+
+        // // Start the timer
+        self.timer
+            .set_timeout_for_weak(self.pid.clone(), self.heartbeat_expiry);
+
+        Produces::ok(())
+    }
+}
+
+#[async_trait]
+impl Tick for NodeWatcher {
+    async fn tick(&mut self) -> ActorResult<()> {
+        if self.timer.tick() {
+            let since_heartbeat = self.clock.now().duration_since(self.last_heartbeat);
+
+            if since_heartbeat >= self.heartbeat_expiry {
+                // haven't received a heartbeat recently
+                (self.expired)(self.node_name.clone())
+            } else {
+                // reschedule timer for when the last heartbeat expires
+                self.timer.set_timeout_for_weak(
+                    self.pid.clone(),
+                    self.heartbeat_expiry - since_heartbeat,
+                );
+            }
+        }
+        Produces::ok(())
+    }
+}
+
+pub(crate) struct NodeWatcher {
+    node_name: NodeName,
+    pid: WeakAddr<Self>,
+    // tells the broker the node missed its heartbeats
+    expired: Expired,
+    clock: Arc<dyn Clock>,
+    timer: Timer,
+
+    heartbeat_expiry: Duration,
+    last_heartbeat: Instant,
+}
+
+impl NodeWatcher {
+    fn new(
+        name: NodeName,
+        heartbeat_expiry: Duration,
+        clock: &Arc<dyn Clock>,
+        expired: Expired,
+    ) -> Self {
+        Self {
+            node_name: name,
+            pid: WeakAddr::detached(),
+            expired,
+            clock: Arc::clone(clock),
+            timer: Timer::new(clock),
+
+            heartbeat_expiry,
+            last_heartbeat: clock.now(),
+        }
+    }
+
+    pub(crate) async fn received_heartbeat(&mut self) -> ActorResult<()> {
+        self.last_heartbeat = self.clock.now();
+        Produces::ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[tokio::test]
+    async fn node_expires_once_its_heartbeats_stop() {
+        let clock = Arc::new(ManualClock::new());
+        let dyn_clock: Arc<dyn Clock> = clock.clone();
+        let (tx, mut expired) = tokio::sync::mpsc::unbounded_channel();
+
+        let watcher = spawn_actor(NodeWatcher::new(
+            "node-1".to_string(),
+            Duration::from_secs(15),
+            &dyn_clock,
+            Box::new(move |node_name| {
+                let _ = tx.send(node_name);
+            }),
+        ));
+        clock.wait_for_sleeps(1).await;
+
+        // a heartbeat pushes the expiry back
+        clock.advance(Duration::from_secs(10));
+        call!(watcher.received_heartbeat()).await.unwrap();
+        clock.advance(Duration::from_secs(5));
+
+        clock.wait_for_sleeps(1).await;
+        assert!(expired.try_recv().is_err());
+
+        clock.advance(Duration::from_secs(10));
+
+        let node_name = tokio::time::timeout(Duration::from_secs(5), expired.recv())
+            .await
+            .expect("the node should expire");
+        assert_eq!(node_name.as_deref(), Some("node-1"));
+    }
+
+    #[tokio::test]
+    async fn local_discoverer_expires_nodes_without_heartbeats() {
+        let clock = Arc::new(ManualClock::new());
+        let dyn_clock: Arc<dyn Clock> = clock.clone();
+        let discoverer = LocalDiscoverer::new(Duration::from_secs(15), &dyn_clock);
+        let (tx, mut expired) = tokio::sync::mpsc::unbounded_channel();
+
+        let _watched = discoverer.watch(
+            &"node-2".to_string(),
+            Box::new(move |node_name| {
+                let _ = tx.send(node_name);
+            }),
+        );
+        clock.wait_for_sleeps(1).await;
+
+        clock.advance(Duration::from_secs(15));
+
+        let node_name = tokio::time::timeout(Duration::from_secs(5), expired.recv())
+            .await
+            .expect("the node should expire");
+        assert_eq!(node_name.as_deref(), Some("node-2"));
+    }
+}
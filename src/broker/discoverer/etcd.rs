@@ -0,0 +1,541 @@
+//! [`Registry::Etcd`][crate::config::Registry::Etcd]: the keys of the `Etcd3` discoverer of
+//! Moleculer JS, written through the JSON gateway of etcd v3 (`/v3/...`).
+//!
+//! - `moleculer-<namespace>/discovery/beats/<node id>/<instance id>` is attached to a lease of
+//!   `heartbeat_timeout`, a new lease is granted with each heartbeat and the previous one revoked
+//! - `moleculer-<namespace>/discovery/info/<node id>` holds the `INFO` packet of the node
+
+use std::{io, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+
+use crate::config::{Config, EtcdRegistry};
+
+use super::keyspace::{self, Beat, Keyspace};
+
+// a gateway that doesn't answer is retried with the next heartbeat
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(crate) struct EtcdKeyspace {
+    config: Arc<Config>,
+    registry: EtcdRegistry,
+    prefix: String,
+    // the leases of the keys of this node, revoked once replaced
+    beat_lease: Option<String>,
+    info_lease: Option<String>,
+}
+
+impl EtcdKeyspace {
+    pub(crate) fn new(config: &Arc<Config>, registry: &EtcdRegistry) -> Self {
+        let prefix = match config.namespace.as_str() {
+            "" => "moleculer/discovery".to_string(),
+            namespace => format!("moleculer-{}/discovery", namespace),
+        };
+
+        Self {
+            config: Arc::clone(config),
+            registry: registry.clone(),
+            prefix,
+            beat_lease: None,
+            info_lease: None,
+        }
+    }
+
+    fn beat_prefix(&self) -> String {
+        format!("{}/beats/", self.prefix)
+    }
+
+    fn info_key(&self, node_id: &str) -> String {
+        format!("{}/info/{}", self.prefix, node_id)
+    }
+
+    async fn grant(&self, ttl: Duration) -> io::Result<String> {
+        let granted: LeaseGrant = self
+            .post("/v3/lease/grant", json!({ "TTL": ttl.as_secs().max(1) }))
+            .await?;
+
+        Ok(granted.id)
+    }
+
+    async fn revoke(&self, lease: Option<String>) -> io::Result<()> {
+        if let Some(lease) = lease {
+            let _: Value = self
+                .post("/v3/lease/revoke", json!({ "ID": lease }))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn put(&self, key: &str, value: &[u8], lease: &str) -> io::Result<()> {
+        let request = json!({
+            "key": base64::encode(key),
+            "value": base64::encode(value),
+            "lease": lease,
+        });
+        let _: Value = self.post("/v3/kv/put", request).await?;
+
+        Ok(())
+    }
+
+    async fn range(&self, key: &str, range_end: Option<&[u8]>) -> io::Result<Vec<KeyValue>> {
+        let mut request = json!({ "key": base64::encode(key) });
+        if let Some(range_end) = range_end {
+            request["range_end"] = base64::encode(range_end).into();
+        }
+
+        let range: Range = self.post("/v3/kv/range", request).await?;
+
+        range
+            .kvs
+            .into_iter()
+            .map(|kv| {
+                Ok(KeyValue {
+                    key: base64::decode(kv.key).map_err(invalid_data)?,
+                    value: base64::decode(kv.value).map_err(invalid_data)?,
+                })
+            })
+            .collect()
+    }
+
+    // one connection per request, the gateway closes it after the response
+    async fn post<T: for<'de> Deserialize<'de>>(&self, path: &str, body: Value) -> io::Result<T> {
+        let address = self
+            .registry
+            .address()
+            .map_err(|reason| io::Error::new(io::ErrorKind::InvalidInput, reason))?;
+        let body = body.to_string();
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            address.host,
+            body.len(),
+            body
+        );
+
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, async {
+            let stream = TcpStream::connect(&address.host).await?;
+            keyspace::write_all(&stream, request.as_bytes()).await?;
+
+            let mut response = Vec::new();
+            while keyspace::read_some(&stream, &mut response).await? > 0 {}
+
+            Ok::<_, io::Error>(response)
+        })
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the etcd gateway didn't answer",
+            ))
+        })?;
+
+        let (status, body) = parse_response(&response)?;
+        if status != 200 {
+            let message = String::from_utf8_lossy(&body);
+            return Err(io::Error::other(format!(
+                "{} answered {}: {}",
+                path,
+                status,
+                message.trim()
+            )));
+        }
+
+        serde_json::from_slice(&body).map_err(invalid_data)
+    }
+}
+
+#[async_trait]
+impl Keyspace for EtcdKeyspace {
+    async fn put_beat(&mut self, seq: u64, ttl: Duration) -> io::Result<()> {
+        let key = format!(
+            "{}{}/{}",
+            self.beat_prefix(),
+            self.config.node_id,
+            self.config.instance_id
+        );
+        let value = keyspace::beat_value(&self.config, seq);
+
+        let lease = self.grant(ttl).await?;
+        self.put(&key, &value, &lease).await?;
+
+        // the key stays attached to the new lease
+        let previous = self.beat_lease.replace(lease);
+        self.revoke(previous).await
+    }
+
+    async fn put_info(&mut self, info: &[u8], ttl: Duration) -> io::Result<()> {
+        let key = self.info_key(&self.config.node_id);
+
+        let lease = self.grant(ttl).await?;
+        self.put(&key, info, &lease).await?;
+
+        let previous = self.info_lease.replace(lease);
+        self.revoke(previous).await
+    }
+
+    async fn beats(&mut self) -> io::Result<Vec<Beat>> {
+        let beat_prefix = self.beat_prefix();
+        let range_end = prefix_end(beat_prefix.as_bytes());
+
+        let beats = self
+            .range(&beat_prefix, Some(&range_end))
+            .await?
+            .into_iter()
+            .filter_map(|kv| parse_beat(&beat_prefix, &kv))
+            .collect();
+
+        Ok(beats)
+    }
+
+    async fn info(&mut self, node_id: &str) -> io::Result<Option<Vec<u8>>> {
+        let key = self.info_key(node_id);
+
+        Ok(self.range(&key, None).await?.pop().map(|kv| kv.value))
+    }
+
+    async fn remove(&mut self) -> io::Result<()> {
+        // the keys are deleted with their lease
+        let beat_lease = self.beat_lease.take();
+        let info_lease = self.info_lease.take();
+
+        self.revoke(beat_lease).await?;
+        self.revoke(info_lease).await
+    }
+}
+
+#[derive(Deserialize)]
+struct LeaseGrant {
+    // int64 are strings in the JSON of the gateway
+    #[serde(rename = "ID")]
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Range {
+    // missing when no key is in the range
+    #[serde(default)]
+    kvs: Vec<EncodedKeyValue>,
+}
+
+#[derive(Deserialize)]
+struct EncodedKeyValue {
+    key: String,
+    #[serde(default)]
+    value: String,
+}
+
+struct KeyValue {
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+// the end of the range of the keys starting with `prefix`
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+
+    // every key after `prefix`
+    vec![0]
+}
+
+// `<node id>/<instance id>` after the prefix, the `seq` is in the value
+fn parse_beat(beat_prefix: &str, kv: &KeyValue) -> Option<Beat> {
+    let key = std::str::from_utf8(&kv.key).ok()?;
+    let (node_id, instance_id) = key.strip_prefix(beat_prefix)?.rsplit_once('/')?;
+    let value: Value = serde_json::from_slice(&kv.value).ok()?;
+
+    Some(Beat {
+        node_id: node_id.to_string(),
+        instance_id: instance_id.to_string(),
+        seq: value.get("seq")?.as_u64()?,
+    })
+}
+
+// the status and the body of a whole HTTP/1.1 response
+fn parse_response(response: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+    let head_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid_data("incomplete response"))?;
+    let head = std::str::from_utf8(&response[..head_end]).map_err(invalid_data)?;
+    let body = &response[head_end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|status_line| status_line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid_data("invalid status line"))?;
+
+    let mut chunked = false;
+    let mut content_length = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+
+            if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = Some(value.parse::<usize>().map_err(invalid_data)?);
+            }
+        }
+    }
+
+    let body = match (chunked, content_length) {
+        (true, _) => decode_chunks(body)?,
+        (false, Some(length)) => body
+            .get(..length)
+            .ok_or_else(|| invalid_data("incomplete body"))?
+            .to_vec(),
+        (false, None) => body.to_vec(),
+    };
+
+    Ok((status, body))
+}
+
+fn decode_chunks(mut chunks: &[u8]) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+
+    loop {
+        let line_end = chunks
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or_else(|| invalid_data("incomplete chunk"))?;
+        let size = std::str::from_utf8(&chunks[..line_end]).map_err(invalid_data)?;
+        // chunk extensions follow a ';'
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(invalid_data)?;
+
+        if size == 0 {
+            return Ok(body);
+        }
+
+        let chunk = chunks
+            .get(line_end + 2..line_end + 2 + size)
+            .ok_or_else(|| invalid_data("incomplete chunk"))?;
+        body.extend_from_slice(chunk);
+        chunks = chunks.get(line_end + 4 + size..).unwrap_or_default();
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigBuilder, Registry};
+    use std::{
+        collections::HashMap,
+        sync::{Arc as StdArc, Mutex},
+    };
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn responses_are_read_with_their_length_or_in_chunks() {
+        assert_eq!(
+            parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}").unwrap(),
+            (200, b"{}".to_vec())
+        );
+        assert_eq!(
+            parse_response(
+                b"HTTP/1.1 400 Bad Request\r\ntransfer-encoding: chunked\r\n\r\n\
+                  4\r\n{\"er\r\n6;ext=1\r\nror\":1\r\n1\r\n}\r\n0\r\n\r\n"
+            )
+            .unwrap(),
+            (400, b"{\"error\":1}".to_vec())
+        );
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n").is_err());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n{}").is_err());
+    }
+
+    #[test]
+    fn the_range_of_a_prefix_ends_after_its_keys() {
+        assert_eq!(prefix_end(b"a/beats/"), b"a/beats0".to_vec());
+        assert_eq!(prefix_end(b"a\xff\xff"), b"b".to_vec());
+        assert_eq!(prefix_end(b"\xff"), vec![0]);
+    }
+
+    #[test]
+    fn beats_hold_the_node_and_instance_in_their_key() {
+        let kv = KeyValue {
+            key: b"moleculer/discovery/beats/node/1/abc-123".to_vec(),
+            value: br#"{"sender":"node/1","seq":7}"#.to_vec(),
+        };
+
+        assert_eq!(
+            parse_beat("moleculer/discovery/beats/", &kv),
+            Some(Beat {
+                node_id: "node/1".to_string(),
+                instance_id: "abc-123".to_string(),
+                seq: 7,
+            })
+        );
+        assert_eq!(parse_beat("moleculer-prod/discovery/beats/", &kv), None);
+    }
+
+    #[derive(Default)]
+    struct Store {
+        next_lease: u64,
+        leases: Vec<String>,
+        // the value and the lease of each key
+        keys: HashMap<Vec<u8>, (Vec<u8>, String)>,
+    }
+
+    impl Store {
+        fn handle(&mut self, path: &str, request: Value) -> Value {
+            let decode = |field: &Value| base64::decode(field.as_str().unwrap()).unwrap();
+
+            match path {
+                "/v3/lease/grant" => {
+                    self.next_lease += 1;
+                    self.leases.push(self.next_lease.to_string());
+                    json!({ "ID": self.next_lease.to_string(), "TTL": request["TTL"] })
+                }
+                "/v3/lease/revoke" => {
+                    let lease = request["ID"].as_str().unwrap().to_string();
+                    self.leases.retain(|granted| *granted != lease);
+                    self.keys.retain(|_, (_, key_lease)| *key_lease != lease);
+                    json!({})
+                }
+                "/v3/kv/put" => {
+                    let lease = request["lease"].as_str().unwrap().to_string();
+                    assert!(self.leases.contains(&lease), "lease {} not granted", lease);
+                    self.keys
+                        .insert(decode(&request["key"]), (decode(&request["value"]), lease));
+                    json!({})
+                }
+                "/v3/kv/range" => {
+                    let key = decode(&request["key"]);
+                    let kvs: Vec<Value> = self
+                        .keys
+                        .iter()
+                        .filter(|(stored, _)| match request.get("range_end") {
+                            Some(range_end) => {
+                                **stored >= key && **stored < decode(range_end)
+                            }
+                            None => **stored == key,
+                        })
+                        .map(|(key, (value, _))| {
+                            json!({ "key": base64::encode(key), "value": base64::encode(value) })
+                        })
+                        .collect();
+
+                    match kvs.is_empty() {
+                        true => json!({ "count": "0" }),
+                        false => json!({ "kvs": kvs, "count": kvs.len().to_string() }),
+                    }
+                }
+                _ => unreachable!("unknown path {}", path),
+            }
+        }
+    }
+
+    // a gateway keeping its keys in memory, leases don't expire
+    async fn fake_gateway(store: StdArc<Mutex<Store>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+
+                // the request is whole once its body is
+                let (path, body) = loop {
+                    keyspace::read_some(&stream, &mut request).await.unwrap();
+
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length: usize = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("Content-Length: "))
+                            .unwrap()
+                            .parse()
+                            .unwrap();
+
+                        if body.len() == length {
+                            let path = head.split(' ').nth(1).unwrap().to_string();
+                            break (path, serde_json::from_str(body).unwrap());
+                        }
+                    }
+                };
+
+                let body = store.lock().unwrap().handle(&path, body).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Transfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                    body.len(),
+                    body
+                );
+                keyspace::write_all(&stream, response.as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn heartbeats_and_info_go_to_the_moleculer_keys() {
+        let store = StdArc::new(Mutex::new(Store::default()));
+        let url = fake_gateway(StdArc::clone(&store)).await;
+        let config = Arc::new(
+            ConfigBuilder::default()
+                .node_id("node-1")
+                .namespace("prod")
+                .registry(Registry::etcd(url))
+                .build(),
+        );
+        let registry = match &config.registry {
+            Registry::Etcd(registry) => registry.clone(),
+            _ => unreachable!(),
+        };
+        let mut keyspace = EtcdKeyspace::new(&config, &registry);
+        let beat = |seq| Beat {
+            node_id: "node-1".to_string(),
+            instance_id: config.instance_id.clone(),
+            seq,
+        };
+
+        keyspace.put_beat(1, Duration::from_secs(15)).await.unwrap();
+        keyspace
+            .put_info(b"{\"sender\":\"node-1\"}", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(keyspace.beats().await.unwrap(), vec![beat(1)]);
+        assert_eq!(
+            keyspace.info("node-1").await.unwrap().as_deref(),
+            Some(&b"{\"sender\":\"node-1\"}"[..])
+        );
+        assert_eq!(keyspace.info("node-2").await.unwrap(), None);
+        assert!(store
+            .lock()
+            .unwrap()
+            .keys
+            .contains_key(&b"moleculer-prod/discovery/info/node-1"[..]));
+
+        // the lease of the previous heartbeat is revoked
+        keyspace.put_beat(2, Duration::from_secs(15)).await.unwrap();
+        assert_eq!(keyspace.beats().await.unwrap(), vec![beat(2)]);
+        assert_eq!(store.lock().unwrap().leases.len(), 2);
+
+        keyspace.remove().await.unwrap();
+        assert_eq!(keyspace.beats().await.unwrap(), vec![]);
+        assert_eq!(keyspace.info("node-1").await.unwrap(), None);
+        assert!(store.lock().unwrap().leases.is_empty());
+    }
+}
@@ -0,0 +1,576 @@
+//! Discoverers keeping the heartbeats of the nodes in a store, where they expire, like the
+//! `Redis` and `Etcd3` discoverers of Moleculer JS.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, io,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use act_zero::runtimes::tokio::spawn_actor;
+use act_zero::timer::Tick;
+use act_zero::*;
+use async_trait::async_trait;
+use log::{debug, error, warn};
+use serde_json::json;
+use tokio::net::TcpStream;
+
+use crate::{
+    channels::{
+        self,
+        messages::{incoming::PacketInfo, PROTOCOL_VERSION},
+    },
+    clock::Timer,
+    config::{Channel, Config},
+};
+
+use super::{Discoverer, Expired, NodeName, ServiceBroker, WatchedNode};
+
+/// How long the `INFO` packet of a node is kept, it is written again before that
+pub(super) const INFO_TTL: Duration = Duration::from_secs(30 * 60);
+
+// the `INFO` packet of this node is written again after this long, even if it didn't change
+const INFO_REFRESH: Duration = Duration::from_secs(10 * 60);
+
+/// The keys of the nodes in a store
+#[async_trait]
+pub(super) trait Keyspace: Send + 'static {
+    /// Writes the heartbeat of this node with the `seq` of its `INFO` packet, it expires after
+    /// `ttl` unless written again
+    async fn put_beat(&mut self, seq: u64, ttl: Duration) -> io::Result<()>;
+
+    /// Writes the `INFO` packet of this node, it expires after `ttl` unless written again
+    async fn put_info(&mut self, info: &[u8], ttl: Duration) -> io::Result<()>;
+
+    /// The heartbeats that haven't expired, this node's included
+    async fn beats(&mut self) -> io::Result<Vec<Beat>>;
+
+    /// The `INFO` packet of a node, `None` if it expired
+    async fn info(&mut self, node_id: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Removes the keys of this node
+    async fn remove(&mut self) -> io::Result<()>;
+}
+
+/// The heartbeat of a node in the store
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Beat {
+    pub(super) node_id: String,
+    pub(super) instance_id: String,
+    /// The `seq` of the node's `INFO` packet, it changes with the node's services
+    pub(super) seq: u64,
+}
+
+/// A [Discoverer] watching the heartbeats of the nodes in a [Keyspace]
+pub(super) struct KeyspaceDiscoverer<K: Keyspace> {
+    config: Arc<Config>,
+    watched: Arc<Mutex<Watched>>,
+    // moved into the watcher once started
+    keyspace: Mutex<Option<K>>,
+    watcher: Mutex<Addr<KeyspaceWatcher<K>>>,
+}
+
+impl<K: Keyspace> KeyspaceDiscoverer<K> {
+    pub(super) fn new(config: &Arc<Config>, keyspace: K) -> Self {
+        Self {
+            config: Arc::clone(config),
+            watched: Arc::default(),
+            keyspace: Mutex::new(Some(keyspace)),
+            watcher: Mutex::new(Addr::detached()),
+        }
+    }
+}
+
+#[async_trait]
+impl<K: Keyspace> Discoverer for KeyspaceDiscoverer<K> {
+    fn watch(&self, node_name: &NodeName, expired: Expired) -> Arc<dyn WatchedNode> {
+        let mut watched = self.watched.lock().unwrap();
+        watched.next_id += 1;
+
+        let id = watched.next_id;
+        watched.nodes.insert(
+            node_name.clone(),
+            WatchEntry {
+                id,
+                since: self.config.clock.now(),
+                expired,
+                gone: false,
+            },
+        );
+
+        Arc::new(KeyspaceNode {
+            node_name: node_name.clone(),
+            id,
+            watched: Arc::clone(&self.watched),
+        })
+    }
+
+    fn start(&self, broker: WeakAddr<ServiceBroker>) {
+        let keyspace = match self.keyspace.lock().unwrap().take() {
+            Some(keyspace) => keyspace,
+            None => return,
+        };
+
+        let watcher =
+            KeyspaceWatcher::new(broker, &self.config, keyspace, Arc::clone(&self.watched));
+        *self.watcher.lock().unwrap() = spawn_actor(watcher);
+    }
+
+    fn local_info(&self, info: &[u8]) {
+        let watcher = self.watcher.lock().unwrap().clone();
+        send!(watcher.local_info(info.to_vec()));
+    }
+
+    async fn stop(&self) {
+        let watcher = std::mem::replace(&mut *self.watcher.lock().unwrap(), Addr::detached());
+
+        let _ = call!(watcher.stop()).await;
+    }
+}
+
+// the nodes of the registry, by name
+#[derive(Default)]
+struct Watched {
+    next_id: u64,
+    nodes: HashMap<NodeName, WatchEntry>,
+}
+
+struct WatchEntry {
+    // a node that connects again is watched again before the previous watch is dropped
+    id: u64,
+    since: Instant,
+    expired: Expired,
+    // `expired` was called, the registry is removing the node
+    gone: bool,
+}
+
+/// A node watched by a [KeyspaceDiscoverer], its heartbeats are in the store
+struct KeyspaceNode {
+    node_name: NodeName,
+    id: u64,
+    watched: Arc<Mutex<Watched>>,
+}
+
+impl WatchedNode for KeyspaceNode {
+    // the node doesn't send `HEARTBEAT` packets in this mode
+    fn heartbeat(&self) {}
+}
+
+impl fmt::Debug for KeyspaceNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyspaceNode")
+            .field("node_name", &self.node_name)
+            .finish()
+    }
+}
+
+impl Drop for KeyspaceNode {
+    fn drop(&mut self) {
+        let mut watched = self.watched.lock().unwrap();
+
+        if watched
+            .nodes
+            .get(&self.node_name)
+            .is_some_and(|entry| entry.id == self.id)
+        {
+            watched.nodes.remove(&self.node_name);
+        }
+    }
+}
+
+#[async_trait]
+impl<K: Keyspace> Actor for KeyspaceWatcher<K> {
+    async fn started(&mut self, pid: Addr<Self>) -> ActorResult<()> {
+        self.pid = pid.downgrade();
+
+        // announce this node right away, its `INFO` packet follows
+        self.beat().await;
+        self.timer
+            .set_timeout_for_weak(self.pid.clone(), self.heartbeat_interval());
+
+        Produces::ok(())
+    }
+
+    async fn error(&mut self, error: ActorError) -> bool {
+        error!("KeyspaceWatcher Actor Error: {:?}", error);
+
+        // do not stop on actor error
+        false
+    }
+}
+
+#[async_trait]
+impl<K: Keyspace> Tick for KeyspaceWatcher<K> {
+    async fn tick(&mut self) -> ActorResult<()> {
+        if self.timer.tick() && !self.stopped {
+            self.beat().await;
+            self.timer
+                .set_timeout_for_weak(self.pid.clone(), self.heartbeat_interval());
+        }
+
+        Produces::ok(())
+    }
+}
+
+/// Writes the heartbeats and the `INFO` packet of this node every `heartbeat_interval`, and
+/// reads the heartbeats of the others: new or changed nodes are discovered from their `INFO`
+/// packet, the watched nodes whose heartbeat expired are gone.
+pub(super) struct KeyspaceWatcher<K: Keyspace> {
+    pid: WeakAddr<Self>,
+    broker: WeakAddr<ServiceBroker>,
+    config: Arc<Config>,
+    keyspace: K,
+    watched: Arc<Mutex<Watched>>,
+    timer: Timer,
+    stopped: bool,
+
+    // the heartbeats of the other nodes at the last check, whose `INFO` packet was read
+    beats: HashMap<NodeName, Beat>,
+    // the last `INFO` packet of this node, and when it was written
+    info: Option<Vec<u8>>,
+    info_written_at: Option<Instant>,
+}
+
+impl<K: Keyspace> KeyspaceWatcher<K> {
+    fn new(
+        broker: WeakAddr<ServiceBroker>,
+        config: &Arc<Config>,
+        keyspace: K,
+        watched: Arc<Mutex<Watched>>,
+    ) -> Self {
+        Self {
+            pid: WeakAddr::detached(),
+            broker,
+            config: Arc::clone(config),
+            keyspace,
+            watched,
+            timer: Timer::new(&config.clock),
+            stopped: false,
+
+            beats: HashMap::new(),
+            info: None,
+            info_written_at: None,
+        }
+    }
+
+    fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.config.heartbeat_interval as u64)
+    }
+
+    pub(super) async fn local_info(&mut self, info: Vec<u8>) {
+        // also sent in reply to each `DISCOVER` packet, the same packet is written once
+        if self.stopped || self.info.as_ref() == Some(&info) && self.info_written_at.is_some() {
+            return;
+        }
+
+        self.info = Some(info);
+        self.info_written_at = None;
+        self.write_info().await;
+    }
+
+    pub(super) async fn stop(&mut self) {
+        self.stopped = true;
+
+        if let Err(err) = self.keyspace.remove().await {
+            warn!("Unable to remove the keys of this node: {}", err);
+        }
+    }
+
+    async fn write_info(&mut self) {
+        let info = match &self.info {
+            Some(info) => info,
+            None => return,
+        };
+
+        match self.keyspace.put_info(info, INFO_TTL).await {
+            Ok(()) => self.info_written_at = Some(self.config.clock.now()),
+            // written again with the next heartbeat
+            Err(err) => warn!("Unable to write the INFO packet of this node: {}", err),
+        }
+    }
+
+    async fn beat(&mut self) {
+        let seq = self.config.seq.load(Ordering::SeqCst);
+        let ttl = self.config.heartbeat_expiry();
+
+        if let Err(err) = self.keyspace.put_beat(seq, ttl).await {
+            warn!("Unable to write the heartbeat of this node: {}", err);
+        }
+
+        let info_due = self.info_written_at.is_none_or(|written_at| {
+            self.config.clock.now().duration_since(written_at) >= INFO_REFRESH
+        });
+        if info_due {
+            self.write_info().await;
+        }
+
+        // the nodes aren't expired while the store can't be read
+        match self.keyspace.beats().await {
+            Ok(beats) => self.check_beats(beats).await,
+            Err(err) => warn!("Unable to read the heartbeats of the nodes: {}", err),
+        }
+    }
+
+    async fn check_beats(&mut self, beats: Vec<Beat>) {
+        let mut alive = HashSet::new();
+        let mut checked = HashMap::new();
+
+        for beat in beats {
+            if beat.node_id == self.config.node_id {
+                continue;
+            }
+            alive.insert(beat.node_id.clone());
+
+            // a new node, or one that restarted or whose services changed, is read again at the
+            // next heartbeat if its `INFO` packet couldn't be read
+            let known = self.beats.get(&beat.node_id) == Some(&beat);
+
+            if known || self.discover(&beat.node_id).await {
+                checked.insert(beat.node_id.clone(), beat);
+            }
+        }
+
+        self.expire_nodes(&alive);
+        self.beats = checked;
+    }
+
+    // sends the `INFO` packet of the node to the broker, `false` if it couldn't be read
+    async fn discover(&mut self, node_id: &str) -> bool {
+        let info = match self.keyspace.info(node_id).await {
+            Ok(Some(info)) => info,
+            // its heartbeat outlived it, or it hasn't written it yet
+            Ok(None) => {
+                debug!("No INFO packet of node {} in the store", node_id);
+                return false;
+            }
+            Err(err) => {
+                warn!(
+                    "Unable to read the INFO packet of node {}: {}",
+                    node_id, err
+                );
+                return false;
+            }
+        };
+
+        match channels::parse_packet::<PacketInfo>(&self.config, &Channel::Info, &info) {
+            Ok(info) => {
+                send!(self.broker.handle_info_message(info));
+                true
+            }
+            Err(err) => {
+                warn!(
+                    "Unable to parse the INFO packet of node {}: {}",
+                    node_id, err
+                );
+                false
+            }
+        }
+    }
+
+    fn expire_nodes(&self, alive: &HashSet<NodeName>) {
+        let now = self.config.clock.now();
+        let heartbeat_expiry = self.config.heartbeat_expiry();
+        let mut watched = self.watched.lock().unwrap();
+
+        for (node_name, entry) in watched.nodes.iter_mut() {
+            if entry.gone || alive.contains(node_name) {
+                continue;
+            }
+
+            // a node announced by its `INFO` packet may not have written its heartbeat yet
+            let seen = self.beats.contains_key(node_name);
+            if seen || now.duration_since(entry.since) >= heartbeat_expiry {
+                debug!("The heartbeat of node {} expired", node_name);
+                entry.gone = true;
+                (entry.expired)(node_name.clone());
+            }
+        }
+    }
+}
+
+/// The value of the heartbeat of this node, like the `HEARTBEAT` packet of the Moleculer JS
+/// discoverers
+pub(super) fn beat_value(config: &Config, seq: u64) -> Vec<u8> {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+
+    json!({
+        "ver": PROTOCOL_VERSION,
+        "sender": config.node_id,
+        "timestamp": timestamp.as_millis() as u64,
+        "seq": seq,
+        "instanceID": config.instance_id,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+/// Writes the whole request to a connection to the store
+pub(super) async fn write_all(stream: &TcpStream, mut bytes: &[u8]) -> io::Result<()> {
+    while !bytes.is_empty() {
+        stream.writable().await?;
+
+        match stream.try_write(bytes) {
+            Ok(written) => bytes = &bytes[written..],
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads what the store sent so far, at least a byte, `0` once the connection is closed
+pub(super) async fn read_some(stream: &TcpStream, buffer: &mut Vec<u8>) -> io::Result<usize> {
+    let mut buf = [0; 4096];
+
+    loop {
+        stream.readable().await?;
+
+        match stream.try_read(&mut buf) {
+            Ok(read) => {
+                buffer.extend_from_slice(&buf[..read]);
+                return Ok(read);
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, config::ConfigBuilder};
+
+    #[derive(Default)]
+    struct Store {
+        beats: HashMap<String, Beat>,
+        infos: HashMap<String, Vec<u8>>,
+        info_reads: usize,
+    }
+
+    // a store shared with the test, keys don't expire
+    struct MemoryKeyspace {
+        node_id: String,
+        store: Arc<Mutex<Store>>,
+    }
+
+    #[async_trait]
+    impl Keyspace for MemoryKeyspace {
+        async fn put_beat(&mut self, seq: u64, _ttl: Duration) -> io::Result<()> {
+            let beat = Beat {
+                node_id: self.node_id.clone(),
+                instance_id: "instance".to_string(),
+                seq,
+            };
+            self.store
+                .lock()
+                .unwrap()
+                .beats
+                .insert(self.node_id.clone(), beat);
+            Ok(())
+        }
+
+        async fn put_info(&mut self, info: &[u8], _ttl: Duration) -> io::Result<()> {
+            let mut store = self.store.lock().unwrap();
+            store.infos.insert(self.node_id.clone(), info.to_vec());
+            Ok(())
+        }
+
+        async fn beats(&mut self) -> io::Result<Vec<Beat>> {
+            Ok(self.store.lock().unwrap().beats.values().cloned().collect())
+        }
+
+        async fn info(&mut self, node_id: &str) -> io::Result<Option<Vec<u8>>> {
+            let mut store = self.store.lock().unwrap();
+            store.info_reads += 1;
+            Ok(store.infos.get(node_id).cloned())
+        }
+
+        async fn remove(&mut self) -> io::Result<()> {
+            let mut store = self.store.lock().unwrap();
+            store.beats.remove(&self.node_id);
+            store.infos.remove(&self.node_id);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn nodes_expire_once_their_heartbeat_is_gone_from_the_store() {
+        let clock = Arc::new(ManualClock::new());
+        let config = Arc::new(
+            ConfigBuilder::default()
+                .node_id("node-1")
+                .clock(clock.clone())
+                .build(),
+        );
+        let store = Arc::new(Mutex::new(Store::default()));
+        let keyspace = || MemoryKeyspace {
+            node_id: "node-1".to_string(),
+            store: Arc::clone(&store),
+        };
+        let discoverer = KeyspaceDiscoverer::new(&config, keyspace());
+        let mut watcher = KeyspaceWatcher::new(
+            WeakAddr::detached(),
+            &config,
+            keyspace(),
+            Arc::clone(&discoverer.watched),
+        );
+
+        let expired = Arc::new(Mutex::new(vec![]));
+        let watch = |node_name: &str| {
+            let expired = Arc::clone(&expired);
+            discoverer.watch(
+                &node_name.to_string(),
+                Box::new(move |node_name| expired.lock().unwrap().push(node_name)),
+            )
+        };
+        let _node_2 = watch("node-2");
+        let _node_3 = watch("node-3");
+
+        // node-2 announced itself, its `INFO` packet isn't written yet
+        store.lock().unwrap().beats.insert(
+            "node-2".to_string(),
+            Beat {
+                node_id: "node-2".to_string(),
+                instance_id: "instance".to_string(),
+                seq: 1,
+            },
+        );
+
+        watcher.local_info(b"{}".to_vec()).await;
+        watcher.beat().await;
+        assert!(store.lock().unwrap().beats.contains_key("node-1"));
+        assert_eq!(store.lock().unwrap().infos["node-1"], b"{}".to_vec());
+        assert_eq!(store.lock().unwrap().info_reads, 1);
+        assert!(expired.lock().unwrap().is_empty());
+
+        // read once it is written, then not until its heartbeat changes
+        let info = json!({ "ver": PROTOCOL_VERSION, "sender": "node-2", "seq": 1 });
+        store
+            .lock()
+            .unwrap()
+            .infos
+            .insert("node-2".to_string(), info.to_string().into_bytes());
+        watcher.beat().await;
+        watcher.beat().await;
+        assert_eq!(store.lock().unwrap().info_reads, 2);
+        assert!(watcher.beats.contains_key("node-2"));
+
+        // node-3 never wrote a heartbeat, it may not have had the time to
+        clock.advance(config.heartbeat_expiry());
+        store.lock().unwrap().beats.remove("node-2");
+        watcher.beat().await;
+        let mut expired_nodes = expired.lock().unwrap().clone();
+        expired_nodes.sort();
+        assert_eq!(expired_nodes, vec!["node-2", "node-3"]);
+
+        // this node's keys are removed once it stops
+        watcher.stop().await;
+        assert!(store.lock().unwrap().beats.is_empty());
+        assert!(!store.lock().unwrap().infos.contains_key("node-1"));
+    }
+}
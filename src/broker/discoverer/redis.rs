@@ -0,0 +1,560 @@
+//! [`Registry::Redis`][crate::config::Registry::Redis]: the keys of the `Redis` discoverer of
+//! Moleculer JS, written with a minimal client of the Redis protocol (RESP).
+//!
+//! - `MOL-<namespace>-DSCVR-BEAT:<node id>|<instance id>|<seq>` expires after
+//!   `heartbeat_timeout`, the key of the previous `seq` is removed when it changes
+//! - `MOL-<namespace>-DSCVR-INFO:<node id>` holds the `INFO` packet of the node
+
+use std::{collections::HashMap, convert::TryFrom, io, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::net::TcpStream;
+
+use crate::config::{Config, RedisRegistry};
+
+use super::keyspace::{self, Beat, Keyspace};
+
+// a server that doesn't answer is retried with the next heartbeat
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(crate) struct RedisKeyspace {
+    config: Arc<Config>,
+    registry: RedisRegistry,
+    prefix: String,
+    connection: Option<Connection>,
+    // the heartbeat key of the last `seq` written
+    beat_key: Option<String>,
+}
+
+impl RedisKeyspace {
+    pub(crate) fn new(config: &Arc<Config>, registry: &RedisRegistry) -> Self {
+        let prefix = match config.namespace.as_str() {
+            "" => "MOL-DSCVR".to_string(),
+            namespace => format!("MOL-{}-DSCVR", namespace),
+        };
+
+        Self {
+            config: Arc::clone(config),
+            registry: registry.clone(),
+            prefix,
+            connection: None,
+            beat_key: None,
+        }
+    }
+
+    fn beat_prefix(&self) -> String {
+        format!("{}-BEAT:", self.prefix)
+    }
+
+    fn info_key(&self, node_id: &str) -> String {
+        format!("{}-INFO:{}", self.prefix, node_id)
+    }
+
+    // connects on the first command, and again after the connection failed
+    async fn command(&mut self, args: &[&[u8]]) -> io::Result<Reply> {
+        let result = tokio::time::timeout(COMMAND_TIMEOUT, async {
+            let connection = match &mut self.connection {
+                Some(connection) => connection,
+                None => self
+                    .connection
+                    .insert(Connection::open(&self.registry).await?),
+            };
+
+            connection.command(args).await
+        })
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the Redis server didn't answer",
+            ))
+        });
+
+        if result.is_err() {
+            self.connection = None;
+        }
+
+        match result? {
+            Reply::Error(message) => Err(io::Error::other(message)),
+            reply => Ok(reply),
+        }
+    }
+}
+
+#[async_trait]
+impl Keyspace for RedisKeyspace {
+    async fn put_beat(&mut self, seq: u64, ttl: Duration) -> io::Result<()> {
+        let key = format!(
+            "{}{}|{}|{}",
+            self.beat_prefix(),
+            self.config.node_id,
+            self.config.instance_id,
+            seq
+        );
+        let ttl = ttl.as_secs().max(1).to_string();
+        let value = keyspace::beat_value(&self.config, seq);
+
+        self.command(&[b"SETEX", key.as_bytes(), ttl.as_bytes(), &value])
+            .await?;
+
+        // the heartbeat of the previous seq would be seen until it expires
+        if let Some(previous) = self.beat_key.replace(key) {
+            if Some(&previous) != self.beat_key.as_ref() {
+                self.command(&[b"DEL", previous.as_bytes()]).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn put_info(&mut self, info: &[u8], ttl: Duration) -> io::Result<()> {
+        let key = self.info_key(&self.config.node_id);
+        let ttl = ttl.as_secs().max(1).to_string();
+
+        self.command(&[b"SETEX", key.as_bytes(), ttl.as_bytes(), info])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn beats(&mut self) -> io::Result<Vec<Beat>> {
+        let beat_prefix = self.beat_prefix();
+        let pattern = format!("{}*", escape_pattern(&beat_prefix));
+        let mut beats: HashMap<String, Beat> = HashMap::new();
+        let mut cursor = "0".to_string();
+
+        loop {
+            let reply = self
+                .command(&[
+                    b"SCAN",
+                    cursor.as_bytes(),
+                    b"MATCH",
+                    pattern.as_bytes(),
+                    b"COUNT",
+                    b"100",
+                ])
+                .await?;
+
+            let (next_cursor, keys) = match reply {
+                Reply::Array(Some(mut items)) if items.len() == 2 => {
+                    let keys = items.pop().expect("two items");
+                    let next_cursor = items.pop().expect("two items");
+                    (bulk_string(next_cursor)?, keys)
+                }
+                reply => return Err(unexpected(&reply)),
+            };
+
+            let keys = match keys {
+                Reply::Array(Some(keys)) => keys,
+                reply => return Err(unexpected(&reply)),
+            };
+
+            for key in keys {
+                let beat = match parse_beat_key(&beat_prefix, &bulk_string(key)?) {
+                    Some(beat) => beat,
+                    None => continue,
+                };
+
+                // a node that stopped without removing its key may have one for an older seq
+                match beats.get(&beat.node_id) {
+                    Some(seen) if seen.seq >= beat.seq => {}
+                    _ => {
+                        beats.insert(beat.node_id.clone(), beat);
+                    }
+                }
+            }
+
+            if next_cursor == "0" {
+                return Ok(beats.into_values().collect());
+            }
+
+            cursor = next_cursor;
+        }
+    }
+
+    async fn info(&mut self, node_id: &str) -> io::Result<Option<Vec<u8>>> {
+        let key = self.info_key(node_id);
+
+        match self.command(&[b"GET", key.as_bytes()]).await? {
+            Reply::Bulk(info) => Ok(info),
+            reply => Err(unexpected(&reply)),
+        }
+    }
+
+    async fn remove(&mut self) -> io::Result<()> {
+        let info_key = self.info_key(&self.config.node_id);
+
+        match self.beat_key.take() {
+            Some(beat_key) => {
+                self.command(&[b"DEL", beat_key.as_bytes(), info_key.as_bytes()])
+                    .await?
+            }
+            None => self.command(&[b"DEL", info_key.as_bytes()]).await?,
+        };
+
+        Ok(())
+    }
+}
+
+/// A connection to the Redis server, authenticated and on its database
+struct Connection {
+    stream: TcpStream,
+    // what was read past the last reply
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    async fn open(registry: &RedisRegistry) -> io::Result<Self> {
+        let address = registry
+            .address()
+            .map_err(|reason| io::Error::new(io::ErrorKind::InvalidInput, reason))?;
+
+        let mut connection = Self {
+            stream: TcpStream::connect(&address.host).await?,
+            buffer: Vec::new(),
+        };
+
+        let auth: Option<Vec<&[u8]>> = match (&address.user, &address.password) {
+            (Some(user), Some(password)) => {
+                Some(vec![b"AUTH", user.as_bytes(), password.as_bytes()])
+            }
+            (None, Some(password)) => Some(vec![b"AUTH", password.as_bytes()]),
+            _ => None,
+        };
+        if let Some(auth) = auth {
+            expect_ok(connection.command(&auth).await?)?;
+        }
+
+        if let Some(database) = address.database {
+            let database = database.to_string();
+            expect_ok(
+                connection
+                    .command(&[b"SELECT", database.as_bytes()])
+                    .await?,
+            )?;
+        }
+
+        Ok(connection)
+    }
+
+    async fn command(&mut self, args: &[&[u8]]) -> io::Result<Reply> {
+        keyspace::write_all(&self.stream, &encode_command(args)).await?;
+
+        loop {
+            if let Some((reply, length)) = parse_reply(&self.buffer)? {
+                self.buffer.drain(..length);
+                return Ok(reply);
+            }
+
+            if keyspace::read_some(&self.stream, &mut self.buffer).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
+
+/// A reply of the Redis server
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    /// `None` for a missing key
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+// a command is sent as an array of bulk strings
+fn encode_command(args: &[&[u8]]) -> Vec<u8> {
+    let mut command = format!("*{}\r\n", args.len()).into_bytes();
+
+    for arg in args {
+        command.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        command.extend_from_slice(arg);
+        command.extend_from_slice(b"\r\n");
+    }
+
+    command
+}
+
+// the reply at the start of `buffer` and its length, `None` until it is fully received
+fn parse_reply(buffer: &[u8]) -> io::Result<Option<(Reply, usize)>> {
+    let line_end = match buffer.windows(2).position(|window| window == b"\r\n") {
+        Some(line_end) => line_end,
+        None => return Ok(None),
+    };
+
+    let line = std::str::from_utf8(&buffer[1..line_end.max(1)]).map_err(invalid_data)?;
+    let parsed = line_end + 2;
+    let length = || line.parse::<i64>().map_err(invalid_data);
+
+    let reply = match buffer[0] {
+        b'+' => (Reply::Status(line.to_string()), parsed),
+        b'-' => (Reply::Error(line.to_string()), parsed),
+        b':' => (Reply::Integer(length()?), parsed),
+        b'$' => match usize::try_from(length()?) {
+            Err(_) => (Reply::Bulk(None), parsed),
+            Ok(length) if buffer.len() < parsed + length + 2 => return Ok(None),
+            Ok(length) => (
+                Reply::Bulk(Some(buffer[parsed..parsed + length].to_vec())),
+                parsed + length + 2,
+            ),
+        },
+        b'*' => match usize::try_from(length()?) {
+            Err(_) => (Reply::Array(None), parsed),
+            Ok(length) => {
+                let mut items = Vec::with_capacity(length.min(1024));
+                let mut offset = parsed;
+
+                for _ in 0..length {
+                    match parse_reply(&buffer[offset..])? {
+                        Some((item, item_length)) => {
+                            items.push(item);
+                            offset += item_length;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+
+                (Reply::Array(Some(items)), offset)
+            }
+        },
+        kind => {
+            return Err(invalid_data(format!(
+                "unknown reply type '{}'",
+                (kind as char).escape_default()
+            )))
+        }
+    };
+
+    Ok(Some(reply))
+}
+
+// `<node id>|<instance id>|<seq>` after the prefix, node ids may contain '|'
+fn parse_beat_key(beat_prefix: &str, key: &str) -> Option<Beat> {
+    let mut parts = key.strip_prefix(beat_prefix)?.rsplitn(3, '|');
+
+    let seq = parts.next()?.parse().ok()?;
+    let instance_id = parts.next()?.to_string();
+    let node_id = parts.next()?.to_string();
+
+    Some(Beat {
+        node_id,
+        instance_id,
+        seq,
+    })
+}
+
+// the namespace is matched literally by `SCAN`
+fn escape_pattern(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len());
+
+    for c in prefix.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+
+    pattern
+}
+
+fn bulk_string(reply: Reply) -> io::Result<String> {
+    match reply {
+        Reply::Bulk(Some(bytes)) => String::from_utf8(bytes).map_err(invalid_data),
+        reply => Err(unexpected(&reply)),
+    }
+}
+
+fn expect_ok(reply: Reply) -> io::Result<()> {
+    match reply {
+        Reply::Status(_) => Ok(()),
+        Reply::Error(message) => Err(io::Error::new(io::ErrorKind::PermissionDenied, message)),
+        reply => Err(unexpected(&reply)),
+    }
+}
+
+fn unexpected(reply: &Reply) -> io::Error {
+    invalid_data(format!("unexpected reply {:?}", reply))
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigBuilder, Registry};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn replies_are_parsed_once_complete() {
+        let cases: Vec<(&[u8], Reply)> = vec![
+            (b"+OK\r\n", Reply::Status("OK".to_string())),
+            (b"-ERR wrong\r\n", Reply::Error("ERR wrong".to_string())),
+            (b":42\r\n", Reply::Integer(42)),
+            (b"$5\r\nhe\r\no\r\n", Reply::Bulk(Some(b"he\r\no".to_vec()))),
+            (b"$-1\r\n", Reply::Bulk(None)),
+            (
+                b"*2\r\n$1\r\n0\r\n*1\r\n$3\r\nkey\r\n",
+                Reply::Array(Some(vec![
+                    Reply::Bulk(Some(b"0".to_vec())),
+                    Reply::Array(Some(vec![Reply::Bulk(Some(b"key".to_vec()))])),
+                ])),
+            ),
+        ];
+
+        for (bytes, expected) in cases {
+            assert_eq!(
+                parse_reply(bytes).unwrap(),
+                Some((expected, bytes.len())),
+                "{:?}",
+                String::from_utf8_lossy(bytes)
+            );
+
+            for partial in 0..bytes.len() {
+                assert_eq!(parse_reply(&bytes[..partial]).unwrap(), None);
+            }
+        }
+
+        assert!(parse_reply(b"!oops\r\n").is_err());
+    }
+
+    #[test]
+    fn commands_are_arrays_of_bulk_strings() {
+        assert_eq!(
+            encode_command(&[b"GET", b"MOL-DSCVR-INFO:node-1"]),
+            b"*2\r\n$3\r\nGET\r\n$21\r\nMOL-DSCVR-INFO:node-1\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn beat_keys_hold_the_node_instance_and_seq() {
+        assert_eq!(
+            parse_beat_key("MOL-DSCVR-BEAT:", "MOL-DSCVR-BEAT:node|1|abc-123|7"),
+            Some(Beat {
+                node_id: "node|1".to_string(),
+                instance_id: "abc-123".to_string(),
+                seq: 7,
+            })
+        );
+        assert_eq!(
+            parse_beat_key("MOL-DSCVR-BEAT:", "MOL-DSCVR-BEAT:node-1|abc"),
+            None
+        );
+        assert_eq!(escape_pattern("MOL-a[1]-DSCVR"), "MOL-a\\[1\\]-DSCVR");
+    }
+
+    // a Redis server keeping its keys in memory, keys don't expire
+    async fn fake_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://:secret@{}/2", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut keys: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+            let mut buffer = vec![];
+
+            loop {
+                let command = match parse_reply(&buffer).unwrap() {
+                    Some((Reply::Array(Some(args)), length)) => {
+                        buffer.drain(..length);
+                        args.into_iter()
+                            .map(|arg| match arg {
+                                Reply::Bulk(Some(arg)) => arg,
+                                arg => panic!("unexpected argument {:?}", arg),
+                            })
+                            .collect::<Vec<_>>()
+                    }
+                    Some(reply) => panic!("unexpected command {:?}", reply),
+                    None => match keyspace::read_some(&stream, &mut buffer).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(_) => continue,
+                    },
+                };
+
+                let reply = match (command[0].as_slice(), &command[1..]) {
+                    (b"AUTH", [password]) if password == b"secret" => b"+OK\r\n".to_vec(),
+                    (b"SELECT", [database]) if database == b"2" => b"+OK\r\n".to_vec(),
+                    (b"SETEX", [key, _, value]) => {
+                        keys.insert(key.clone(), value.clone());
+                        b"+OK\r\n".to_vec()
+                    }
+                    (b"GET", [key]) => match keys.get(key) {
+                        Some(value) => {
+                            [format!("${}\r\n", value.len()).as_bytes(), value, b"\r\n"].concat()
+                        }
+                        None => b"$-1\r\n".to_vec(),
+                    },
+                    (b"DEL", deleted) => {
+                        let count = deleted
+                            .iter()
+                            .filter(|key| keys.remove(*key).is_some())
+                            .count();
+                        format!(":{}\r\n", count).into_bytes()
+                    }
+                    // one page, the pattern is a prefix
+                    (b"SCAN", [_, _, pattern, _, _]) => {
+                        let prefix = &pattern[..pattern.len() - 1];
+                        let matched: Vec<&[u8]> = keys
+                            .keys()
+                            .filter(|key| key.starts_with(prefix))
+                            .map(Vec::as_slice)
+                            .collect();
+                        let mut reply = b"*2\r\n$1\r\n0\r\n".to_vec();
+                        reply.extend_from_slice(&encode_command(&matched));
+                        reply
+                    }
+                    _ => b"-ERR unknown command\r\n".to_vec(),
+                };
+
+                keyspace::write_all(&stream, &reply).await.unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn heartbeats_and_info_go_to_the_moleculer_keys() {
+        let url = fake_server().await;
+        let config = Arc::new(
+            ConfigBuilder::default()
+                .node_id("node-1")
+                .namespace("prod")
+                .registry(Registry::redis(url))
+                .build(),
+        );
+        let registry = match &config.registry {
+            Registry::Redis(registry) => registry.clone(),
+            _ => unreachable!(),
+        };
+        let mut keyspace = RedisKeyspace::new(&config, &registry);
+        let beat = |seq| Beat {
+            node_id: "node-1".to_string(),
+            instance_id: config.instance_id.clone(),
+            seq,
+        };
+
+        keyspace.put_beat(1, Duration::from_secs(15)).await.unwrap();
+        keyspace
+            .put_info(b"{\"sender\":\"node-1\"}", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(keyspace.beats().await.unwrap(), vec![beat(1)]);
+        assert_eq!(
+            keyspace.info("node-1").await.unwrap().as_deref(),
+            Some(&b"{\"sender\":\"node-1\"}"[..])
+        );
+        assert_eq!(keyspace.info("node-2").await.unwrap(), None);
+
+        // the key of the previous seq is removed
+        keyspace.put_beat(2, Duration::from_secs(15)).await.unwrap();
+        assert_eq!(keyspace.beats().await.unwrap(), vec![beat(2)]);
+
+        keyspace.remove().await.unwrap();
+        assert_eq!(keyspace.beats().await.unwrap(), vec![]);
+        assert_eq!(keyspace.info("node-1").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,92 @@
+//! The `$node` service every node has in Moleculer JS, read by tools like the Moleculer REPL
+//! and Studio to inspect a node.
+
+use std::error::Error;
+
+use act_zero::*;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    errors::MoleculerError,
+    service::{ActionBuilder, Service},
+    ActionContext,
+};
+
+pub(crate) fn node_service() -> Service {
+    Service::new("$node")
+        .add_action(ActionBuilder::new("$node.list").add_callback(list).build())
+        .add_action(
+            ActionBuilder::new("$node.actions")
+                .add_callback(actions)
+                .build(),
+        )
+        .add_action(
+            ActionBuilder::new("$node.options")
+                .add_callback(options)
+                .build(),
+        )
+}
+
+// the callbacks run on the broker, they reply once it handled the query
+fn list(ctx: ActionContext) -> Result<(), Box<dyn Error>> {
+    let with_services = ctx
+        .params
+        .get("withServices")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    tokio::spawn(async move {
+        match call!(ctx.broker.addr.node_list(with_services)).await {
+            Ok(nodes) => ctx.reply(nodes),
+            Err(err) => ctx.reply_error(internal_error(err)),
+        }
+    });
+
+    Ok(())
+}
+
+/// Params of `$node.actions`, the options of `registry.getActionList()` in Moleculer JS
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct ActionListOptions {
+    /// Only the actions this node has
+    pub(crate) only_local: bool,
+    /// Only the actions at least one available node has
+    pub(crate) only_available: bool,
+    /// Leave out the actions of the internal `$` services, ex: `$node.list`
+    pub(crate) skip_internal: bool,
+    /// Add the nodes that have each action
+    pub(crate) with_endpoints: bool,
+    /// Only the actions an API gateway exposes, see [Visibility][crate::service::Visibility].
+    /// Not an option of Moleculer JS, where gateways check the `visibility` of each action
+    pub(crate) only_published: bool,
+}
+
+fn actions(ctx: ActionContext) -> Result<(), Box<dyn Error>> {
+    let options: ActionListOptions = serde_json::from_value(ctx.params.clone())?;
+
+    tokio::spawn(async move {
+        match call!(ctx.broker.addr.node_actions(options)).await {
+            Ok(actions) => ctx.reply(actions),
+            Err(err) => ctx.reply_error(internal_error(err)),
+        }
+    });
+
+    Ok(())
+}
+
+fn options(ctx: ActionContext) -> Result<(), Box<dyn Error>> {
+    tokio::spawn(async move {
+        match call!(ctx.broker.addr.node_options()).await {
+            Ok(options) => ctx.reply(options),
+            Err(err) => ctx.reply_error(internal_error(err)),
+        }
+    });
+
+    Ok(())
+}
+
+fn internal_error(err: impl std::fmt::Display) -> MoleculerError {
+    MoleculerError::new("MoleculerError", err.to_string(), 500, "")
+}
@@ -2,38 +2,62 @@ use crate::qset;
 use maplit::hashset;
 use std::{
     collections::{HashMap, HashSet},
-    time::{Duration, Instant},
+    sync::Arc,
+    time::Duration,
 };
 
 use crate::{
-    channels::messages::incoming::{Client, HeartbeatMessage, InfoMessage},
+    channels::messages::incoming::{metadata_weight, Client, PacketHeartbeat, PacketInfo},
+    config::{MetadataMerge, Serializer, Strategy},
     data_structures::QueueSet,
+    service::Service,
+    NodeDiff, RegistryChange,
 };
 
-use act_zero::runtimes::tokio::spawn_actor;
-use act_zero::runtimes::tokio::Timer;
-use act_zero::timer::Tick;
 use act_zero::*;
-use async_trait::async_trait;
+use log::info;
+use serde_json::{json, Value};
 
-use super::ServiceBroker;
+use super::{
+    discoverer::{Discoverer, WatchedNode},
+    strategy::Balancer,
+    ServiceBroker,
+};
 
 pub(crate) type ActionName = String;
 pub(crate) type EventName = String;
 pub(crate) type NodeName = String;
+pub(crate) type GroupName = String;
 
 pub(crate) struct Registry {
     actions: HashMap<EventName, QueueSet<NodeName>>,
     events: HashMap<EventName, QueueSet<NodeName>>,
+    // the nodes of each group listening to an event, an emitted event goes to one node per group
+    event_groups: HashMap<EventName, HashMap<GroupName, QueueSet<NodeName>>>,
     nodes: HashMap<NodeName, Node>,
+    metadata_merge: MetadataMerge,
+    discoverer: Box<dyn Discoverer>,
+
+    action_balancer: Balancer,
+    event_balancer: Balancer,
 }
 
 impl Registry {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(
+        strategy: Strategy,
+        metadata_merge: MetadataMerge,
+        discoverer: Box<dyn Discoverer>,
+    ) -> Self {
         Self {
             actions: HashMap::new(),
             events: HashMap::new(),
+            event_groups: HashMap::new(),
             nodes: HashMap::new(),
+            metadata_merge,
+            discoverer,
+
+            action_balancer: Balancer::new(strategy.clone()),
+            event_balancer: Balancer::new(strategy),
         }
     }
 
@@ -43,35 +67,200 @@ impl Registry {
         Some(event_nodes.iter().cloned().collect())
     }
 
-    pub(crate) fn get_node_name_for_event(&mut self, event_name: &str) -> Option<NodeName> {
-        let event_nodes = self.events.get_mut(event_name)?;
-        event_nodes.get_round_robin()
+    /// The groups listening to the event on remote nodes
+    pub(crate) fn get_event_groups(&self, event_name: &str) -> Vec<GroupName> {
+        self.event_groups
+            .get(event_name)
+            .map(|groups| groups.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Picks a node for each group listening to the event, but `local_group` which is handled
+    /// by the listener on this node. A node picked for several groups is returned once, with
+    /// all the groups it handles
+    pub(crate) fn get_node_names_for_event(
+        &mut self,
+        event_name: &str,
+        params: &Value,
+        local_group: Option<&str>,
+    ) -> Vec<(NodeName, Vec<GroupName>)> {
+        let groups = match self.event_groups.get_mut(event_name) {
+            Some(groups) => groups,
+            None => return vec![],
+        };
+
+        let mut picked: Vec<(NodeName, Vec<GroupName>)> = vec![];
+
+        for (group, group_nodes) in groups.iter_mut() {
+            if local_group == Some(group.as_str()) {
+                continue;
+            }
+
+            let node_name =
+                match self
+                    .event_balancer
+                    .select(event_name, params, group_nodes, &self.nodes)
+                {
+                    Some(node_name) => node_name,
+                    None => continue,
+                };
+
+            match picked.iter_mut().find(|(picked, _)| *picked == node_name) {
+                Some((_, node_groups)) => node_groups.push(group.clone()),
+                None => picked.push((node_name, vec![group.clone()])),
+            }
+        }
+
+        picked
+    }
+
+    pub(crate) fn has_node(&self, node_name: &str) -> bool {
+        self.nodes.contains_key(node_name)
+    }
+
+    pub(crate) fn node_has_action(&self, node_name: &str, action_name: &str) -> bool {
+        self.nodes
+            .get(node_name)
+            .is_some_and(|node| node.actions.contains(action_name))
     }
 
-    pub(crate) fn get_node_name_for_action(&mut self, action_name: &str) -> Option<NodeName> {
+    /// Serializers advertised by the node in its last `INFO` packet
+    pub(crate) fn get_node_serializers(&self, node_name: &str) -> Option<&[Serializer]> {
+        self.nodes
+            .get(node_name)
+            .map(|node| node.serializers.as_slice())
+    }
+
+    pub(crate) fn has_action(&self, action_name: &str) -> bool {
+        self.actions.contains_key(action_name)
+    }
+
+    /// Names of the actions available on any node, this one included
+    pub(crate) fn get_action_names(&self) -> impl Iterator<Item = &str> {
+        self.actions.keys().map(String::as_str)
+    }
+
+    pub(crate) fn get_node_name_for_action(
+        &mut self,
+        action_name: &str,
+        params: &Value,
+    ) -> Option<NodeName> {
         let action_nodes = self.actions.get_mut(action_name)?;
-        action_nodes.get_round_robin()
+        self.action_balancer
+            .select(action_name, params, action_nodes, &self.nodes)
+    }
+
+    /// Tracks the liveness of the nodes, see [config::Registry][crate::config::Registry]
+    pub(crate) fn discoverer(&self) -> &dyn Discoverer {
+        self.discoverer.as_ref()
     }
 
     pub(crate) fn add_or_update_node(
         &mut self,
         broker: Addr<ServiceBroker>,
-        heartbeat_timeout: u32,
-        info: InfoMessage,
-    ) {
+        info: PacketInfo,
+    ) -> Vec<RegistryChange> {
+        let node_id = info.envelope.sender.clone();
+        let mut changes = vec![];
+
+        if let Some(node) = self.nodes.get(&node_id) {
+            if node.instance_id != info.instance_id {
+                // a restarted node lost its state, its entry is replaced instead of merged
+                changes = self.reset_restarted_node(&node_id);
+            } else if info.seq < node.seq {
+                // INFO packets can arrive out of order, an older one would revert the node's
+                // services
+                return vec![];
+            }
+        }
+
         // get or insert node from/into registry
-        let node: &mut Node = match self.nodes.get_mut(&info.sender) {
-            Some(node) => node,
+        let node: &mut Node = match self.nodes.get_mut(&node_id) {
+            Some(node) => {
+                let metadata = merge_metadata(&node.metadata, &info.metadata, self.metadata_merge);
+                let diff = node_diff(node, &info, &metadata);
+                changes.push(RegistryChange::NodeUpdated { node_id, diff });
+
+                node.metadata = metadata;
+                node
+            }
             None => {
-                let node = Node::new(broker, heartbeat_timeout, &info);
+                let broker = broker.downgrade();
+                let watched = self.discoverer.watch(
+                    &node_id,
+                    Box::new(move |node_name| send!(broker.missed_heartbeat(node_name))),
+                );
+                let node = Node::new(watched, &info);
+
+                self.nodes.insert(node_id.clone(), node);
+                changes.push(RegistryChange::NodeConnected { node_id });
 
-                self.nodes.insert(info.sender.clone(), node);
                 self.nodes
-                    .get_mut(&info.sender)
+                    .get_mut(&info.envelope.sender)
                     .expect("present because just added the node")
             }
         };
 
+        node.serializers = info.serializers();
+        node.weight = metadata_weight(&node.metadata);
+        node.instance_id = info.instance_id.clone();
+        node.seq = info.seq;
+
+        let service_names: HashSet<String> = info
+            .services
+            .iter()
+            .map(|service| service.name.clone())
+            .collect();
+
+        for service in node.services.difference(&service_names) {
+            changes.push(RegistryChange::ServiceRemoved {
+                node_id: node.name.clone(),
+                service: service.clone(),
+            });
+        }
+
+        for service in service_names.difference(&node.services) {
+            changes.push(RegistryChange::ServiceAdded {
+                node_id: node.name.clone(),
+                service: service.clone(),
+            });
+        }
+
+        node.services = service_names;
+        node.service_definitions = info.services.clone();
+
+        // actions and events of services the node removed (or stopped advertising) are no longer
+        // routed to it
+        let event_names: HashSet<&EventName> = info
+            .services
+            .iter()
+            .flat_map(|service| service.events.keys())
+            .collect();
+        let action_names: HashSet<&ActionName> = remote_action_names(&info).collect();
+
+        // the groups are added back below, a listener may have moved to another group
+        for event_name in &node.events {
+            remove_node_from_groups(&mut self.event_groups, event_name, &node.name);
+        }
+
+        for event_name in node
+            .events
+            .iter()
+            .filter(|name| !event_names.contains(name))
+        {
+            remove_node_from(&mut self.events, event_name, &node.name);
+        }
+        node.events.retain(|name| event_names.contains(name));
+
+        for action_name in node
+            .actions
+            .iter()
+            .filter(|name| !action_names.contains(name))
+        {
+            remove_node_from(&mut self.actions, action_name, &node.name);
+        }
+        node.actions.retain(|name| action_names.contains(name));
+
         // get event_names  from info message
         let event_names = info
             .services
@@ -95,13 +284,21 @@ impl Registry {
             node.events.insert(event_name.clone());
         }
 
-        // get action_names from info message
-        let action_names = info
-            .services
-            .iter()
-            .flat_map(|service| service.actions.keys());
+        for service in &info.services {
+            for (event_name, event) in &service.events {
+                let group = event.group.as_ref().unwrap_or(&service.name);
 
-        for action_name in action_names {
+                self.event_groups
+                    .entry(event_name.clone())
+                    .or_default()
+                    .entry(group.clone())
+                    .or_insert_with(|| qset![])
+                    .insert(node.name.clone());
+            }
+        }
+
+        // get action_names from info message
+        for action_name in remote_action_names(&info) {
             match self.actions.get_mut(action_name) {
                 // action present from another node, add node_name to action's node_names set
                 Some(node_names) => {
@@ -118,153 +315,574 @@ impl Registry {
             // insert action into node's actions set
             node.actions.insert(action_name.clone());
         }
-    }
 
-    pub(crate) fn remove_node(&mut self, node_name: NodeName) -> Option<()> {
-        let node = self.nodes.remove(&node_name)?;
+        changes
+    }
 
-        for event_name in node.events {
-            let node_names_left_for_event = self
-                .events
-                .get_mut(&event_name)
-                // go through the node's events and remove node from each event
-                .map(|node_names| {
-                    node_names.remove(&node_name);
-                    node_names
-                });
+    pub(crate) fn remove_node(
+        &mut self,
+        node_name: NodeName,
+        unexpected: bool,
+    ) -> Vec<RegistryChange> {
+        let node = match self.nodes.remove(&node_name) {
+            Some(node) => node,
+            None => return vec![],
+        };
 
-            // if the event doesn't have any associated nodes remove the event entirely
-            if let Some(0) = node_names_left_for_event.map(|node_names| node_names.len()) {
-                self.events.remove(&event_name);
-            }
+        let mut changes: Vec<RegistryChange> = node
+            .services
+            .iter()
+            .map(|service| RegistryChange::ServiceRemoved {
+                node_id: node_name.clone(),
+                service: service.clone(),
+            })
+            .collect();
+
+        changes.push(RegistryChange::NodeDisconnected {
+            node_id: node_name.clone(),
+            unexpected,
+        });
+
+        self.action_balancer.remove_node(&node_name);
+        self.event_balancer.remove_node(&node_name);
+
+        for event_name in &node.events {
+            remove_node_from(&mut self.events, event_name, &node_name);
+            remove_node_from_groups(&mut self.event_groups, event_name, &node_name);
         }
 
-        for action_name in node.actions {
-            let node_names_left_for_action = self
-                .actions
-                .get_mut(&action_name)
-                // go through the node's actions and remove node from each action
-                .map(|node_names| {
-                    node_names.remove(&node_name);
-                    node_names
-                });
-
-            // if the action doesn't have any associated nodes remove the action entirely
-            if let Some(0) = node_names_left_for_action.map(|node_names| node_names.len()) {
-                self.actions.remove(&action_name);
-            }
+        for action_name in &node.actions {
+            remove_node_from(&mut self.actions, action_name, &node_name);
         }
 
-        Some(())
+        changes
+    }
+
+    /// Removes a node that restarted, seen by a new instance id in its `INFO` or `HEARTBEAT`.
+    /// It is added back from its next `INFO` packet with a fresh state
+    pub(crate) fn reset_restarted_node(&mut self, node_name: &str) -> Vec<RegistryChange> {
+        info!("Node {} restarted, resetting its registry entry", node_name);
+
+        // the node didn't send a DISCONNECT for its previous instance
+        self.remove_node(node_name.to_string(), true)
+    }
+
+    /// Whether the node sent a different instance id in its last `INFO` packet, the node
+    /// restarted since
+    pub(crate) fn is_new_instance(&self, node_name: &str, instance_id: &str) -> bool {
+        self.nodes
+            .get(node_name)
+            .is_some_and(|node| node.instance_id != instance_id)
     }
 
-    pub(crate) fn update_node(&mut self, heartbeat: HeartbeatMessage) -> Option<()> {
-        let node = self.nodes.get_mut(&heartbeat.sender)?;
-        node.cpu = Some(heartbeat.cpu);
+    /// Services of every remote node, as sent in their last `INFO` packet
+    pub(crate) fn get_node_services(&self) -> impl Iterator<Item = (&NodeName, &Service)> {
+        self.nodes.values().flat_map(|node| {
+            node.service_definitions
+                .iter()
+                .map(move |service| (&node.name, service))
+        })
+    }
+
+    pub(crate) fn get_node(&self, node_name: &str) -> Option<&Node> {
+        self.nodes.get(node_name)
+    }
+
+    /// Remote nodes, only the ones still connected are kept
+    pub(crate) fn get_nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.values()
+    }
+
+    /// Sequence number of the node's last `INFO` packet
+    pub(crate) fn get_node_seq(&self, node_name: &str) -> Option<u64> {
+        self.nodes.get(node_name).map(|node| node.seq)
+    }
+
+    pub(crate) fn update_node(&mut self, heartbeat: PacketHeartbeat) -> Option<()> {
+        let node = self.nodes.get_mut(&heartbeat.envelope.sender)?;
+        node.cpu = heartbeat.cpu;
+        node.memory = heartbeat.memory;
+        node.load = heartbeat.load;
+
+        node.watched.heartbeat();
+
+        Some(())
+    }
 
-        send!(node.node_watcher_pid.received_heartbeat());
+    /// Adds a latency sample to the node's moving average, `smoothing` is the weight of the
+    /// new sample
+    pub(crate) fn record_latency(
+        &mut self,
+        node_name: &str,
+        latency: Duration,
+        smoothing: f64,
+    ) -> Option<()> {
+        let node = self.nodes.get_mut(node_name)?;
+
+        node.latency = Some(match node.latency {
+            Some(average) => Duration::from_secs_f64(
+                average.as_secs_f64() + smoothing * (latency.as_secs_f64() - average.as_secs_f64()),
+            ),
+            None => latency,
+        });
 
         Some(())
     }
+
+    pub(crate) fn get_node_latencies(&self) -> HashMap<NodeName, Duration> {
+        self.nodes
+            .values()
+            .filter_map(|node| Some((node.name.clone(), node.latency?)))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct Node {
-    node_watcher_pid: Addr<NodeWatcher>,
+    watched: Arc<dyn WatchedNode>,
 
     pub(crate) name: NodeName,
     pub(crate) cpu: Option<f32>,
+    pub(crate) memory: Option<f32>,
+    pub(crate) load: Option<f64>,
     pub(crate) ip_list: Vec<String>,
     pub(crate) hostname: String,
     pub(crate) client: Client,
+    pub(crate) metadata: Value,
     pub(crate) instance_id: String,
+    pub(crate) seq: u64,
+    pub(crate) serializers: Vec<Serializer>,
+    pub(crate) weight: u32,
+    pub(crate) latency: Option<Duration>,
+    pub(crate) services: HashSet<String>,
+    pub(crate) service_definitions: Vec<Service>,
     pub(crate) events: HashSet<EventName>,
     pub(crate) actions: HashSet<ActionName>,
 }
 
 impl Node {
-    fn new(broker: Addr<ServiceBroker>, heartbeat_timeout: u32, info: &InfoMessage) -> Self {
-        let node_watcher =
-            NodeWatcher::new(info.sender.clone(), heartbeat_timeout, broker.downgrade());
+    /// The node in the shape of an entry of `$node.list` in Moleculer JS
+    pub(crate) fn to_json(&self, with_services: bool) -> Value {
+        let mut node = json!({
+            "id": self.name,
+            "instanceID": self.instance_id,
+            "available": true,
+            "local": false,
+            "client": self.client,
+            "ipList": self.ip_list,
+            "hostname": self.hostname,
+            "metadata": self.metadata,
+            "seq": self.seq,
+            "cpu": self.cpu,
+        });
+
+        if with_services {
+            node["services"] = json!(self.service_definitions);
+        }
+
+        node
+    }
 
+    fn new(watched: Arc<dyn WatchedNode>, info: &PacketInfo) -> Self {
         Self {
-            node_watcher_pid: spawn_actor(node_watcher),
-            name: info.sender.clone(),
+            watched,
+            name: info.envelope.sender.clone(),
             cpu: None,
+            memory: None,
+            load: None,
             ip_list: info.ip_list.clone(),
             hostname: info.hostname.clone(),
             client: info.client.clone(),
+            metadata: info.metadata.clone(),
             instance_id: info.instance_id.clone(),
+            seq: info.seq,
+            serializers: info.serializers(),
+            weight: info.weight(),
+            latency: None,
+            services: hashset![],
+            service_definitions: vec![],
             events: hashset![],
             actions: hashset![],
         }
     }
 }
 
-#[async_trait]
-impl Actor for NodeWatcher {
-    async fn started(&mut self, pid: Addr<Self>) -> ActorResult<()> {
-        self.pid = pid.downgrade();
-
-        // // Start the timer
-        self.timer.set_timeout_for_weak(
-            self.pid.clone(),
-            Duration::from_secs(self.heartbeat_timeout as u64),
-        );
+fn merge_metadata(stored: &Value, received: &Value, metadata_merge: MetadataMerge) -> Value {
+    match (metadata_merge, stored, received) {
+        (MetadataMerge::Merge, Value::Object(stored), Value::Object(received)) => {
+            let mut metadata = stored.clone();
+            metadata.extend(received.clone());
+            Value::Object(metadata)
+        }
+        _ => received.clone(),
+    }
+}
 
-        Produces::ok(())
+// compared before the node is updated from its new INFO, `metadata` is its updated metadata
+fn node_diff(node: &Node, info: &PacketInfo, metadata: &Value) -> NodeDiff {
+    let actions: HashSet<&ActionName> = info
+        .services
+        .iter()
+        .flat_map(|service| service.actions.keys())
+        .collect();
+    let events: HashSet<&EventName> = info
+        .services
+        .iter()
+        .flat_map(|service| service.events.keys())
+        .collect();
+
+    let sorted = |names: Vec<&String>| {
+        let mut names: Vec<String> = names.into_iter().cloned().collect();
+        names.sort();
+        names
+    };
+
+    let empty = serde_json::Map::new();
+    let old_metadata = node.metadata.as_object().unwrap_or(&empty);
+    let new_metadata = metadata.as_object().unwrap_or(&empty);
+
+    NodeDiff {
+        added_actions: sorted(
+            actions
+                .iter()
+                .filter(|name| !node.actions.contains(**name))
+                .copied()
+                .collect(),
+        ),
+        removed_actions: sorted(
+            node.actions
+                .iter()
+                .filter(|name| !actions.contains(name))
+                .collect(),
+        ),
+        added_events: sorted(
+            events
+                .iter()
+                .filter(|name| !node.events.contains(**name))
+                .copied()
+                .collect(),
+        ),
+        removed_events: sorted(
+            node.events
+                .iter()
+                .filter(|name| !events.contains(name))
+                .collect(),
+        ),
+        changed_metadata: new_metadata
+            .iter()
+            .filter(|(key, value)| old_metadata.get(*key) != Some(value))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        removed_metadata: sorted(
+            old_metadata
+                .keys()
+                .filter(|key| !new_metadata.contains_key(*key))
+                .collect(),
+        ),
     }
 }
 
-#[async_trait]
-impl Tick for NodeWatcher {
-    async fn tick(&mut self) -> ActorResult<()> {
-        if self.timer.tick() {
-            let now = Instant::now();
-
-            if now.duration_since(self.last_heartbeat).as_secs() >= self.heartbeat_timeout as u64 {
-                // haven't received a heartbeat recently
-                send!(self.broker.missed_heartbeat(self.node_name.clone()))
-            } else {
-                // reschedule timer
-                self.timer.set_timeout_for_weak(
-                    self.pid.clone(),
-                    Duration::from_secs(self.heartbeat_timeout as u64),
-                );
-            }
+// removes the node from the nodes of an event or action,
+// and the event or action entirely if it doesn't have any nodes left
+fn remove_node_from(
+    names: &mut HashMap<String, QueueSet<NodeName>>,
+    name: &str,
+    node_name: &NodeName,
+) {
+    if let Some(node_names) = names.get_mut(name) {
+        node_names.remove(node_name);
+
+        if node_names.len() == 0 {
+            names.remove(name);
         }
-        Produces::ok(())
     }
 }
 
-struct NodeWatcher {
-    node_name: NodeName,
-    pid: WeakAddr<Self>,
-    broker: WeakAddr<ServiceBroker>,
-    timer: Timer,
+// removes the node from the groups of an event, and the groups and event left without nodes
+fn remove_node_from_groups(
+    event_groups: &mut HashMap<EventName, HashMap<GroupName, QueueSet<NodeName>>>,
+    event_name: &str,
+    node_name: &NodeName,
+) {
+    if let Some(groups) = event_groups.get_mut(event_name) {
+        groups.retain(|_, node_names| {
+            node_names.remove(node_name);
+            node_names.len() > 0
+        });
+
+        if groups.is_empty() {
+            event_groups.remove(event_name);
+        }
+    }
+}
 
-    heartbeat_timeout: u32,
-    last_heartbeat: Instant,
+// the actions of a node other nodes can call, its protected ones are only called by itself
+fn remote_action_names(info: &PacketInfo) -> impl Iterator<Item = &ActionName> {
+    info.services.iter().flat_map(|service| {
+        service
+            .actions
+            .iter()
+            .filter(|(_, action)| action.visibility.is_remote())
+            .map(|(name, _)| name)
+    })
 }
 
-impl NodeWatcher {
-    pub(crate) fn new(
-        name: NodeName,
-        heartbeat_timeout: u32,
-        broker: WeakAddr<ServiceBroker>,
-    ) -> Self {
-        Self {
-            node_name: name,
-            pid: WeakAddr::detached(),
-            broker,
-            timer: Timer::default(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        broker::discoverer::LocalDiscoverer,
+        channels::parse_packet,
+        clock::{Clock, ManualClock},
+        config::{Channel, ConfigBuilder},
+    };
+
+    fn registry() -> Registry {
+        let clock: Arc<dyn Clock> = Arc::new(ManualClock::new());
+        let discoverer = LocalDiscoverer::new(Duration::from_secs(15), &clock);
+
+        Registry::new(
+            Strategy::default(),
+            MetadataMerge::default(),
+            Box::new(discoverer),
+        )
+    }
 
-            heartbeat_timeout,
-            last_heartbeat: Instant::now(),
-        }
+    fn info(sender: &str, instance_id: &str, seq: u64, actions: &[&str]) -> PacketInfo {
+        let actions: serde_json::Map<String, Value> = actions
+            .iter()
+            .map(|action| (action.to_string(), json!({ "name": action })))
+            .collect();
+
+        serde_json::from_value(json!({
+            "ver": "4",
+            "sender": sender,
+            "instanceID": instance_id,
+            "seq": seq,
+            "services": [{ "name": "users", "actions": actions, "events": {} }],
+        }))
+        .unwrap()
+    }
+
+    // a node with a service listening to `user.created`
+    fn listener(sender: &str, service: &str, event: Value) -> PacketInfo {
+        serde_json::from_value(json!({
+            "ver": "4",
+            "sender": sender,
+            "instanceID": sender,
+            "services": [{ "name": service, "actions": {}, "events": { "user.created": event } }],
+        }))
+        .unwrap()
+    }
+
+    fn add(registry: &mut Registry, info: PacketInfo) -> Vec<RegistryChange> {
+        registry.add_or_update_node(Addr::detached(), info)
+    }
+
+    #[tokio::test]
+    async fn emitted_event_is_delivered_once_to_a_group_with_a_local_listener() {
+        let mut registry = registry();
+        let event = json!({ "name": "user.created" });
+        add(&mut registry, listener("node-2", "users", event));
+
+        // the listener on this node is in the same group as the remote one
+        let node_names =
+            registry.get_node_names_for_event("user.created", &json!({}), Some("users"));
+        assert!(node_names.is_empty());
+
+        let node_names = registry.get_node_names_for_event("user.created", &json!({}), None);
+        assert_eq!(
+            node_names,
+            vec![("node-2".to_string(), vec!["users".to_string()])]
+        );
+    }
+
+    #[tokio::test]
+    async fn emitted_event_is_delivered_to_one_node_per_group() {
+        let mut registry = registry();
+        let event = json!({ "name": "user.created" });
+        add(&mut registry, listener("node-2", "users", event.clone()));
+        add(&mut registry, listener("node-3", "users", event.clone()));
+        add(&mut registry, listener("node-4", "mail", event));
+
+        let node_names =
+            registry.get_node_names_for_event("user.created", &json!({}), Some("users"));
+        assert_eq!(
+            node_names,
+            vec![("node-4".to_string(), vec!["mail".to_string()])]
+        );
+
+        let mut groups: Vec<Vec<GroupName>> = registry
+            .get_node_names_for_event("user.created", &json!({}), None)
+            .into_iter()
+            .map(|(_, groups)| groups)
+            .collect();
+        groups.sort();
+        assert_eq!(
+            groups,
+            vec![vec!["mail".to_string()], vec!["users".to_string()]]
+        );
+    }
+
+    #[tokio::test]
+    async fn event_group_advertised_by_a_node_replaces_its_service_name() {
+        let mut registry = registry();
+        let event = json!({ "name": "user.created", "group": "audit" });
+        add(&mut registry, listener("node-2", "users", event));
+
+        let node_names =
+            registry.get_node_names_for_event("user.created", &json!({}), Some("users"));
+        assert_eq!(
+            node_names,
+            vec![("node-2".to_string(), vec!["audit".to_string()])]
+        );
+
+        registry.remove_node("node-2".to_string(), false);
+        assert!(registry
+            .get_node_names_for_event("user.created", &json!({}), None)
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn older_info_is_ignored() {
+        let mut registry = registry();
+
+        add(
+            &mut registry,
+            info("node-1", "a", 2, &["users.get", "users.list"]),
+        );
+        let changes = add(&mut registry, info("node-1", "a", 1, &["users.get"]));
+
+        assert!(changes.is_empty());
+        assert_eq!(registry.get_node_seq("node-1"), Some(2));
+        assert!(registry.node_has_action("node-1", "users.list"));
+    }
+
+    #[tokio::test]
+    async fn newer_info_updates_the_node() {
+        let mut registry = registry();
+
+        add(
+            &mut registry,
+            info("node-1", "a", 1, &["users.get", "users.list"]),
+        );
+        add(&mut registry, info("node-1", "a", 2, &["users.get"]));
+
+        assert_eq!(registry.get_node_seq("node-1"), Some(2));
+        assert!(registry.node_has_action("node-1", "users.get"));
+        assert!(!registry.node_has_action("node-1", "users.list"));
+        assert!(!registry.has_action("users.list"));
+    }
+
+    #[tokio::test]
+    async fn restarted_node_is_reset_instead_of_merged() {
+        let mut registry = registry();
+
+        add(
+            &mut registry,
+            info("node-1", "a", 5, &["users.get", "users.list"]),
+        );
+
+        assert!(!registry.is_new_instance("node-1", "a"));
+        assert!(registry.is_new_instance("node-1", "b"));
+
+        // the restarted node starts counting again, its lower seq isn't ignored
+        let changes = add(&mut registry, info("node-1", "b", 1, &["users.get"]));
+
+        assert_eq!(
+            changes,
+            vec![
+                RegistryChange::ServiceRemoved {
+                    node_id: "node-1".to_string(),
+                    service: "users".to_string(),
+                },
+                RegistryChange::NodeDisconnected {
+                    node_id: "node-1".to_string(),
+                    unexpected: true,
+                },
+                RegistryChange::NodeConnected {
+                    node_id: "node-1".to_string(),
+                },
+                RegistryChange::ServiceAdded {
+                    node_id: "node-1".to_string(),
+                    service: "users".to_string(),
+                },
+            ]
+        );
+        assert_eq!(registry.get_node_seq("node-1"), Some(1));
+        assert!(!registry.is_new_instance("node-1", "b"));
+        assert!(registry.node_has_action("node-1", "users.get"));
+        assert!(!registry.has_action("users.list"));
+    }
+
+    #[tokio::test]
+    async fn multi_segment_event_names_route_like_any_other() {
+        let mut registry = registry();
+        let info = serde_json::from_value(json!({
+            "ver": "4",
+            "sender": "node-2",
+            "services": [{
+                "name": "users",
+                "actions": {},
+                "events": {
+                    "user.profile.updated": { "name": "user.profile.updated" },
+                    "user.profile": { "name": "user.profile" },
+                },
+            }],
+        }))
+        .unwrap();
+        add(&mut registry, info);
+
+        let node_names =
+            registry.get_node_names_for_event("user.profile.updated", &json!({}), None);
+        assert_eq!(
+            node_names,
+            vec![("node-2".to_string(), vec!["users".to_string()])]
+        );
+        assert!(registry
+            .get_node_names_for_event("user.profile.*", &json!({}), None)
+            .is_empty());
+        assert!(registry
+            .get_node_names_for_event("user", &json!({}), None)
+            .is_empty());
+        assert_eq!(
+            registry.get_event_groups("user.profile"),
+            vec!["users".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn minimal_info_without_serializer_registers_with_json() {
+        let config = ConfigBuilder::default().build();
+        let packet = br#"{"ver":"4","sender":"node-js","seq":1,"futureField":{"a":1}}"#;
+        let info: PacketInfo = parse_packet(&config, &Channel::Info, packet).unwrap();
+
+        let mut registry = registry();
+        add(&mut registry, info);
+
+        assert!(registry.has_node("node-js"));
+        assert_eq!(
+            registry.get_node_serializers("node-js"),
+            Some(&[Serializer::JSON][..])
+        );
     }
 
-    pub(crate) async fn received_heartbeat(&mut self) {
-        self.last_heartbeat = Instant::now()
+    #[tokio::test]
+    async fn unknown_or_single_serializers_fall_back_to_json() {
+        let unknown: PacketInfo = serde_json::from_value(json!({
+            "ver": "4",
+            "sender": "node-2",
+            "config": { "serializers": ["Avro"] },
+        }))
+        .unwrap();
+        assert_eq!(unknown.serializers(), vec![Serializer::JSON]);
+
+        let single: PacketInfo = serde_json::from_value(json!({
+            "ver": "4",
+            "sender": "node-2",
+            "config": { "serializer": "JSON" },
+        }))
+        .unwrap();
+        assert_eq!(single.serializers(), vec![Serializer::JSON]);
     }
 }
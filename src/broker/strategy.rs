@@ -0,0 +1,201 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use rand::seq::IteratorRandom;
+use serde_json::Value;
+
+use crate::{
+    config::{LatencyOptions, ShardOptions, Strategy},
+    data_structures::QueueSet,
+    util::fnv1a,
+};
+
+use super::registry::{Node, NodeName};
+
+/// Picks which node handles an emitted event or a call, among the nodes that have it.
+pub(crate) struct Balancer {
+    strategy: Strategy,
+
+    // smooth weighted round robin state, per event or action name
+    current_weights: HashMap<String, HashMap<NodeName, i64>>,
+
+    // consistent hash rings, per event or action name
+    rings: HashMap<String, HashRing>,
+}
+
+impl Balancer {
+    pub(crate) fn new(strategy: Strategy) -> Self {
+        Self {
+            strategy,
+            current_weights: HashMap::new(),
+            rings: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn select(
+        &mut self,
+        name: &str,
+        params: &Value,
+        node_names: &mut QueueSet<NodeName>,
+        nodes: &HashMap<NodeName, Node>,
+    ) -> Option<NodeName> {
+        match &self.strategy {
+            Strategy::RoundRobin => node_names.get_round_robin(),
+            Strategy::WeightedRoundRobin => self.select_weighted(name, node_names, nodes),
+            Strategy::Latency(options) => select_latency(options, node_names, nodes),
+            Strategy::Shard(options) => match options.key(params) {
+                Some(key) => {
+                    let options = options.clone();
+                    self.select_shard(name, &options, key, node_names)
+                }
+                None => node_names.get_round_robin(),
+            },
+        }
+    }
+
+    pub(crate) fn remove_node(&mut self, node_name: &str) {
+        for current_weights in self.current_weights.values_mut() {
+            current_weights.remove(node_name);
+        }
+
+        self.rings
+            .retain(|_, ring| !ring.node_names.iter().any(|name| name == node_name));
+    }
+
+    fn select_shard(
+        &mut self,
+        name: &str,
+        options: &ShardOptions,
+        key: &Value,
+        node_names: &QueueSet<NodeName>,
+    ) -> Option<NodeName> {
+        let mut current_node_names: Vec<&NodeName> = node_names.iter().collect();
+        current_node_names.sort();
+
+        // the ring is only rebuilt when the nodes change
+        let ring = self
+            .rings
+            .entry(name.to_string())
+            .or_insert_with(|| HashRing::new(&[], options.vnodes));
+        if !ring
+            .node_names
+            .iter()
+            .eq(current_node_names.iter().copied())
+        {
+            *ring = HashRing::new(&current_node_names, options.vnodes);
+        }
+
+        let hash = match key {
+            Value::String(key) => fnv1a(key.as_bytes()),
+            key => fnv1a(key.to_string().as_bytes()),
+        };
+
+        ring.get(hash).cloned()
+    }
+
+    // every pick adds each node's weight to its current weight, the node with the highest
+    // current weight is picked and its current weight is lowered by the total weight.
+    // Nodes are picked proportionally to their weight and picks of heavy nodes are spread out
+    fn select_weighted(
+        &mut self,
+        name: &str,
+        node_names: &QueueSet<NodeName>,
+        nodes: &HashMap<NodeName, Node>,
+    ) -> Option<NodeName> {
+        let current_weights = match self.current_weights.get_mut(name) {
+            Some(current_weights) => current_weights,
+            None => self.current_weights.entry(name.to_string()).or_default(),
+        };
+
+        let mut total_weight = 0;
+        let mut selected: Option<(&NodeName, i64)> = None;
+
+        for node_name in node_names.iter() {
+            let weight = nodes.get(node_name).map_or(1, |node| node.weight) as i64;
+            total_weight += weight;
+
+            let current_weight = current_weights.entry(node_name.clone()).or_insert(0);
+            *current_weight += weight;
+
+            match selected {
+                Some((_, highest)) if highest >= *current_weight => {}
+                _ => selected = Some((node_name, *current_weight)),
+            }
+        }
+
+        let (node_name, _) = selected?;
+        if let Some(current_weight) = current_weights.get_mut(node_name) {
+            *current_weight -= total_weight;
+        }
+
+        Some(node_name.clone())
+    }
+}
+
+// compares a random subset of the nodes so callers don't all converge on the same node,
+// unmeasured nodes are picked straight away so they get measured
+fn select_latency(
+    options: &LatencyOptions,
+    node_names: &QueueSet<NodeName>,
+    nodes: &HashMap<NodeName, Node>,
+) -> Option<NodeName> {
+    let low_latency = Duration::from_millis(options.low_latency as u64);
+    let candidates = node_names.iter().choose_multiple(
+        &mut rand::thread_rng(),
+        options.collect_count.max(1) as usize,
+    );
+
+    let mut selected: Option<(&NodeName, Duration)> = None;
+
+    for node_name in candidates {
+        let latency = match nodes.get(node_name).and_then(|node| node.latency) {
+            Some(latency) if latency > low_latency => latency,
+            _ => return Some(node_name.clone()),
+        };
+
+        match selected {
+            Some((_, lowest)) if lowest <= latency => {}
+            _ => selected = Some((node_name, latency)),
+        }
+    }
+
+    selected.map(|(node_name, _)| node_name.clone())
+}
+
+struct HashRing {
+    node_names: Vec<NodeName>,
+    points: BTreeMap<u64, NodeName>,
+}
+
+impl HashRing {
+    // node names are sorted so colliding points go to the same node on every broker
+    fn new(node_names: &[&NodeName], vnodes: u32) -> Self {
+        let mut points = BTreeMap::new();
+
+        for node_name in node_names {
+            for vnode in 0..vnodes.max(1) {
+                let point = fnv1a(format!("{}-{}", node_name, vnode).as_bytes());
+                points.insert(point, (*node_name).clone());
+            }
+        }
+
+        Self {
+            node_names: node_names
+                .iter()
+                .map(|node_name| (*node_name).clone())
+                .collect(),
+            points,
+        }
+    }
+
+    // first point clockwise from the hash, wrapping around to the start of the ring
+    fn get(&self, hash: u64) -> Option<&NodeName> {
+        self.points
+            .range(hash..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, node_name)| node_name)
+    }
+}
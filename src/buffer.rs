@@ -0,0 +1,106 @@
+/*!
+Binary params and responses, ex: images or other blobs passed to an action.
+
+Params and responses are [serde_json::Value]s, bytes are put in them in the shape Node.js gives a
+`Buffer` in JSON, `{"type": "Buffer", "data": [...]}`: what a JS caller sends for a `Buffer`
+param, and what a JS handler turns back into one with `Buffer.from(ctx.params.image)`.
+
+```rust, ignore
+fn thumbnail(ctx: ActionContext) -> Result<(), Box<dyn Error>> {
+    let image = buffer::from_value(&ctx.params["image"]).ok_or("expected an image")?;
+
+    ctx.reply(json!({ "thumbnail": buffer::to_value(&resize(&image)) }));
+    Ok(())
+}
+```
+
+How the bytes travel depends on the [Serializer][crate::config::Serializer]:
+- `JSON`, the only serializer this crate has: each byte is a number of the `data` array, so a
+  payload takes up to 4 times its size. Large payloads should be streamed instead, see [stream].
+- Binary serializers (MessagePack, CBOR) that would carry the bytes as is aren't supported, the
+  nodes talking to this one use `JSON` for its packets.
+
+[stream]: crate::stream
+*/
+
+use serde_json::{json, Value};
+
+/// The bytes as a Node.js `Buffer` serialized to JSON
+pub fn to_value(bytes: &[u8]) -> Value {
+    json!({ "type": "Buffer", "data": bytes })
+}
+
+/// The bytes of a Node.js `Buffer` serialized to JSON, `None` if `value` isn't one
+pub fn from_value(value: &Value) -> Option<Vec<u8>> {
+    let buffer = value.as_object()?;
+
+    if buffer.get("type")? != "Buffer" {
+        return None;
+    }
+
+    buffer
+        .get("data")?
+        .as_array()?
+        .iter()
+        .map(|byte| byte.as_u64().filter(|byte| *byte <= u8::MAX as u64))
+        .map(|byte| byte.map(|byte| byte as u8))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_round_trip() {
+        let bytes: Vec<u8> = (0..=u8::MAX).collect();
+        let value = to_value(&bytes);
+
+        assert_eq!(value["type"], "Buffer");
+        assert_eq!(value["data"][255], 255);
+        assert_eq!(from_value(&value), Some(bytes));
+        assert_eq!(from_value(&to_value(&[])), Some(vec![]));
+    }
+
+    #[test]
+    fn bytes_round_trip_through_a_serialized_packet() {
+        let serialized = serde_json::to_vec(&json!({ "image": to_value(b"\x89PNG") })).unwrap();
+        let params: Value = serde_json::from_slice(&serialized).unwrap();
+
+        assert_eq!(from_value(&params["image"]), Some(b"\x89PNG".to_vec()));
+    }
+
+    #[test]
+    fn entries_that_arent_bytes_are_rejected() {
+        let entries = [
+            json!(256),
+            json!(-1),
+            json!(1.5),
+            json!("1"),
+            json!(null),
+            json!([1]),
+        ];
+
+        for entry in entries {
+            let value = json!({ "type": "Buffer", "data": [1, entry, 3] });
+            assert_eq!(from_value(&value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn values_that_arent_buffers_are_rejected() {
+        let values = [
+            json!({ "type": "Buffer" }),
+            json!({ "type": "Buffer", "data": "AQID" }),
+            json!({ "type": "Uint8Array", "data": [1, 2, 3] }),
+            json!({ "data": [1, 2, 3] }),
+            json!([1, 2, 3]),
+            json!("AQID"),
+            json!(null),
+        ];
+
+        for value in values {
+            assert_eq!(from_value(&value), None, "{}", value);
+        }
+    }
+}
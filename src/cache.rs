@@ -0,0 +1,173 @@
+/*!
+Cache keys of action results, to build a cache of the responses of an action.
+
+The crate has no cacher, [cache_key()] gives the key a response is stored under: the same
+action called with the same params gets the same key, on every node and across Rust versions,
+so it can be used with a cache shared between nodes (ex: Redis).
+
+Like `cache.keys` in Moleculer JS, the keys to use are listed in `keys`:
+- no keys: the whole params
+- a dotted path into the params, ex: `user.id` or `items.0.sku`
+- a dotted path into the meta prefixed with `#`, ex: `#tenant`
+
+Values are canonicalized before they are hashed, the fields of objects are sorted by name:
+`{"a": 1, "b": 2}` and `{"b": 2, "a": 1}` give the same key. A key missing from the params
+counts as `null`. The order of `keys` matters, list them in the same order on every node.
+
+```rust, ignore
+fn get_user(ctx: ActionContext) -> Result<(), Box<dyn Error>> {
+    let key = cache::cache_key("users.get", &ctx.params, &ctx.meta, &["id", "#tenant"]);
+
+    if let Some(user) = CACHE.get(&key) {
+        ctx.reply(user);
+        return Ok(());
+    }
+
+    let user = load_user(&ctx.params)?;
+    CACHE.insert(key, user.clone());
+    ctx.reply(user);
+
+    Ok(())
+}
+```
+*/
+
+use serde_json::Value;
+
+use crate::util;
+
+/// The key the response of `action` called with `params` and `meta` is cached under, only the
+/// values of `keys` are used if it isn't empty, see the [module docs][self].
+///
+/// Keys look like `users.get:5c2f1e8a9b7d3c41`: the action name and a 64 bits hash.
+pub fn cache_key<S: AsRef<str>>(action: &str, params: &Value, meta: &Value, keys: &[S]) -> String {
+    let mut canonical = String::new();
+
+    if keys.is_empty() {
+        write_canonical(params, &mut canonical);
+    } else {
+        canonical.push('[');
+
+        for (index, key) in keys.iter().enumerate() {
+            if index > 0 {
+                canonical.push(',');
+            }
+
+            let key = key.as_ref();
+            let value = match key.strip_prefix('#') {
+                Some(meta_key) => util::value_at_path(meta, meta_key),
+                None => util::value_at_path(params, key),
+            };
+
+            write_canonical(value.unwrap_or(&Value::Null), &mut canonical);
+        }
+
+        canonical.push(']');
+    }
+
+    format!("{}:{:016x}", action, util::fnv1a(canonical.as_bytes()))
+}
+
+// JSON with the fields of objects sorted by name, whatever the order of the map
+fn write_canonical(value: &Value, output: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut fields: Vec<(&String, &Value)> = map.iter().collect();
+            fields.sort_by_key(|(name, _)| *name);
+
+            output.push('{');
+            for (index, (name, value)) in fields.into_iter().enumerate() {
+                if index > 0 {
+                    output.push(',');
+                }
+
+                output.push_str(&Value::String(name.clone()).to_string());
+                output.push(':');
+                write_canonical(value, output);
+            }
+            output.push('}');
+        }
+        Value::Array(values) => {
+            output.push('[');
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    output.push(',');
+                }
+
+                write_canonical(value, output);
+            }
+            output.push(']');
+        }
+        value => output.push_str(&value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const NO_KEYS: &[&str] = &[];
+
+    fn parse(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn reordered_params_give_the_same_key() {
+        let params = parse(r#"{"id": 5, "filter": {"active": true, "roles": ["a", "b"]}}"#);
+        let reordered = parse(r#"{"filter": {"roles": ["a", "b"], "active": true}, "id": 5}"#);
+
+        assert_eq!(
+            cache_key("users.list", &params, &Value::Null, NO_KEYS),
+            cache_key("users.list", &reordered, &Value::Null, NO_KEYS)
+        );
+    }
+
+    #[test]
+    fn reordered_params_and_meta_give_the_same_key_with_keys() {
+        let keys = ["user", "#auth"];
+        let params = parse(r#"{"user": {"id": 5, "name": "a"}, "page": 1}"#);
+        let meta = parse(r#"{"auth": {"tenant": "t1", "role": "admin"}}"#);
+        let reordered_params = parse(r#"{"page": 2, "user": {"name": "a", "id": 5}}"#);
+        let reordered_meta = parse(r#"{"auth": {"role": "admin", "tenant": "t1"}}"#);
+
+        // `page` isn't a key
+        assert_eq!(
+            cache_key("users.get", &params, &meta, &keys),
+            cache_key("users.get", &reordered_params, &reordered_meta, &keys)
+        );
+    }
+
+    #[test]
+    fn different_values_give_different_keys() {
+        let keys = ["id", "#tenant"];
+        let params = json!({ "id": 5 });
+
+        let key = cache_key("users.get", &params, &json!({ "tenant": "t1" }), &keys);
+
+        assert_ne!(
+            key,
+            cache_key("users.get", &params, &json!({ "tenant": "t2" }), &keys)
+        );
+        assert_ne!(
+            key,
+            cache_key("users.find", &params, &json!({ "tenant": "t1" }), &keys)
+        );
+        assert_ne!(
+            cache_key("users.get", &json!({ "id": 5 }), &Value::Null, NO_KEYS),
+            cache_key("users.get", &json!({ "id": "5" }), &Value::Null, NO_KEYS)
+        );
+    }
+
+    #[test]
+    fn missing_keys_count_as_null() {
+        let keys = ["id"];
+
+        assert_eq!(
+            cache_key("users.get", &json!({}), &Value::Null, &keys),
+            cache_key("users.get", &json!({ "id": null }), &Value::Null, &keys)
+        );
+        assert!(cache_key("users.get", &json!({}), &Value::Null, &keys).starts_with("users.get:"));
+    }
+}
@@ -2,8 +2,10 @@ pub(crate) mod messages;
 
 mod event;
 
+mod balanced;
 mod disconnect;
 mod discover;
+mod durable;
 mod heartbeat;
 mod info;
 mod ping;
@@ -11,31 +13,42 @@ mod pong;
 mod request;
 mod response;
 
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use act_zero::runtimes::tokio::spawn_actor;
 use act_zero::*;
 use async_trait::async_trait;
-use log::{debug, error};
+use log::{debug, error, info, warn};
 use serde_json::Value;
 use thiserror::Error;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
 use tokio::sync::oneshot::Sender;
+use tokio::sync::Semaphore;
 
 use crate::{
     broker::ServiceBroker,
     config,
-    config::{Channel, Config, Transporter},
-    nats,
+    config::{Channel, Config, PacketDirection, Serializer, Transporter},
+    nats::{self, ConnectionEvent, Subscription},
+    service::DurableConsumer,
+    ConnectionState, PendingInfo,
 };
 
+use messages::incoming::Packet;
+
+pub(crate) use self::balanced::BalancedSubject;
+
 use self::{
+    balanced::Balanced,
     disconnect::Disconnect,
     discover::{Discover, DiscoverTargeted},
+    durable::{DurableEventConsumer, DurableEventPublisher},
     event::Event,
     heartbeat::Heartbeat,
     info::{Info, InfoTargeted},
-    messages::outgoing::DisconnectMessage,
+    messages::outgoing::PacketDisconnect,
     ping::{Ping, PingTargeted},
     pong::Pong,
     request::Request,
@@ -55,6 +68,168 @@ pub(crate) enum Error {
 
     #[error(transparent)]
     Serialize(#[from] config::SerializeError),
+
+    #[error("Received packet without a sender")]
+    MissingSender,
+
+    #[error("Transit middleware failed: {0}")]
+    TransitMiddleware(String),
+
+    #[error("Protocol version mismatch, node '{sender}' sent version '{version}'")]
+    ProtocolVersionMismatch { sender: String, version: String },
+}
+
+/// Count of inbound packets dropped per channel because they couldn't be parsed.
+#[derive(Debug, Default)]
+pub(crate) struct MalformedPackets(Mutex<HashMap<Channel, u64>>);
+
+impl MalformedPackets {
+    fn increment(&self, channel: &Channel) {
+        let mut counts = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *counts.entry(channel.clone()).or_default() += 1;
+    }
+
+    pub(crate) fn counts(&self) -> HashMap<Channel, u64> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+/// Parses `data` as a packet of the channel, and runs the helpers reading the parsed packet
+/// before it is handled. Used by the fuzz targets, see [crate::fuzzing]
+#[cfg(feature = "fuzzing")]
+pub(crate) fn fuzz_packet(config: &Config, channel: &Channel, data: &[u8]) {
+    use messages::incoming::*;
+
+    let _ = match channel {
+        Channel::Event => parse_packet::<PacketEvent>(config, channel, data).map(drop),
+        Channel::Request => parse_packet::<PacketRequest>(config, channel, data).map(drop),
+        Channel::Response => parse_packet::<PacketResponse>(config, channel, data).map(drop),
+        Channel::Discover | Channel::DiscoverTargeted => {
+            parse_packet::<PacketDiscover>(config, channel, data).map(drop)
+        }
+        Channel::Info | Channel::InfoTargeted => parse_packet::<PacketInfo>(config, channel, data)
+            .map(|info| {
+                info.serializers();
+                info.weight();
+            }),
+        Channel::Heartbeat => parse_packet::<PacketHeartbeat>(config, channel, data).map(drop),
+        Channel::Ping | Channel::PingTargeted => {
+            parse_packet::<PacketPing>(config, channel, data).map(drop)
+        }
+        Channel::Pong | Channel::PongPrefix => {
+            parse_packet::<PacketPong>(config, channel, data).map(drop)
+        }
+        Channel::Disconnect => parse_packet::<PacketDisconnect>(config, channel, data).map(drop),
+    };
+}
+
+/// Deserialize an inbound packet and check its envelope.
+///
+/// Packets that can't be parsed or have no sender are logged, counted in
+/// `config.malformed_packets` and returned as an error so the caller can skip them
+/// while keeping its subscription alive.
+/// Parses up to `concurrency` packets of the subscription at once, on tasks of the runtime,
+/// and hands each one to `handle`. Packets are handled in the order they were parsed
+pub(crate) async fn parse_concurrently<P, H>(
+    config: Arc<Config>,
+    subscription: Subscription,
+    channel: Channel,
+    concurrency: usize,
+    handle: H,
+) where
+    P: Packet + Send + 'static,
+    H: Fn(P) + Clone + Send + 'static,
+{
+    let permits = Arc::new(Semaphore::new(concurrency));
+
+    while let Some(msg) = subscription.next().await {
+        let permit = match Arc::clone(&permits).acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => return,
+        };
+
+        let config = Arc::clone(&config);
+        let channel = channel.clone();
+        let handle = handle.clone();
+
+        tokio::spawn(async move {
+            let _permit = permit;
+
+            match parse_packet::<P>(&config, &channel, &msg.data) {
+                Ok(packet) => handle(packet),
+                Err(err) => error!(
+                    "Unable to handle {} message: {}",
+                    channel.packet_type(),
+                    err
+                ),
+            }
+        });
+    }
+}
+
+pub(crate) fn parse_packet<P: Packet>(
+    config: &Config,
+    channel: &Channel,
+    data: &[u8],
+) -> Result<P, Error> {
+    config::tap_inbound(config, channel, data);
+
+    let data = match config::transform_inbound(config, channel, data) {
+        Ok(data) => data,
+        Err(err) => {
+            config.malformed_packets.increment(channel);
+            warn!(
+                "Dropping {} packet rejected by a transit middleware: {}",
+                channel.packet_type(),
+                err
+            );
+
+            return Err(Error::TransitMiddleware(err.to_string()));
+        }
+    };
+    let data = data.as_ref();
+
+    let serializer = config.serializer_for(channel);
+
+    // nodes that don't support the configured serializer send packets as JSON
+    let packet = serializer
+        .deserialize::<P>(data)
+        .or_else(|err| match serializer {
+            Serializer::JSON => Err(err),
+            #[allow(unreachable_patterns)]
+            _ => Serializer::JSON.deserialize::<P>(data),
+        })
+        .map_err(Error::from)
+        .and_then(|packet| packet.envelope().check(config).map(|_| packet));
+
+    match packet {
+        Err(err @ Error::Deserialize(_)) | Err(err @ Error::MissingSender) => {
+            config.malformed_packets.increment(channel);
+            warn!(
+                "Dropping malformed {} packet: {}",
+                channel.packet_type(),
+                err
+            );
+
+            Err(err)
+        }
+        Ok(packet) => {
+            config::log_packet(
+                config,
+                PacketDirection::Inbound,
+                channel.packet_type(),
+                &packet,
+            );
+            Ok(packet)
+        }
+        packet => packet,
+    }
 }
 
 #[async_trait]
@@ -71,6 +246,14 @@ impl Actor for ChannelSupervisor {
     }
 }
 
+/// A packet held while the transporter is disconnected, see `send_buffer_size` in [Config].
+struct BufferedPacket {
+    channel: String,
+    message: Vec<u8>,
+    deadline: Option<Instant>,
+    request_id: Option<String>,
+}
+
 pub(crate) struct ChannelSupervisor {
     broker: Addr<ServiceBroker>,
 
@@ -79,6 +262,11 @@ pub(crate) struct ChannelSupervisor {
     pid: WeakAddr<Self>,
     channels: HashMap<Channel, String>,
 
+    connection_events: Option<UnboundedReceiver<ConnectionEvent>>,
+    disconnected_at: Option<Instant>,
+    stopping: bool,
+    send_buffer: VecDeque<BufferedPacket>,
+
     // channels
     event: Addr<Event>,
 
@@ -99,16 +287,27 @@ pub(crate) struct ChannelSupervisor {
 
     pong: Addr<Pong>,
     disconnect: Addr<Disconnect>,
+
+    durable_events: Addr<DurableEventPublisher>,
+    balanced: Addr<Balanced>,
+    // by service name
+    durable_consumers: HashMap<String, Addr<DurableEventConsumer>>,
 }
 
 impl ChannelSupervisor {
     async fn new(broker: Addr<ServiceBroker>, config: Arc<Config>) -> Self {
         let channels = Channel::build_hashmap(&config);
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
 
         let conn = match &config.transporter {
-            Transporter::Nats(nats_address) => nats::Conn::new(nats_address)
-                .await
-                .expect("NATS should connect"),
+            Transporter::Nats(nats) => nats::Conn::new(
+                nats,
+                config.connection_name.as_ref().unwrap_or(&config.node_id),
+                (!config.transit.disable_reconnect).then_some(config.reconnect_policy),
+                events_tx,
+            )
+            .await
+            .expect("NATS should connect"),
         };
 
         Self {
@@ -117,6 +316,11 @@ impl ChannelSupervisor {
             config,
             channels,
 
+            connection_events: Some(events_rx),
+            stopping: false,
+            disconnected_at: None,
+            send_buffer: VecDeque::new(),
+
             pid: WeakAddr::detached(),
 
             event: Addr::detached(),
@@ -137,14 +341,31 @@ impl ChannelSupervisor {
 
             pong: Addr::detached(),
             disconnect: Addr::detached(),
+
+            durable_events: Addr::detached(),
+            balanced: Addr::detached(),
+            durable_consumers: HashMap::new(),
         }
     }
 
     async fn start_listeners(&mut self) -> ActorResult<()> {
         let broker_pid = self.broker.clone().downgrade();
 
-        self.heartbeat = spawn_actor(
-            Heartbeat::new(
+        // the discoverers keeping the heartbeats in a store don't send them
+        if self.config.registry.heartbeats_over_transporter() {
+            self.heartbeat = spawn_actor(
+                Heartbeat::new(
+                    self.pid.clone(),
+                    broker_pid.clone(),
+                    &self.config,
+                    &self.conn,
+                )
+                .await,
+            );
+        }
+
+        self.ping = spawn_actor(
+            Ping::new(
                 self.pid.clone(),
                 broker_pid.clone(),
                 &self.config,
@@ -153,12 +374,18 @@ impl ChannelSupervisor {
             .await,
         );
 
-        self.ping = spawn_actor(Ping::new(self.pid.clone(), &self.config, &self.conn).await);
-
         self.ping_targeted =
             spawn_actor(PingTargeted::new(self.pid.clone(), &self.config, &self.conn).await);
 
-        self.pong = spawn_actor(Pong::new(self.pid.clone(), &self.config, &self.conn).await);
+        self.pong = spawn_actor(
+            Pong::new(
+                self.pid.clone(),
+                broker_pid.clone(),
+                &self.config,
+                &self.conn,
+            )
+            .await,
+        );
 
         self.disconnect =
             spawn_actor(Disconnect::new(broker_pid.clone(), &self.config, &self.conn).await);
@@ -183,13 +410,85 @@ impl ChannelSupervisor {
 
         self.event = spawn_actor(Event::new(broker_pid.clone(), &self.config, &self.conn).await);
 
-        self.request = spawn_actor(Request::new(broker_pid, &self.config, &self.conn).await);
+        self.request =
+            spawn_actor(Request::new(broker_pid.clone(), &self.config, &self.conn).await);
+
+        if self.config.disable_balancer {
+            self.balanced =
+                spawn_actor(Balanced::new(broker_pid.clone(), &self.config, &self.conn));
+        }
+
+        self.response = spawn_actor(Response::new(broker_pid, &self.config, &self.conn).await);
+
+        if self.config.durable_events.is_some() {
+            self.durable_events = spawn_actor(DurableEventPublisher::new(&self.config, &self.conn));
+
+            // created before any event is published or consumer started, the broker waits for
+            // the listeners to be started
+            call!(self.durable_events.create_stream()).await?;
+        }
+
+        if let Some(mut connection_events) = self.connection_events.take() {
+            let pid = self.pid.upgrade();
 
-        self.response = spawn_actor(Response::new(&self.config, &self.conn).await);
+            pid.clone().send_fut(async move {
+                while let Some(event) = connection_events.recv().await {
+                    send!(pid.handle_connection_event(event));
+                }
+            });
+        }
 
         Produces::ok(())
     }
 
+    async fn handle_connection_event(&mut self, event: ConnectionEvent) {
+        match event {
+            ConnectionEvent::Disconnected => {
+                warn!("Lost connection to transporter");
+                self.disconnected_at = Some(self.config.clock.now());
+                send!(self
+                    .broker
+                    .set_connection_state(ConnectionState::Reconnecting));
+            }
+
+            ConnectionEvent::Reconnected { attempts } => {
+                send!(self.broker.set_connection_state(ConnectionState::Connected));
+
+                let downtime = self
+                    .disconnected_at
+                    .take()
+                    .map(|disconnected_at| self.config.clock.now().duration_since(disconnected_at))
+                    .unwrap_or_default();
+
+                info!(
+                    "Reconnected to transporter after {:?} ({} attempts)",
+                    downtime, attempts
+                );
+
+                // subscriptions are restored by the NATS client,
+                // re-announce this node and rediscover the cluster before notifying the broker
+                send!(self.broker.broadcast_info());
+                send!(self.discover.broadcast());
+                self.flush_send_buffer().await;
+                send!(self
+                    .broker
+                    .set_connection_state(ConnectionState::Discovered));
+                send!(self.broker.handle_reconnect(downtime));
+            }
+
+            // closed by `stop()`
+            ConnectionEvent::Closed { .. } if self.stopping => {}
+
+            ConnectionEvent::Closed { attempts } => {
+                error!(
+                    "Unable to reconnect to transporter after {} attempts, giving up",
+                    attempts
+                );
+                send!(self.broker.handle_connect_failed(attempts));
+            }
+        }
+    }
+
     pub(crate) async fn broadcast_discover(&self) {
         send!(self.discover.broadcast());
     }
@@ -202,32 +501,223 @@ impl ChannelSupervisor {
     where
         T: AsRef<str>,
     {
-        let res = self.conn.send(channel.as_ref(), message).await;
-
-        if let Err(err) = res {
+        if let Err(err) = self.send_packet(channel.as_ref(), message).await {
             error!("Unable to send message: {}", err)
         }
 
         Produces::ok(())
     }
 
+    // a packet the transit middlewares fail on is dropped, it isn't an error of the transporter
+    async fn send_packet(&self, channel: &str, message: Vec<u8>) -> Result<(), nats::Error> {
+        let message = match config::transform_outbound(&self.config, channel, message) {
+            Ok(message) => message,
+            Err(err) => {
+                error!(
+                    "Transit middleware failed, packet to {} dropped: {}",
+                    channel, err
+                );
+                return Ok(());
+            }
+        };
+
+        config::tap_outbound(&self.config, channel, &message);
+
+        let result = self
+            .conn
+            .send(channel, &message, self.config.publish_timeout())
+            .await;
+
+        self.config.buffer_pool.put(message);
+
+        result
+    }
+
+    // the caller of a request whose packet timed out gets the error now, not at its timeout
+    async fn send_buffered_packet(
+        &self,
+        channel: String,
+        message: Vec<u8>,
+        request_id: Option<String>,
+    ) {
+        let err = match self.send_packet(&channel, message).await {
+            Ok(()) => return,
+            Err(err) => err,
+        };
+
+        error!("Unable to send message: {}", err);
+
+        if let (nats::Error::PublishTimeout(..), Some(request_id)) = (err, request_id) {
+            send!(self
+                .response
+                .publish_timed_out(request_id, crate::Error::PublishTimeout(channel)));
+        }
+    }
+
+    /// Publish the copy of a durable event to its stream, see
+    /// [DurableEvents][crate::config::DurableEvents]
+    pub(crate) async fn publish_durable(
+        &self,
+        subject: String,
+        event_id: String,
+        message: Vec<u8>,
+    ) {
+        // read back by durable consumers, which parse it like any inbound packet
+        match config::transform_outbound(&self.config, &subject, message) {
+            Ok(message) => send!(self.durable_events.publish(subject, event_id, message)),
+            Err(err) => error!(
+                "Transit middleware failed, durable event {} not stored: {}",
+                subject, err
+            ),
+        }
+    }
+
+    /// Listen to the balanced subjects of the events and actions of this node, see
+    /// `disable_balancer` in the config
+    pub(crate) async fn subscribe_balanced(&mut self, subjects: Vec<BalancedSubject>) {
+        send!(self.balanced.subscribe(subjects));
+    }
+
+    /// Start consuming the durable events a service listens to, see
+    /// [DurableConsumer][crate::service::DurableConsumer]
+    pub(crate) async fn start_durable_consumer(
+        &mut self,
+        service_name: String,
+        options: DurableConsumer,
+        events: Vec<String>,
+    ) {
+        let consumer = spawn_actor(DurableEventConsumer::new(
+            self.broker.clone().downgrade(),
+            &self.config,
+            &self.conn,
+            service_name.clone(),
+            events,
+            options,
+        ));
+
+        if let Some(previous) = self.durable_consumers.insert(service_name, consumer) {
+            send!(previous.stop());
+        }
+    }
+
+    pub(crate) async fn stop_durable_consumer(&mut self, service_name: String) {
+        if let Some(consumer) = self.durable_consumers.remove(&service_name) {
+            send!(consumer.stop());
+        }
+    }
+
+    /// Publish a request or an event, held in the send buffer while the transporter is
+    /// disconnected. Packets with a `deadline` are dropped if it passes before they are sent,
+    /// the pending request `request_id` fails if its packet can't be published in time
+    pub(crate) async fn publish_buffered(
+        &mut self,
+        channel: String,
+        message: Vec<u8>,
+        deadline: Option<Instant>,
+        request_id: Option<String>,
+    ) -> ActorResult<()> {
+        let send_buffer_size = self.config.send_buffer_size;
+
+        if self.disconnected_at.is_none() || send_buffer_size == 0 {
+            self.send_buffered_packet(channel, message, request_id)
+                .await;
+            return Produces::ok(());
+        }
+
+        if self.send_buffer.len() >= send_buffer_size {
+            warn!(
+                "Send buffer full ({} packets), dropping packet to {}",
+                send_buffer_size, channel
+            );
+            return Produces::ok(());
+        }
+
+        self.send_buffer.push_back(BufferedPacket {
+            channel,
+            message,
+            deadline,
+            request_id,
+        });
+
+        Produces::ok(())
+    }
+
+    /// Reports once the packets published before this call were flushed to the transporter.
+    /// The flush runs on its own task so publishing isn't held up while waiting
+    pub(crate) async fn flush(&self, tx: Sender<Result<(), crate::Error>>) {
+        if !self.send_buffer.is_empty() {
+            let _ = tx.send(Err(crate::Error::PublishFailed(format!(
+                "transporter is disconnected, {} packets are held in the send buffer",
+                self.send_buffer.len()
+            ))));
+            return;
+        }
+
+        let conn = self.conn.clone();
+        let timeout = self.config.publish_timeout();
+
+        tokio::spawn(async move {
+            let result = conn.flush(timeout).await.map_err(|err| match err {
+                nats::Error::PublishTimeout(..) => {
+                    crate::Error::PublishTimeout("the transporter".to_string())
+                }
+                err => crate::Error::PublishFailed(err.to_string()),
+            });
+
+            let _ = tx.send(result);
+        });
+    }
+
+    pub(crate) async fn send_buffer_depth(&self) -> ActorResult<usize> {
+        Produces::ok(self.send_buffer.len())
+    }
+
+    // callers of expired requests already got a timeout error from their response waiter
+    async fn flush_send_buffer(&mut self) {
+        let now = self.config.clock.now();
+        let mut expired = 0;
+
+        while let Some(packet) = self.send_buffer.pop_front() {
+            match packet.deadline {
+                Some(deadline) if deadline <= now => expired += 1,
+                _ => {
+                    self.send_buffered_packet(packet.channel, packet.message, packet.request_id)
+                        .await
+                }
+            }
+        }
+
+        if expired > 0 {
+            debug!("Dropped {} expired packets from the send buffer", expired);
+        }
+    }
+
     pub(crate) async fn start_response_waiter(
         &self,
+        timeout: i32,
         node_name: String,
+        action: String,
         request_id: String,
-        tx: Sender<Value>,
+        tx: Sender<Result<Value, crate::Error>>,
     ) -> ActorResult<()> {
-        call!(self.response.start_response_waiter(
-            self.config.request_timeout,
-            node_name,
-            request_id,
-            tx
-        ))
+        call!(self
+            .response
+            .start_response_waiter(timeout, node_name, action, request_id, tx))
         .await?;
 
         Produces::ok(())
     }
 
+    pub(crate) async fn active_subscriptions(&self) -> ActorResult<Vec<String>> {
+        Produces::ok(self.conn.subscriptions())
+    }
+
+    pub(crate) async fn pending_requests(&self) -> ActorResult<Vec<PendingInfo>> {
+        let pending_requests = call!(self.response.pending_requests()).await?;
+
+        Produces::ok(pending_requests)
+    }
+
     async fn publish(&self, channel: Channel, message: Vec<u8>) -> ActorResult<()> {
         let channel = self
             .channels
@@ -241,11 +731,25 @@ impl ChannelSupervisor {
         Produces::ok(())
     }
 
+    // sends DISCONNECT before closing the connection, which ends all the listeners
+    pub(crate) async fn stop(&mut self) -> ActorResult<()> {
+        self.stopping = true;
+        let _ = call!(self.response.log_pending_requests()).await;
+        self.send_disconnect().await?;
+        self.conn.close().await?;
+
+        info!("Closed transporter connection");
+        Produces::ok(())
+    }
+
     async fn send_disconnect(&self) -> ActorResult<()> {
-        let msg = DisconnectMessage::new(&self.config.node_id);
+        let msg = PacketDisconnect::new(&self.config);
 
         let _ = self
-            .publish(Channel::Disconnect, self.config.serializer.serialize(msg)?)
+            .publish(
+                Channel::Disconnect,
+                self.config.serialize_packet(&Channel::Disconnect, msg)?,
+            )
             .await;
 
         debug!("Disconnect message sent");
@@ -0,0 +1,139 @@
+use crate::{
+    broker::ServiceBroker,
+    channels::{
+        messages::incoming::{PacketEvent, PacketRequest},
+        parse_packet,
+    },
+    config::{Channel, Config},
+    nats::{Conn, Subscription},
+};
+
+use act_zero::*;
+use async_nats::Message;
+use async_trait::async_trait;
+use log::{debug, error, info};
+use std::{collections::HashMap, sync::Arc};
+
+/// A subject of the events or requests of this node when `disable_balancer` is set, the nodes
+/// subscribe to it as members of the `queue` group
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct BalancedSubject {
+    pub(crate) subject: String,
+    pub(crate) queue: String,
+    /// [`Channel::Event`] or [`Channel::Request`], the packets received on the subject
+    pub(crate) channel: Channel,
+}
+
+#[async_trait]
+impl Actor for Balanced {
+    async fn started(&mut self, pid: Addr<Self>) -> ActorResult<()> {
+        self.pid = pid.downgrade();
+        Produces::ok(())
+    }
+
+    async fn error(&mut self, error: ActorError) -> bool {
+        error!("Balanced Actor Error: {:?}", error);
+
+        // do not stop on actor error
+        false
+    }
+}
+
+/// Listens to the balanced subjects of the events and actions of this node, they change as
+/// services are added and removed
+pub(crate) struct Balanced {
+    config: Arc<Config>,
+    broker: WeakAddr<ServiceBroker>,
+    conn: Conn,
+    pid: WeakAddr<Self>,
+
+    subscriptions: HashMap<BalancedSubject, Arc<Subscription>>,
+}
+
+impl Balanced {
+    pub(crate) fn new(broker: WeakAddr<ServiceBroker>, config: &Arc<Config>, conn: &Conn) -> Self {
+        Self {
+            broker,
+            conn: conn.clone(),
+            config: Arc::clone(config),
+            pid: WeakAddr::detached(),
+
+            subscriptions: HashMap::new(),
+        }
+    }
+
+    /// Subscribes to the subjects it isn't subscribed to yet, and unsubscribes from the ones
+    /// that aren't listed anymore
+    pub(crate) async fn subscribe(&mut self, subjects: Vec<BalancedSubject>) {
+        let removed: Vec<BalancedSubject> = self
+            .subscriptions
+            .keys()
+            .filter(|subscribed| !subjects.contains(subscribed))
+            .cloned()
+            .collect();
+
+        for subject in removed {
+            if let Some(subscription) = self.subscriptions.remove(&subject) {
+                info!("Stopped listening to {}", subject.subject);
+                if let Err(err) = subscription.unsubscribe().await {
+                    error!("Unable to unsubscribe from {}: {}", subject.subject, err);
+                }
+            }
+        }
+
+        for subject in subjects {
+            if self.subscriptions.contains_key(&subject) {
+                continue;
+            }
+
+            let subscription = match self
+                .conn
+                .queue_subscribe(&subject.subject, &subject.queue)
+                .await
+            {
+                Ok(subscription) => Arc::new(subscription),
+                Err(err) => {
+                    error!("Unable to listen to {}: {}", subject.subject, err);
+                    continue;
+                }
+            };
+
+            info!(
+                "Listening to {} in queue group '{}'",
+                subject.subject, subject.queue
+            );
+
+            let pid = self.pid.clone();
+            let channel = subject.channel.clone();
+            let messages = Arc::clone(&subscription);
+
+            self.pid.upgrade().send_fut(async move {
+                // ends once unsubscribed
+                while let Some(msg) = messages.next().await {
+                    match call!(pid.handle_message(channel.clone(), msg)).await {
+                        Ok(_) => debug!("Successfully handled balanced {:?} message", channel),
+                        Err(e) => error!("Unable to handle balanced {:?} message: {}", channel, e),
+                    }
+                }
+            });
+
+            self.subscriptions.insert(subject, subscription);
+        }
+    }
+
+    async fn handle_message(&self, channel: Channel, msg: Message) -> ActorResult<()> {
+        match channel {
+            Channel::Event => {
+                let event: PacketEvent = parse_packet(&self.config, &channel, &msg.data)?;
+                send!(self.broker.handle_incoming_event(event));
+            }
+            Channel::Request => {
+                let request: PacketRequest = parse_packet(&self.config, &channel, &msg.data)?;
+                send!(self.broker.handle_incoming_request(request));
+            }
+            _ => unreachable!("only events and requests are balanced"),
+        }
+
+        Produces::ok(())
+    }
+}
@@ -4,7 +4,7 @@ use crate::{
     nats::Conn,
 };
 
-use super::messages::incoming::DisconnectMessage;
+use super::{messages::incoming::PacketDisconnect, parse_packet};
 use act_zero::*;
 use async_nats::Message;
 use async_trait::async_trait;
@@ -65,7 +65,8 @@ impl Disconnect {
     }
 
     async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let disconnect_msg: DisconnectMessage = self.config.serializer.deserialize(&msg.data)?;
+        let disconnect_msg: PacketDisconnect =
+            parse_packet(&self.config, &Channel::Disconnect, &msg.data)?;
 
         send!(self.broker.handle_disconnect_message(disconnect_msg));
 
@@ -4,7 +4,7 @@ use crate::{
     nats::Conn,
 };
 
-use super::{messages::incoming, messages::outgoing, ChannelSupervisor};
+use super::{messages::incoming, messages::outgoing, parse_packet, ChannelSupervisor};
 use act_zero::*;
 use async_nats::Message;
 use async_trait::async_trait;
@@ -67,22 +67,22 @@ impl Discover {
     }
 
     pub(crate) async fn broadcast(&self) {
-        let msg = outgoing::DiscoverMessage::new(&self.config.node_id);
+        let msg = outgoing::PacketDiscover::new(&self.config);
         send!(self.parent.publish(
             Channel::Discover,
             self.config
-                .serializer
-                .serialize(msg)
+                .serialize_packet(&Channel::Discover, msg)
                 .expect("should always serialize discover msg")
         ));
     }
 
     async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let discover: incoming::DiscoverMessage = self.config.serializer.deserialize(&msg.data)?;
+        let discover: incoming::PacketDiscover =
+            parse_packet(&self.config, &Channel::Discover, &msg.data)?;
         let channel = format!(
             "{}.{}",
             Channel::Info.channel_to_string(&self.config),
-            discover.sender
+            discover.envelope.sender
         );
 
         send!(self.broker.publish_info_to_channel(channel));
@@ -146,11 +146,12 @@ impl DiscoverTargeted {
     }
 
     async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let discover: incoming::DiscoverMessage = self.config.serializer.deserialize(&msg.data)?;
+        let discover: incoming::PacketDiscover =
+            parse_packet(&self.config, &Channel::DiscoverTargeted, &msg.data)?;
         let channel = format!(
             "{}.{}",
             Channel::Info.channel_to_string(&self.config),
-            discover.sender
+            discover.envelope.sender
         );
 
         send!(self.broker.publish_info_to_channel(channel));
@@ -0,0 +1,353 @@
+use crate::{
+    broker::ServiceBroker,
+    channels::{messages::incoming::PacketEvent, parse_packet},
+    config::{self, Channel, Config, DurableEvents},
+    nats::{self, Conn, Subscription},
+    service::DurableConsumer,
+};
+
+use act_zero::*;
+use async_nats::Message;
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Duration};
+
+// JetStream error code of a stream that already exists with another config
+const STREAM_NAME_IN_USE: u64 = 10058;
+// JetStream error code of a request to a stream that doesn't exist
+const STREAM_NOT_FOUND: u64 = 10059;
+// times a consumer is created while its stream doesn't exist yet, ex: another node creates it
+const CONSUMER_CREATE_ATTEMPTS: u32 = 5;
+
+// acknowledgements sent in reply to a message pushed by a consumer
+const ACK: &[u8] = b"+ACK";
+const NAK: &[u8] = b"-NAK";
+const TERM: &[u8] = b"+TERM";
+
+#[async_trait]
+impl Actor for DurableEventPublisher {
+    async fn error(&mut self, error: ActorError) -> bool {
+        error!("DurableEventPublisher Actor Error: {:?}", error);
+
+        // do not stop on actor error
+        false
+    }
+}
+
+/// Publishes the copies of durable events to their JetStream stream. Copies are published one
+/// at a time, in the order of the mailbox, so they are stored in the order they were emitted.
+pub(crate) struct DurableEventPublisher {
+    config: Arc<Config>,
+    conn: Conn,
+}
+
+impl DurableEventPublisher {
+    pub(crate) fn new(config: &Arc<Config>, conn: &Conn) -> Self {
+        Self {
+            config: Arc::clone(config),
+            conn: conn.clone(),
+        }
+    }
+
+    fn options(&self) -> &DurableEvents {
+        self.config
+            .durable_events
+            .as_ref()
+            .expect("only spawned with durable events")
+    }
+
+    /// Creates the stream, a stream that already exists is left as is
+    pub(crate) async fn create_stream(&self) -> ActorResult<()> {
+        let options = self.options();
+        let subject = format!("$JS.API.STREAM.CREATE.{}", options.stream);
+        let stream_config = json!({
+            "name": options.stream,
+            "subjects": [options.stream_subjects(&self.config)],
+            "retention": "limits",
+            "storage": "file",
+            "max_age": Duration::from_secs(options.max_age as u64).as_nanos() as u64,
+        });
+
+        let timeout = Duration::from_millis(options.ack_timeout as u64);
+
+        match self
+            .conn
+            .jetstream_request(&subject, &stream_config, timeout)
+            .await
+        {
+            Ok(_) => info!("Created JetStream stream {}", options.stream),
+            Err(nats::Error::JetStream(error))
+                if error.get("err_code").and_then(Value::as_u64) == Some(STREAM_NAME_IN_USE) =>
+            {
+                debug!("JetStream stream {} already exists", options.stream)
+            }
+            Err(err) => error!(
+                "Unable to create JetStream stream {}, durable events won't be stored: {}",
+                options.stream, err
+            ),
+        }
+
+        Produces::ok(())
+    }
+
+    pub(crate) async fn publish(
+        &self,
+        subject: String,
+        event_id: String,
+        message: Vec<u8>,
+    ) -> ActorResult<()> {
+        let options = self.options();
+        let timeout = Duration::from_millis(options.ack_timeout as u64);
+        let max_attempts = options.max_publish_attempts.max(1);
+
+        for attempt in 1..=max_attempts {
+            match self
+                .conn
+                .publish_with_ack(&subject, &event_id, &message, timeout)
+                .await
+            {
+                Ok(()) => {
+                    self.config.buffer_pool.put(message);
+                    return Produces::ok(());
+                }
+                Err(err) => warn!(
+                    "Durable event {} not stored (attempt {}/{}): {}",
+                    subject, attempt, max_attempts, err
+                ),
+            }
+
+            // the stream may be unavailable for a moment, ex: during a leader election
+            if attempt < max_attempts {
+                self.config
+                    .clock
+                    .sleep(timeout.min(Duration::from_secs(1)))
+                    .await;
+            }
+        }
+
+        error!(
+            "Durable event {} ({}) lost after {} attempts",
+            subject, event_id, max_attempts
+        );
+
+        Produces::ok(())
+    }
+}
+
+#[async_trait]
+impl Actor for DurableEventConsumer {
+    async fn started(&mut self, pid: Addr<Self>) -> ActorResult<()> {
+        send!(pid.subscribe(pid.clone()));
+        Produces::ok(())
+    }
+
+    async fn error(&mut self, error: ActorError) -> bool {
+        error!("DurableEventConsumer Actor Error: {:?}", error);
+
+        // do not stop on actor error
+        false
+    }
+}
+
+/// Delivers the durable events a service listens to from its JetStream consumers, see
+/// [DurableConsumer]. Events are handled one at a time, in the order of the stream.
+pub(crate) struct DurableEventConsumer {
+    broker: WeakAddr<ServiceBroker>,
+    config: Arc<Config>,
+    conn: Conn,
+
+    service: String,
+    events: Vec<String>,
+    options: DurableConsumer,
+    subscriptions: Vec<Arc<Subscription>>,
+}
+
+impl DurableEventConsumer {
+    pub(crate) fn new(
+        broker: WeakAddr<ServiceBroker>,
+        config: &Arc<Config>,
+        conn: &Conn,
+        service: String,
+        events: Vec<String>,
+        options: DurableConsumer,
+    ) -> Self {
+        Self {
+            broker,
+            config: Arc::clone(config),
+            conn: conn.clone(),
+
+            service,
+            events,
+            options,
+            subscriptions: vec![],
+        }
+    }
+
+    fn durable_events(&self) -> &DurableEvents {
+        self.config
+            .durable_events
+            .as_ref()
+            .expect("only spawned with durable events")
+    }
+
+    // consumer names are a level of the subjects of the JetStream API
+    fn consumer_name(&self, event_name: &str) -> String {
+        let name = self.options.name.as_deref().unwrap_or(&self.service);
+
+        config::subject_token(&format!("{}-{}", name, event_name))
+    }
+
+    async fn subscribe(&mut self, pid: Addr<Self>) -> ActorResult<()> {
+        for event_name in self.events.clone() {
+            let consumer = self.consumer_name(&event_name);
+            let deliver_subject = self
+                .durable_events()
+                .deliver_subject(&self.config, &consumer);
+
+            // without a consumer nothing would be delivered to the subscription
+            if let Err(err) = self
+                .create_consumer(&event_name, &consumer, &deliver_subject)
+                .await
+            {
+                error!(
+                    "Unable to create JetStream consumer {}, durable event '{}' won't be consumed: {}",
+                    consumer, event_name, err
+                );
+                continue;
+            }
+
+            // instances of the service sharing the consumer get each event once
+            let subscription = match self.conn.queue_subscribe(&deliver_subject, &consumer).await {
+                Ok(subscription) => Arc::new(subscription),
+                Err(err) => {
+                    error!("Unable to consume durable event '{}': {}", event_name, err);
+                    continue;
+                }
+            };
+
+            info!("Consuming durable event '{}' as {}", event_name, consumer);
+            self.subscriptions.push(Arc::clone(&subscription));
+
+            let pid = pid.clone();
+            pid.clone().send_fut(async move {
+                while let Some(msg) = subscription.next().await {
+                    if let Err(err) = call!(pid.handle_message(msg)).await {
+                        error!("Unable to handle durable EVENT message: {}", err);
+                    }
+                }
+            });
+        }
+
+        Produces::ok(())
+    }
+
+    // a consumer that already exists keeps its position, JetStream resumes from there
+    async fn create_consumer(
+        &self,
+        event_name: &str,
+        consumer: &str,
+        deliver_subject: &str,
+    ) -> Result<(), nats::Error> {
+        let durable_events = self.durable_events();
+        let subject = format!(
+            "$JS.API.CONSUMER.DURABLE.CREATE.{}.{}",
+            durable_events.stream, consumer
+        );
+        let consumer_config = json!({
+            "stream_name": durable_events.stream,
+            "config": {
+                "durable_name": consumer,
+                "deliver_subject": deliver_subject,
+                "deliver_group": consumer,
+                "deliver_policy": "all",
+                "ack_policy": "explicit",
+                "ack_wait": Duration::from_millis(self.options.ack_wait as u64).as_nanos() as u64,
+                "max_deliver": self.options.max_deliver.max(1),
+                "filter_subject": durable_events.subject(&self.config, event_name),
+            },
+        });
+
+        let timeout = Duration::from_millis(durable_events.ack_timeout as u64);
+        let mut attempt = 1;
+
+        loop {
+            match self
+                .conn
+                .jetstream_request(&subject, &consumer_config, timeout)
+                .await
+            {
+                Err(nats::Error::JetStream(error))
+                    if attempt < CONSUMER_CREATE_ATTEMPTS
+                        && error.get("err_code").and_then(Value::as_u64)
+                            == Some(STREAM_NOT_FOUND) =>
+                {
+                    warn!(
+                        "JetStream stream {} doesn't exist yet, creating consumer {} again (attempt {}/{})",
+                        durable_events.stream, consumer, attempt, CONSUMER_CREATE_ATTEMPTS
+                    );
+                    attempt += 1;
+
+                    self.config
+                        .clock
+                        .sleep(timeout.min(Duration::from_secs(1)))
+                        .await;
+                }
+                result => return result.map(|_| ()),
+            }
+        }
+    }
+
+    async fn handle_message(&self, msg: Message) -> ActorResult<()> {
+        // a packet that can't be parsed never will, it isn't delivered again
+        let event: PacketEvent = match parse_packet(&self.config, &Channel::Event, &msg.data) {
+            Ok(event) => event,
+            Err(err) => {
+                let _ = msg.respond(TERM).await;
+                return Err(err.into());
+            }
+        };
+
+        let event_name = event.event.clone();
+        let broker = self.broker.upgrade();
+
+        match call!(broker.handle_durable_event(self.service.clone(), event)).await {
+            Ok(()) => {
+                msg.respond(ACK).await?;
+            }
+            Err(err) => {
+                let delivered = delivery_count(&msg).unwrap_or_default();
+
+                if delivered >= self.options.max_deliver as u64 {
+                    error!(
+                        "Durable event '{}' failed {} times, giving up: {}",
+                        event_name, delivered, err
+                    );
+                } else {
+                    warn!(
+                        "Durable event '{}' failed (delivery {}), it will be redelivered: {}",
+                        event_name, delivered, err
+                    );
+                }
+
+                msg.respond(NAK).await?;
+            }
+        }
+
+        Produces::ok(())
+    }
+
+    /// Stops receiving events, the consumers stay on the stream and resume from the last
+    /// acknowledged event
+    pub(crate) async fn stop(&mut self) {
+        for subscription in self.subscriptions.drain(..) {
+            let _ = subscription.unsubscribe().await;
+        }
+    }
+}
+
+// the reply subject of a pushed message is
+// `$JS.ACK.<stream>.<consumer>.<delivered>.<stream seq>.<consumer seq>.<timestamp>.<pending>`
+fn delivery_count(msg: &Message) -> Option<u64> {
+    msg.reply.as_ref()?.split('.').nth(4)?.parse().ok()
+}
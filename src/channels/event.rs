@@ -1,14 +1,13 @@
 use crate::{
     broker::ServiceBroker,
-    channels::messages::incoming::EventMessage,
-    config::{self, Channel, Config},
+    channels::{messages::incoming::PacketEvent, parse_concurrently, parse_packet},
+    config::{Channel, Config},
     nats::Conn,
 };
 
 use act_zero::*;
 use async_nats::Message;
 use async_trait::async_trait;
-use config::DeserializeError;
 use log::{debug, error, info};
 use std::sync::Arc;
 use thiserror::Error;
@@ -58,6 +57,24 @@ impl Event {
             .await
             .unwrap();
 
+        let concurrency = self.config.concurrency_for(&Channel::Event);
+
+        if concurrency > 1 {
+            let broker = self.broker.clone();
+            let handle = move |event: PacketEvent| {
+                send!(broker.handle_incoming_event(event));
+            };
+
+            pid.send_fut(parse_concurrently(
+                Arc::clone(&self.config),
+                channel,
+                Channel::Event,
+                concurrency,
+                handle,
+            ));
+            return;
+        }
+
         pid.clone().send_fut(async move {
             while let Some(msg) = channel.next().await {
                 match call!(pid.handle_message(msg)).await {
@@ -69,8 +86,7 @@ impl Event {
     }
 
     async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let event_context: Result<EventMessage, DeserializeError> =
-            self.config.serializer.deserialize(&msg.data);
+        let event_context: PacketEvent = parse_packet(&self.config, &Channel::Event, &msg.data)?;
 
         send!(self.broker.handle_incoming_event(event_context));
 
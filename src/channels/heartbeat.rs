@@ -1,12 +1,12 @@
 use crate::{
     broker::ServiceBroker,
+    clock::Timer,
     config::{Channel, Config},
     nats::Conn,
 };
 
 use super::messages::{incoming, outgoing};
-use super::ChannelSupervisor;
-use act_zero::runtimes::tokio::Timer;
+use super::{parse_packet, ChannelSupervisor};
 use act_zero::timer::Tick;
 use act_zero::*;
 use async_nats::Message;
@@ -81,7 +81,7 @@ impl Heartbeat {
             broker,
             conn: conn.clone(),
             heartbeat_interval: config.heartbeat_interval,
-            timer: Timer::default(),
+            timer: Timer::new(&config.clock),
             system: System::new_with_specifics(RefreshKind::new().with_cpu()),
         }
     }
@@ -106,23 +106,38 @@ impl Heartbeat {
     }
 
     async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let heartbeat: incoming::HeartbeatMessage =
-            self.config.serializer.deserialize(&msg.data)?;
+        let heartbeat: incoming::PacketHeartbeat =
+            parse_packet(&self.config, &Channel::Heartbeat, &msg.data)?;
 
         send!(self.broker.handle_heartbeat_message(heartbeat));
 
         Produces::ok(())
     }
 
-    async fn send_heartbeat(&self) -> ActorResult<()> {
-        let msg = outgoing::HeartbeatMessage::new(
-            &self.config.node_id,
+    async fn send_heartbeat(&mut self) -> ActorResult<()> {
+        let mut msg = outgoing::PacketHeartbeat::new(
+            &self.config,
             self.system.global_processor_info().cpu_usage(),
         );
 
-        send!(self
-            .parent
-            .publish(Channel::Heartbeat, self.config.serializer.serialize(msg)?));
+        // only read when enabled, memory is refreshed from the OS on every heartbeat
+        if self.config.heartbeat_metrics {
+            self.system.refresh_memory();
+
+            let total_memory = self.system.total_memory();
+            let memory = if total_memory == 0 {
+                0.0
+            } else {
+                self.system.used_memory() as f32 / total_memory as f32 * 100.0
+            };
+
+            msg = msg.with_metrics(memory, self.system.load_average().one);
+        }
+
+        send!(self.parent.publish(
+            Channel::Heartbeat,
+            self.config.serialize_packet(&Channel::Heartbeat, msg)?
+        ));
 
         Produces::ok(())
     }
@@ -4,7 +4,7 @@ use crate::{
     nats::Conn,
 };
 
-use super::messages::incoming::InfoMessage;
+use super::{messages::incoming::PacketInfo, parse_packet};
 use act_zero::*;
 use async_nats::Message;
 use async_trait::async_trait;
@@ -65,7 +65,7 @@ impl Info {
     }
 
     async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let info_message: InfoMessage = self.config.serializer.deserialize(&msg.data)?;
+        let info_message: PacketInfo = parse_packet(&self.config, &Channel::Info, &msg.data)?;
         send!(self.broker.handle_info_message(info_message));
 
         Produces::ok(())
@@ -129,7 +129,8 @@ impl InfoTargeted {
     }
 
     async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let info_message: InfoMessage = self.config.serializer.deserialize(&msg.data)?;
+        let info_message: PacketInfo =
+            parse_packet(&self.config, &Channel::InfoTargeted, &msg.data)?;
         send!(self.broker.handle_info_message(info_message));
 
         Produces::ok(())
@@ -1,13 +1,74 @@
 pub(crate) mod incoming {
-    use std::collections::HashMap;
-
-    use serde::Deserialize;
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
     use serde_json::Value;
+    use uuid::Uuid;
+
+    use super::PROTOCOL_VERSION;
+    use crate::{
+        broker::EventHeaders,
+        channels::Error,
+        config::{Config, Serializer},
+        errors::MoleculerError,
+        service::Service,
+    };
+
+    /// Fields common to every packet: the protocol version and the node that sent it
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub(crate) struct Envelope {
+        pub(crate) ver: String,
+        pub(crate) sender: String,
+    }
 
-    use crate::service::Service;
+    impl Envelope {
+        /// Rejects packets without a sender, and packets from a different protocol version
+        /// unless `disable_version_check` is set in the [Transit][crate::config::Transit] config
+        pub(crate) fn check(&self, config: &Config) -> Result<(), Error> {
+            if self.sender.is_empty() {
+                return Err(Error::MissingSender);
+            }
 
-    #[derive(Deserialize, Debug, Clone)]
-    #[serde(rename_all = "camelCase")]
+            if self.ver != PROTOCOL_VERSION && !config.transit.disable_version_check {
+                return Err(Error::ProtocolVersionMismatch {
+                    sender: self.sender.clone(),
+                    version: self.ver.clone(),
+                });
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Implemented by every inbound packet, see [parse_packet][crate::channels::parse_packet]
+    pub(crate) trait Packet: DeserializeOwned + Serialize {
+        fn envelope(&self) -> &Envelope;
+    }
+
+    macro_rules! impl_packet {
+        ($($packet:ty),*) => {
+            $(
+                impl Packet for $packet {
+                    fn envelope(&self) -> &Envelope {
+                        &self.envelope
+                    }
+                }
+            )*
+        };
+    }
+
+    impl_packet!(
+        PacketPing,
+        PacketPong,
+        PacketHeartbeat,
+        PacketDisconnect,
+        PacketInfo,
+        PacketDiscover,
+        PacketEvent,
+        PacketRequest,
+        PacketResponse
+    );
+
+    #[derive(Serialize, Deserialize, Debug, Clone, Default)]
+    #[serde(rename_all = "camelCase", default)]
     pub(crate) struct Client {
         #[serde(rename = "type")]
         type_: String,
@@ -15,56 +76,131 @@ pub(crate) mod incoming {
         lang_version: String,
     }
 
-    #[derive(Deserialize, Debug)]
-    pub(crate) struct PingMessage {
-        pub(crate) ver: String,
-        pub(crate) sender: String,
+    #[derive(Serialize, Deserialize, Debug)]
+    pub(crate) struct PacketPing {
+        #[serde(flatten)]
+        pub(crate) envelope: Envelope,
         pub(crate) id: String,
         pub(crate) time: i64,
     }
 
-    #[derive(Deserialize, Debug)]
-    pub(crate) struct HeartbeatMessage {
-        pub(crate) ver: String,
-        pub(crate) sender: String,
-        pub(crate) cpu: f32,
+    #[derive(Serialize, Deserialize, Debug)]
+    pub(crate) struct PacketPong {
+        #[serde(flatten)]
+        pub(crate) envelope: Envelope,
+        pub(crate) id: String,
+        pub(crate) time: i64,
+        pub(crate) arrived: i64,
     }
 
-    #[derive(Deserialize, Debug)]
-    pub(crate) struct DisconnectMessage {
-        pub(crate) ver: String,
-        pub(crate) sender: String,
+    #[derive(Serialize, Deserialize, Debug)]
+    pub(crate) struct PacketHeartbeat {
+        #[serde(flatten)]
+        pub(crate) envelope: Envelope,
+        // nodes that can't measure their cpu usage send `null`
+        #[serde(default)]
+        pub(crate) cpu: Option<f32>,
+        // only sent by nodes with `heartbeat_metrics` enabled
+        #[serde(default)]
+        pub(crate) memory: Option<f32>,
+        #[serde(default)]
+        pub(crate) load: Option<f64>,
+        #[serde(default)]
+        pub(crate) seq: u64,
+        // not sent by Moleculer JS nodes
+        #[serde(rename = "instanceID", default)]
+        pub(crate) instance_id: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub(crate) struct PacketDisconnect {
+        #[serde(flatten)]
+        pub(crate) envelope: Envelope,
     }
 
-    #[derive(Deserialize, Debug)]
+    // minimal or older nodes leave out some fields, unknown fields are ignored
+    #[derive(Serialize, Deserialize, Debug)]
     #[serde(rename_all = "camelCase")]
-    pub(crate) struct InfoMessage {
-        pub(crate) ver: String,
-        pub(crate) sender: String,
+    pub(crate) struct PacketInfo {
+        #[serde(flatten)]
+        pub(crate) envelope: Envelope,
 
+        #[serde(default)]
         pub(crate) services: Vec<Service>,
+        #[serde(default)]
         pub(crate) ip_list: Vec<String>,
+        #[serde(default)]
         pub(crate) hostname: String,
+        #[serde(default)]
         pub(crate) client: Client,
 
-        #[serde(rename = "instanceID")]
+        #[serde(rename = "instanceID", default)]
         pub(crate) instance_id: String,
 
-        pub(crate) config: HashMap<String, String>,
-        pub(crate) metadata: HashMap<String, String>,
+        #[serde(default)]
+        pub(crate) config: Value,
+        #[serde(default)]
+        pub(crate) metadata: Value,
+
+        // nodes that don't track it send `0`
+        #[serde(default)]
+        pub(crate) seq: u64,
     }
 
-    #[derive(Deserialize, Debug)]
-    pub(crate) struct DiscoverMessage {
-        pub(crate) ver: String,
-        pub(crate) sender: String,
+    impl PacketInfo {
+        /// Serializers the node can decode: its `serializers`, or the single `serializer` some
+        /// nodes send. Nodes that advertise neither (or only ones unknown here) support JSON
+        pub(crate) fn serializers(&self) -> Vec<Serializer> {
+            let advertised = match self.config.get("serializers") {
+                Some(Value::Array(serializers)) => serializers.clone(),
+                _ => self.config.get("serializer").cloned().into_iter().collect(),
+            };
+
+            let serializers: Vec<Serializer> = advertised
+                .into_iter()
+                .filter_map(|serializer| serde_json::from_value(serializer).ok())
+                .collect();
+
+            if serializers.is_empty() {
+                vec![Serializer::JSON]
+            } else {
+                serializers
+            }
+        }
+
+        /// Load balancing `weight` from the node's metadata, `1` when missing or invalid
+        pub(crate) fn weight(&self) -> u32 {
+            metadata_weight(&self.metadata)
+        }
     }
 
-    #[derive(Deserialize, Debug)]
-    pub(crate) struct EventMessage {
+    /// Load balancing `weight` from the metadata of a node, `1` when missing or invalid
+    pub(crate) fn metadata_weight(metadata: &Value) -> u32 {
+        let weight = match metadata.get("weight") {
+            Some(Value::Number(weight)) => weight.as_u64(),
+            Some(Value::String(weight)) => weight.parse().ok(),
+            _ => None,
+        };
+
+        weight
+            .filter(|weight| *weight > 0)
+            .map_or(1, |weight| weight.min(u32::MAX as u64) as u32)
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub(crate) struct PacketDiscover {
+        #[serde(flatten)]
+        pub(crate) envelope: Envelope,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub(crate) struct PacketEvent {
+        #[serde(flatten)]
+        pub(crate) envelope: Envelope,
+
+        // set by emitters to a unique id kept by redeliveries, see `EventDedup`
+        #[serde(default)]
         pub(crate) id: String,
-        pub(crate) sender: String,
-        pub(crate) ver: String,
 
         pub(crate) event: String,
 
@@ -100,11 +236,57 @@ pub(crate) mod incoming {
         pub(crate) broadcast: Option<bool>,
     }
 
-    #[derive(Deserialize, Debug)]
-    pub(crate) struct RequestMessage {
+    impl PacketEvent {
+        /// Builds an event packet for events delivered to listeners on this node without going
+        /// through the transporter
+        pub(crate) fn new_local(
+            config: &Config,
+            event: &str,
+            data: Value,
+            broadcast: bool,
+        ) -> Self {
+            Self {
+                envelope: Envelope {
+                    ver: PROTOCOL_VERSION.to_string(),
+                    sender: config.node_id.clone(),
+                },
+
+                id: Uuid::new_v4().to_string(),
+                event: event.to_string(),
+                data,
+                meta: Value::Object(Default::default()),
+                level: 1,
+
+                tracing: None,
+                parent_id: None,
+                request_id: None,
+                caller: None,
+                stream: None,
+                seq: None,
+                groups: None,
+                broadcast: Some(broadcast),
+            }
+        }
+
+        /// The meta of the event and its place in the request chain
+        pub(crate) fn with_headers(self, headers: EventHeaders) -> Self {
+            Self {
+                meta: headers.meta,
+                level: headers.level,
+                parent_id: headers.parent_id,
+                request_id: headers.request_id,
+                caller: headers.caller,
+                ..self
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub(crate) struct PacketRequest {
+        #[serde(flatten)]
+        pub(crate) envelope: Envelope,
+
         pub(crate) id: String,
-        pub(crate) sender: String,
-        pub(crate) ver: String,
 
         pub(crate) action: String,
 
@@ -135,11 +317,12 @@ pub(crate) mod incoming {
         #[serde(default)]
         pub(crate) seq: Option<i32>,
     }
-    #[derive(Deserialize, Debug)]
-    pub(crate) struct ResponseMessage {
+    #[derive(Serialize, Deserialize, Debug)]
+    pub(crate) struct PacketResponse {
+        #[serde(flatten)]
+        pub(crate) envelope: Envelope,
+
         pub(crate) id: String,
-        pub(crate) sender: String,
-        pub(crate) ver: String,
 
         #[serde(default)]
         pub(crate) data: Value,
@@ -148,7 +331,7 @@ pub(crate) mod incoming {
         pub(crate) meta: Value,
 
         #[serde(default)]
-        pub(crate) error: Option<crate::channels::messages::MoleculerError>,
+        pub(crate) error: Option<MoleculerError>,
 
         #[serde(default)]
         pub(crate) success: bool,
@@ -156,14 +339,38 @@ pub(crate) mod incoming {
 }
 
 pub(crate) mod outgoing {
-    use std::{collections::HashMap, time::SystemTime};
-
-    use super::incoming::PingMessage;
-    use crate::{built_info, config::Config, service::Service};
+    use std::{collections::HashMap, sync::atomic::Ordering, time::SystemTime};
+
+    use super::{incoming, PROTOCOL_VERSION};
+    use crate::{
+        broker::EventHeaders,
+        built_info,
+        config::{Config, Serializer},
+        errors::MoleculerError,
+        service::Service,
+        CallOptions,
+    };
     use serde::Serialize;
     use serde_json::{json, Value};
     use uuid::Uuid;
 
+    /// Fields common to every packet: the protocol version and the node that sent it
+    #[derive(Serialize, Debug, Clone)]
+    pub(crate) struct Envelope<'a> {
+        ver: &'static str,
+        sender: &'a str,
+    }
+
+    impl<'a> Envelope<'a> {
+        /// Stamps the packet with the protocol version and the local node id as the sender
+        pub(crate) fn new(config: &'a Config) -> Self {
+            Self {
+                ver: PROTOCOL_VERSION,
+                sender: &config.node_id,
+            }
+        }
+    }
+
     #[derive(Serialize, Debug)]
     #[serde(rename_all = "camelCase")]
     pub(crate) struct Client {
@@ -174,7 +381,7 @@ pub(crate) mod outgoing {
     }
 
     impl Client {
-        fn new() -> Self {
+        pub(crate) fn new() -> Self {
             Self {
                 type_: "rust",
                 version: env!("CARGO_PKG_VERSION"),
@@ -183,78 +390,120 @@ pub(crate) mod outgoing {
         }
     }
 
+    fn now_millis() -> i64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("now should always be before unix epoch")
+            .as_millis() as i64
+    }
+
     #[derive(Serialize)]
-    pub(crate) struct PongMessage<'a> {
-        ver: String,
-        sender: &'a str,
+    pub(crate) struct PacketPing<'a> {
+        #[serde(flatten)]
+        envelope: Envelope<'a>,
+        pub(crate) id: String,
+        time: i64,
+    }
+
+    impl<'a> PacketPing<'a> {
+        pub(crate) fn new(config: &'a Config) -> Self {
+            Self {
+                envelope: Envelope::new(config),
+                id: Uuid::new_v4().to_string(),
+                time: now_millis(),
+            }
+        }
+    }
+
+    #[derive(Serialize)]
+    pub(crate) struct PacketPong<'a> {
+        #[serde(flatten)]
+        envelope: Envelope<'a>,
         id: String,
         time: i64,
         arrived: i64,
     }
 
-    impl<'a> From<(PingMessage, &'a str)> for PongMessage<'a> {
-        fn from(from: (PingMessage, &'a str)) -> Self {
-            let (ping, node_id) = from;
+    impl<'a> From<(incoming::PacketPing, &'a Config)> for PacketPong<'a> {
+        fn from(from: (incoming::PacketPing, &'a Config)) -> Self {
+            let (ping, config) = from;
 
             Self {
-                ver: ping.ver,
+                envelope: Envelope::new(config),
                 id: ping.id,
-                sender: node_id,
                 time: ping.time,
-                arrived: SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .expect("now should always be before unix epoch")
-                    .as_millis() as i64,
+                arrived: now_millis(),
             }
         }
     }
 
     #[derive(Serialize)]
-    pub(crate) struct HeartbeatMessage<'a> {
-        ver: &'static str,
-        sender: &'a str,
+    pub(crate) struct PacketHeartbeat<'a> {
+        #[serde(flatten)]
+        envelope: Envelope<'a>,
         cpu: f32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        memory: Option<f32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        load: Option<f64>,
+        seq: u64,
+        #[serde(rename = "instanceID")]
+        instance_id: &'a str,
     }
 
-    impl<'a> HeartbeatMessage<'a> {
-        pub(crate) fn new(sender: &'a str, cpu: f32) -> Self {
+    impl<'a> PacketHeartbeat<'a> {
+        pub(crate) fn new(config: &'a Config, cpu: f32) -> Self {
             Self {
-                ver: "4",
-                sender,
+                envelope: Envelope::new(config),
                 cpu,
+                memory: None,
+                load: None,
+                seq: config.seq.load(Ordering::SeqCst),
+                instance_id: &config.instance_id,
             }
         }
+
+        /// Adds the memory usage (percent) and the 1 minute load average
+        pub(crate) fn with_metrics(mut self, memory: f32, load: f64) -> Self {
+            self.memory = Some(memory);
+            self.load = Some(load);
+            self
+        }
     }
 
     #[derive(Serialize)]
-    pub(crate) struct DisconnectMessage<'a> {
-        ver: &'static str,
-        sender: &'a str,
+    pub(crate) struct PacketDisconnect<'a> {
+        #[serde(flatten)]
+        envelope: Envelope<'a>,
     }
 
-    impl<'a> DisconnectMessage<'a> {
-        pub(crate) fn new(sender: &'a str) -> Self {
-            Self { ver: "4", sender }
+    impl<'a> PacketDisconnect<'a> {
+        pub(crate) fn new(config: &'a Config) -> Self {
+            Self {
+                envelope: Envelope::new(config),
+            }
         }
     }
 
     #[derive(Serialize)]
-    pub(crate) struct DiscoverMessage<'a> {
-        ver: &'static str,
-        sender: &'a str,
+    pub(crate) struct PacketDiscover<'a> {
+        #[serde(flatten)]
+        envelope: Envelope<'a>,
     }
 
-    impl<'a> DiscoverMessage<'a> {
-        pub(crate) fn new(sender: &'a str) -> Self {
-            Self { ver: "4", sender }
+    impl<'a> PacketDiscover<'a> {
+        pub(crate) fn new(config: &'a Config) -> Self {
+            Self {
+                envelope: Envelope::new(config),
+            }
         }
     }
 
     #[derive(Serialize)]
     #[serde(rename_all = "camelCase")]
-    pub(crate) struct InfoMessage<'a> {
-        ver: &'static str,
-        sender: &'a str,
+    pub(crate) struct PacketInfo<'a> {
+        #[serde(flatten)]
+        envelope: Envelope<'a>,
 
         #[serde(rename = "instanceID")]
         instance_id: &'a str,
@@ -263,15 +512,15 @@ pub(crate) mod outgoing {
         hostname: &'a str,
         client: Client,
 
-        config: HashMap<String, String>,
-        metadata: HashMap<String, String>,
+        config: Value,
+        metadata: &'a HashMap<String, String>,
+        seq: u64,
     }
 
-    impl<'a> InfoMessage<'a> {
+    impl<'a> PacketInfo<'a> {
         pub(crate) fn new(config: &'a Config, services: &'a [Service]) -> Self {
             Self {
-                ver: "4",
-                sender: &config.node_id,
+                envelope: Envelope::new(config),
 
                 instance_id: &config.instance_id,
                 services,
@@ -279,17 +528,19 @@ pub(crate) mod outgoing {
                 hostname: &config.hostname,
                 client: Client::new(),
 
-                config: HashMap::new(),
-                metadata: HashMap::new(),
+                config: json!({ "serializers": Serializer::supported() }),
+                metadata: &config.meta_data,
+                seq: config.seq.load(Ordering::SeqCst),
             }
         }
     }
 
-    #[derive(Serialize, Debug)]
-    pub(crate) struct EventMessage<'a> {
+    #[derive(Serialize, Debug, Clone)]
+    pub(crate) struct PacketEvent<'a> {
+        #[serde(flatten)]
+        pub(crate) envelope: Envelope<'a>,
+
         pub(crate) id: String,
-        pub(crate) sender: &'a str,
-        pub(crate) ver: &'static str,
 
         pub(crate) event: &'a str,
 
@@ -325,14 +576,16 @@ pub(crate) mod outgoing {
         pub(crate) broadcast: Option<bool>,
     }
 
-    impl<'a> EventMessage<'a> {
-        pub(crate) fn new_for_emit(config: &'a Config, event: &'a str, params: Value) -> Self {
+    impl<'a> PacketEvent<'a> {
+        pub(crate) fn new_for_emit(config: &'a Config, event: &'a str, mut params: Value) -> Self {
+            config.apply_safe_integers(&mut params, || format!("data of '{}' event", event));
+            config.apply_null_fields(&mut params);
+
             Self {
                 event,
 
-                ver: "4",
+                envelope: Envelope::new(config),
                 id: Uuid::new_v4().to_string(),
-                sender: &config.node_id,
                 data: params,
                 meta: json!({}),
                 level: 1,
@@ -352,16 +605,29 @@ pub(crate) mod outgoing {
         pub(crate) fn new_for_broadcast(config: &'a Config, event: &'a str, params: Value) -> Self {
             Self {
                 broadcast: Some(true),
-                ..EventMessage::new_for_emit(config, event, params)
+                ..PacketEvent::new_for_emit(config, event, params)
+            }
+        }
+
+        /// The meta of the event and its place in the request chain
+        pub(crate) fn with_headers(self, headers: &'a EventHeaders) -> Self {
+            Self {
+                meta: headers.meta.clone(),
+                level: headers.level,
+                parent_id: &headers.parent_id,
+                request_id: &headers.request_id,
+                caller: &headers.caller,
+                ..self
             }
         }
     }
 
     #[derive(Serialize, Debug)]
-    pub(crate) struct ResponseMessage<'a> {
+    pub(crate) struct PacketResponse<'a> {
+        #[serde(flatten)]
+        pub(crate) envelope: Envelope<'a>,
+
         pub(crate) id: &'a str,
-        pub(crate) sender: &'a str,
-        pub(crate) ver: &'static str,
 
         #[serde(default)]
         pub(crate) data: Value,
@@ -370,31 +636,52 @@ pub(crate) mod outgoing {
         pub(crate) meta: Value,
 
         #[serde(default)]
-        pub(crate) error: Option<crate::channels::messages::MoleculerError>,
+        pub(crate) error: Option<MoleculerError>,
 
         #[serde(default)]
         pub(crate) success: bool,
     }
 
-    impl<'a> ResponseMessage<'a> {
-        pub(crate) fn new(config: &'a Config, request_id: &'a str, params: Value) -> Self {
+    impl<'a> PacketResponse<'a> {
+        pub(crate) fn new(config: &'a Config, request_id: &'a str, mut params: Value) -> Self {
+            config.apply_safe_integers(&mut params, || format!("response to {}", request_id));
+            config.apply_null_fields(&mut params);
+
             Self {
-                ver: "4",
+                envelope: Envelope::new(config),
                 id: request_id,
                 data: params,
                 meta: Value::default(),
-                sender: &config.node_id,
                 success: true,
                 error: None,
             }
         }
+
+        pub(crate) fn new_error(
+            config: &'a Config,
+            request_id: &'a str,
+            error: MoleculerError,
+        ) -> Self {
+            Self {
+                envelope: Envelope::new(config),
+                id: request_id,
+                data: Value::Null,
+                meta: Value::default(),
+                success: false,
+                error: Some(MoleculerError {
+                    node_id: Some(config.node_id.clone()),
+                    ..error
+                }),
+            }
+        }
     }
 
     #[derive(Serialize, Debug)]
-    pub(crate) struct RequestMessage<'a> {
+    pub(crate) struct PacketRequest<'a> {
+        #[serde(flatten)]
+        pub(crate) envelope: Envelope<'a>,
+
         pub(crate) id: String,
-        pub(crate) sender: &'a str,
-        pub(crate) ver: &'static str,
 
         pub(crate) action: &'a str,
 
@@ -426,28 +713,34 @@ pub(crate) mod outgoing {
         pub(crate) seq: Option<i32>,
     }
 
-    impl<'a> RequestMessage<'a> {
-        pub(crate) fn new(config: &'a Config, action_name: &'a str, params: Value) -> Self {
-            let id = Uuid::new_v4();
+    impl<'a> PacketRequest<'a> {
+        pub(crate) fn new(
+            config: &'a Config,
+            action_name: &'a str,
+            mut params: Value,
+            options: &'a CallOptions,
+        ) -> Self {
+            let id = (config.request_id_generator)();
+            config.apply_safe_integers(&mut params, || format!("params of '{}'", action_name));
+            config.apply_null_fields(&mut params);
 
             Self {
-                ver: "4",
-                sender: &config.node_id,
-                id: id.to_string(),
+                envelope: Envelope::new(config),
+                request_id: options.request_id.clone().unwrap_or_else(|| id.clone()),
+                id,
 
                 params,
                 action: action_name,
 
-                meta: serde_json::Value::default(),
+                meta: config.with_default_meta(options.meta.clone()),
 
-                timeout: config.request_timeout as f32,
-                level: 1,
+                timeout: options.timeout.unwrap_or(config.request_timeout) as f32,
+                level: options.level.saturating_add(1),
 
                 tracing: None,
-                parent_id: None,
+                parent_id: options.parent_id.as_deref(),
 
-                request_id: id.to_string(),
-                caller: None,
+                caller: options.caller.as_deref(),
 
                 stream: None,
                 seq: None,
@@ -456,11 +749,29 @@ pub(crate) mod outgoing {
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
-pub(crate) struct MoleculerError {
-    message: String,
-    code: i8,
-    #[serde(rename = "type")]
-    type_: String,
-    data: serde_json::Value,
+/// The Moleculer protocol version, sent as `ver` on every packet
+pub(crate) const PROTOCOL_VERSION: &str = "4";
+
+/// The packet types defined by the Moleculer protocol, displayed as the name used in the
+/// packet's channel subject, ex: `MOL.REQ.node-1`
+#[derive(strum::Display, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub(crate) enum PacketType {
+    #[strum(serialize = "EVENT")]
+    Event,
+    #[strum(serialize = "REQ")]
+    Request,
+    #[strum(serialize = "RES")]
+    Response,
+    #[strum(serialize = "DISCOVER")]
+    Discover,
+    #[strum(serialize = "INFO")]
+    Info,
+    #[strum(serialize = "HEARTBEAT")]
+    Heartbeat,
+    #[strum(serialize = "PING")]
+    Ping,
+    #[strum(serialize = "PONG")]
+    Pong,
+    #[strum(serialize = "DISCONNECT")]
+    Disconnect,
 }
@@ -1,22 +1,41 @@
 use crate::{
-    config::{Channel, Config},
+    broker::ServiceBroker,
+    clock::Timer,
+    config::{Channel, Config, Strategy},
     nats::Conn,
 };
 
 use super::{
-    messages::{incoming::PingMessage, outgoing::PongMessage},
-    ChannelSupervisor,
+    messages::{
+        incoming::PacketPing,
+        outgoing::{self, PacketPong},
+    },
+    parse_packet, ChannelSupervisor,
 };
 
+use act_zero::timer::Tick;
 use act_zero::*;
 use async_nats::Message;
 use async_trait::async_trait;
 use log::{debug, error, info};
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 #[async_trait]
 impl Actor for Ping {
     async fn started(&mut self, pid: Addr<Self>) -> ActorResult<()> {
+        self.pid = pid.downgrade();
+
+        // the latency strategy is seeded and refreshed by pinging every node
+        if let Some(ping_interval) = self.ping_interval() {
+            self.timer
+                .set_timeout_for_weak(pid.downgrade(), ping_interval);
+        }
+
+        if let Some(liveness_interval) = self.liveness_interval() {
+            self.liveness_timer
+                .set_timeout_for_weak(pid.downgrade(), liveness_interval);
+        }
+
         let pid_clone = pid.clone();
         send!(pid_clone.listen(pid));
         Produces::ok(())
@@ -29,25 +48,87 @@ impl Actor for Ping {
         false
     }
 }
+#[async_trait]
+impl Tick for Ping {
+    async fn tick(&mut self) -> ActorResult<()> {
+        if self.timer.tick() {
+            if let Some(ping_interval) = self.ping_interval() {
+                self.timer
+                    .set_timeout_for_weak(self.pid.clone(), ping_interval);
+            }
+
+            self.send_ping()?;
+        }
+
+        if self.liveness_timer.tick() {
+            if let Some(liveness_interval) = self.liveness_interval() {
+                self.liveness_timer
+                    .set_timeout_for_weak(self.pid.clone(), liveness_interval);
+            }
+
+            send!(self.broker.check_liveness());
+        }
+        Produces::ok(())
+    }
+}
+
 pub(crate) struct Ping {
+    pid: WeakAddr<Self>,
     config: Arc<Config>,
     conn: Conn,
     parent: WeakAddr<ChannelSupervisor>,
+    broker: WeakAddr<ServiceBroker>,
+    timer: Timer,
+    liveness_timer: Timer,
 }
 
 impl Ping {
     pub(crate) async fn new(
         parent: WeakAddr<ChannelSupervisor>,
+        broker: WeakAddr<ServiceBroker>,
         config: &Arc<Config>,
         conn: &Conn,
     ) -> Self {
         Self {
+            pid: WeakAddr::detached(),
             parent,
+            broker,
             conn: conn.clone(),
             config: Arc::clone(config),
+            timer: Timer::new(&config.clock),
+            liveness_timer: Timer::new(&config.clock),
+        }
+    }
+
+    // the nodes of the liveness checks are pinged by the broker, which tracks their PONGs
+    fn liveness_interval(&self) -> Option<Duration> {
+        self.config
+            .liveness_checks
+            .as_ref()
+            .filter(|options| !options.nodes.is_empty())
+            .map(|options| Duration::from_secs(options.interval.max(1) as u64))
+    }
+
+    fn ping_interval(&self) -> Option<Duration> {
+        match &self.config.strategy {
+            Strategy::Latency(options) => {
+                Some(Duration::from_secs(options.ping_interval.max(1) as u64))
+            }
+            _ => None,
         }
     }
 
+    fn send_ping(&self) -> ActorResult<()> {
+        let msg = outgoing::PacketPing::new(&self.config);
+
+        send!(self.parent.publish(
+            Channel::Ping,
+            self.config.serialize_packet(&Channel::Ping, msg)?
+        ));
+
+        Produces::ok(())
+    }
+
     pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
         info!("Listening for PING messages");
 
@@ -68,18 +149,20 @@ impl Ping {
     }
 
     async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let ping_message: PingMessage = self.config.serializer.deserialize(&msg.data)?;
+        let ping_message: PacketPing = parse_packet(&self.config, &Channel::Ping, &msg.data)?;
         let channel = format!(
             "{}.{}",
             Channel::PongPrefix.channel_to_string(&self.config),
-            &ping_message.sender
+            &ping_message.envelope.sender
         );
 
-        let pong_message: PongMessage = (ping_message, self.config.node_id.as_str()).into();
+        let pong_message: PacketPong = (ping_message, self.config.as_ref()).into();
 
-        send!(self
-            .parent
-            .publish_to_channel(channel, self.config.serializer.serialize(pong_message)?));
+        send!(self.parent.publish_to_channel(
+            channel,
+            self.config
+                .serialize_packet(&Channel::PongPrefix, pong_message)?
+        ));
 
         Produces::ok(())
     }
@@ -139,18 +222,21 @@ impl PingTargeted {
     }
 
     async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let ping_message: PingMessage = self.config.serializer.deserialize(&msg.data)?;
+        let ping_message: PacketPing =
+            parse_packet(&self.config, &Channel::PingTargeted, &msg.data)?;
         let channel = format!(
             "{}.{}",
             Channel::PongPrefix.channel_to_string(&self.config),
-            &ping_message.sender
+            &ping_message.envelope.sender
         );
 
-        let pong_message: PongMessage = (ping_message, self.config.node_id.as_str()).into();
+        let pong_message: PacketPong = (ping_message, self.config.as_ref()).into();
 
-        send!(self
-            .parent
-            .publish_to_channel(channel, self.config.serializer.serialize(pong_message)?));
+        send!(self.parent.publish_to_channel(
+            channel,
+            self.config
+                .serialize_packet(&Channel::PongPrefix, pong_message)?
+        ));
 
         Produces::ok(())
     }
@@ -1,14 +1,18 @@
 use crate::{
-    config::{Channel, Config},
-    nats::Conn,
+    broker::ServiceBroker,
+    config::{Channel, Config, Strategy},
+    nats::{Conn, Subscription},
 };
 
-use super::{ChannelSupervisor, Error};
+use super::{messages::incoming::PacketPong, parse_packet, ChannelSupervisor, Error};
 use act_zero::*;
-use async_nats::{Message, Subscription};
+use async_nats::Message;
 use async_trait::async_trait;
 use log::{debug, error, info};
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 #[async_trait]
 impl Actor for Pong {
@@ -30,25 +34,19 @@ pub(crate) struct Pong {
     config: Arc<Config>,
     channel: Subscription,
     parent: WeakAddr<ChannelSupervisor>,
+    broker: WeakAddr<ServiceBroker>,
 }
 
-// #[derive(Deserialize)]
-// struct PongMessage {
-//     ver: String,
-//     sender: String,
-//     id: String,
-//     time: i64,
-//     arrived: i64,
-// }
-
 impl Pong {
     pub(crate) async fn new(
         parent: WeakAddr<ChannelSupervisor>,
+        broker: WeakAddr<ServiceBroker>,
         config: &Arc<Config>,
         conn: &Conn,
     ) -> Self {
         Self {
             parent,
+            broker,
             channel: conn
                 .subscribe(&Channel::Pong.channel_to_string(config))
                 .await
@@ -68,9 +66,35 @@ impl Pong {
         }
     }
 
-    async fn handle_message(&self, _msg: Message) -> Result<(), Error> {
-        // let pong_msg: PongMessage = self.config.serializer.deserialize(&msg.data)?;
-        // do nothing with incoming disconnect messages for now
+    async fn handle_message(&self, msg: Message) -> Result<(), Error> {
+        let pong_msg: PacketPong = parse_packet(&self.config, &Channel::Pong, &msg.data)?;
+
+        debug!(
+            "Received PONG from {} for ping {}",
+            pong_msg.envelope.sender, pong_msg.id
+        );
+
+        // round trip from the time this node sent the PING, clocks of other nodes aren't used
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("now should always be before unix epoch")
+            .as_millis() as i64;
+        let round_trip = Duration::from_millis(now.saturating_sub(pong_msg.time).max(0) as u64);
+
+        if let Strategy::Latency(_) = self.config.strategy {
+            send!(self
+                .broker
+                .record_latency(pong_msg.envelope.sender.clone(), round_trip));
+        }
+
+        if self.config.liveness_checks.is_some() {
+            send!(self.broker.handle_liveness_pong(
+                pong_msg.envelope.sender,
+                pong_msg.id,
+                round_trip
+            ));
+        }
+
         Ok(())
     }
 }
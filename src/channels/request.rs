@@ -1,14 +1,13 @@
 use crate::{
     broker::ServiceBroker,
-    channels::messages::incoming::RequestMessage,
-    config::{self, Channel, Config},
+    channels::{messages::incoming::PacketRequest, parse_concurrently, parse_packet},
+    config::{Channel, Config},
     nats::Conn,
 };
 
 use act_zero::*;
 use async_nats::Message;
 use async_trait::async_trait;
-use config::DeserializeError;
 use log::{debug, error, info};
 use std::sync::Arc;
 
@@ -54,6 +53,24 @@ impl Request {
             .await
             .unwrap();
 
+        let concurrency = self.config.concurrency_for(&Channel::Request);
+
+        if concurrency > 1 {
+            let broker = self.broker.clone();
+            let handle = move |request: PacketRequest| {
+                send!(broker.handle_incoming_request(request));
+            };
+
+            pid.send_fut(parse_concurrently(
+                Arc::clone(&self.config),
+                channel,
+                Channel::Request,
+                concurrency,
+                handle,
+            ));
+            return;
+        }
+
         pid.clone().send_fut(async move {
             while let Some(msg) = channel.next().await {
                 match call!(pid.handle_message(msg)).await {
@@ -65,8 +82,8 @@ impl Request {
     }
 
     async fn handle_message(&self, msg: Message) -> ActorResult<()> {
-        let request_context: Result<RequestMessage, DeserializeError> =
-            self.config.serializer.deserialize(&msg.data);
+        let request_context: PacketRequest =
+            parse_packet(&self.config, &Channel::Request, &msg.data)?;
 
         send!(self.broker.handle_incoming_request(request_context));
 
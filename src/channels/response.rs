@@ -1,17 +1,25 @@
 use crate::{
-    channels::messages::incoming::ResponseMessage,
-    config::{Channel, Config},
+    broker::ServiceBroker,
+    channels::{messages::incoming::PacketResponse, parse_packet},
+    clock::{Clock, Timer},
+    config::{Channel, Config, ResponseUnwrapper, Strategy},
+    errors::MoleculerError,
     nats::Conn,
+    Error, PendingInfo,
 };
 
-use act_zero::runtimes::tokio::{spawn_actor, Timer};
+use act_zero::runtimes::tokio::spawn_actor;
 use act_zero::timer::Tick;
 use act_zero::*;
 use async_nats::Message;
 use async_trait::async_trait;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use serde_json::Value;
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::oneshot::Sender;
 
 type RequestId = String;
@@ -19,6 +27,8 @@ type RequestId = String;
 #[async_trait]
 impl Actor for Response {
     async fn started(&mut self, pid: Addr<Self>) -> ActorResult<()> {
+        self.pid = pid.downgrade();
+
         let pid_clone = pid.clone();
         send!(pid_clone.listen(pid));
         Produces::ok(())
@@ -32,14 +42,31 @@ impl Actor for Response {
     }
 }
 pub(crate) struct Response {
+    pid: WeakAddr<Self>,
+    broker: WeakAddr<ServiceBroker>,
     config: Arc<Config>,
-    waiters: HashMap<RequestId, Addr<ResponseWaiter>>,
+    waiters: HashMap<RequestId, Waiter>,
     conn: Conn,
 }
 
+// what `pending_requests` reports is kept here so it doesn't need to ask every waiter
+struct Waiter {
+    pid: Addr<ResponseWaiter>,
+    action: String,
+    node_name: String,
+    sent_at: Instant,
+    deadline: Instant,
+}
+
 impl Response {
-    pub(crate) async fn new(config: &Arc<Config>, conn: &Conn) -> Self {
+    pub(crate) async fn new(
+        broker: WeakAddr<ServiceBroker>,
+        config: &Arc<Config>,
+        conn: &Conn,
+    ) -> Self {
         Self {
+            pid: WeakAddr::detached(),
+            broker,
             conn: conn.clone(),
             config: Arc::clone(config),
             waiters: HashMap::new(),
@@ -50,17 +77,72 @@ impl Response {
         &mut self,
         timeout: i32,
         node_name: String,
+        action: String,
         request_id: RequestId,
-        tx: Sender<Value>,
-    ) {
-        let response_waiter_pid = spawn_actor(ResponseWaiter::new(
-            timeout,
-            request_id.clone(),
+        tx: Sender<Result<Value, Error>>,
+    ) -> ActorResult<()> {
+        let max_pending_requests = self.config.max_pending_requests;
+
+        if self.waiters.len() >= max_pending_requests {
+            let _ = tx.send(Err(Error::TooManyPendingRequests(max_pending_requests)));
+            return Err(Error::TooManyPendingRequests(max_pending_requests).into());
+        }
+
+        let sent_at = self.config.clock.now();
+        let waiter = Waiter {
+            pid: spawn_actor(ResponseWaiter::new(
+                self.pid.clone(),
+                &self.config,
+                timeout,
+                request_id.clone(),
+                node_name.clone(),
+                action.clone(),
+                tx,
+            )),
+            action,
             node_name,
-            tx,
-        ));
+            sent_at,
+            deadline: sent_at + Duration::from_millis(timeout.max(0) as u64),
+        };
+
+        self.waiters.insert(request_id, waiter);
 
-        self.waiters.insert(request_id, response_waiter_pid);
+        Produces::ok(())
+    }
+
+    pub(crate) async fn pending_requests(&self) -> ActorResult<Vec<PendingInfo>> {
+        Produces::ok(self.snapshot())
+    }
+
+    pub(crate) async fn log_pending_requests(&self) {
+        for pending in self.snapshot() {
+            warn!(
+                "Request {} to '{}' on {} still pending at shutdown after {}ms",
+                pending.id,
+                pending.action,
+                pending.node_id,
+                pending.elapsed.as_millis()
+            );
+        }
+    }
+
+    fn snapshot(&self) -> Vec<PendingInfo> {
+        let now = self.config.clock.now();
+
+        let mut pending_requests: Vec<PendingInfo> = self
+            .waiters
+            .iter()
+            .map(|(request_id, waiter)| PendingInfo {
+                id: request_id.clone(),
+                action: waiter.action.clone(),
+                node_id: waiter.node_name.clone(),
+                elapsed: now.saturating_duration_since(waiter.sent_at),
+                deadline: waiter.deadline,
+            })
+            .collect();
+
+        pending_requests.sort_by_key(|pending| std::cmp::Reverse(pending.elapsed));
+        pending_requests
     }
 
     pub(crate) async fn listen(&mut self, pid: Addr<Self>) {
@@ -85,16 +167,31 @@ impl Response {
         self.waiters.remove(&request_id);
     }
 
+    /// The `REQ` packet of the request couldn't be published, its caller fails right away
+    pub(crate) async fn publish_timed_out(&mut self, request_id: String, error: Error) {
+        if let Some(waiter) = self.waiters.remove(&request_id) {
+            send!(waiter.pid.fail(error));
+        }
+    }
+
     async fn handle_message(&mut self, msg: Message) -> ActorResult<()> {
-        let response: ResponseMessage = self.config.serializer.deserialize(&msg.data)?;
+        let response: PacketResponse = parse_packet(&self.config, &Channel::Response, &msg.data)?;
         let response_id = response.id.clone();
+        let sender = response.envelope.sender.clone();
 
-        if let Some(response_waiter) = self.waiters.get(&response_id) {
-            let response_waiter = response_waiter.clone();
+        if let Some(waiter) = self.waiters.get(&response_id) {
+            let response_waiter = waiter.pid.clone();
 
             // wether send_response succeeds or fails we should remove it from hashmap
-            let _ = call!(response_waiter.send_response(response)).await;
+            let response_time = call!(response_waiter.send_response(response)).await;
             self.waiters.remove(&response_id);
+
+            // response times of calls refresh the latency measured by PING packets
+            if let (Ok(response_time), Strategy::Latency(_)) =
+                (response_time, &self.config.strategy)
+            {
+                send!(self.broker.record_latency(sender, response_time));
+            }
         }
 
         Produces::ok(())
@@ -125,6 +222,11 @@ impl Actor for ResponseWaiter {
 impl Tick for ResponseWaiter {
     async fn tick(&mut self) -> ActorResult<()> {
         if self.timer.tick() {
+            if let Some(tx) = self.tx.take() {
+                let error = MoleculerError::request_timeout(&self.action, &self.node_name);
+                let _ = tx.send(Err(error.into()));
+            }
+
             send!(self.parent.timeout_reached(self.request_id.clone()))
         }
         Produces::ok(())
@@ -138,36 +240,131 @@ struct ResponseWaiter {
 
     timeout: i32,
     node_name: String,
-    tx: Option<Sender<Value>>,
+    action: String,
+    tx: Option<Sender<Result<Value, Error>>>,
+    sent_at: Instant,
+    slow_request_threshold: Option<Duration>,
+    response_unwrapper: Option<ResponseUnwrapper>,
+    // NATS picks the node answering the request, see `disable_balancer`
+    balanced: bool,
 
+    clock: Arc<dyn Clock>,
     timer: Timer,
 }
 
 impl ResponseWaiter {
-    fn new(timeout: i32, request_id: RequestId, node_name: String, tx: Sender<Value>) -> Self {
+    fn new(
+        parent: WeakAddr<Response>,
+        config: &Config,
+        timeout: i32,
+        request_id: RequestId,
+        node_name: String,
+        action: String,
+        tx: Sender<Result<Value, Error>>,
+    ) -> Self {
         Self {
-            parent: WeakAddr::detached(),
+            parent,
             pid: WeakAddr::detached(),
 
             request_id,
             timeout,
             node_name,
+            action,
             tx: Some(tx),
+            sent_at: config.clock.now(),
+            slow_request_threshold: config.slow_request_threshold(),
+            response_unwrapper: config.response_unwrapper,
+            balanced: config.disable_balancer,
 
-            timer: Timer::default(),
+            clock: Arc::clone(&config.clock),
+            timer: Timer::new(&config.clock),
         }
     }
 
-    async fn send_response(&mut self, response: ResponseMessage) -> ActorResult<()> {
-        if self.node_name != response.sender {
+    async fn fail(&mut self, error: Error) {
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(Err(error));
+        }
+    }
+
+    // returns how long the response took
+    async fn send_response(&mut self, response: PacketResponse) -> ActorResult<Duration> {
+        if !self.balanced && self.node_name != response.envelope.sender {
             // something went wrong here, should handle this error better
             error!("Node name does not match sender")
         }
 
-        // take the tx from actor state and replace it with a none
-        let tx = std::mem::take(&mut self.tx).unwrap();
+        let result = match response.error {
+            Some(error) if !response.success => Err(Error::ActionError(error)),
+            _ => Ok(match self.response_unwrapper {
+                Some(unwrap) => unwrap(response.data, &response.meta),
+                None => response.data,
+            }),
+        };
 
-        tx.send(response.data).unwrap();
-        Produces::ok(())
+        // take the tx from actor state and replace it with a none,
+        // the caller may have already given up waiting so ignore send errors
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(result);
+        }
+
+        let response_time = self.clock.now().duration_since(self.sent_at);
+
+        if self
+            .slow_request_threshold
+            .is_some_and(|threshold| response_time > threshold)
+        {
+            warn!(
+                "Slow request: call to '{}' on {} took {}ms (request id {})",
+                self.action,
+                self.node_name,
+                response_time.as_millis(),
+                self.request_id
+            );
+        }
+
+        Produces::ok(response_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, config::ConfigBuilder};
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn request_times_out_once_the_clock_passes_the_timeout() {
+        let clock = Arc::new(ManualClock::new());
+        let config = ConfigBuilder::default().clock(clock.clone()).build();
+        let (tx, mut rx) = oneshot::channel();
+
+        let _waiter = spawn_actor(ResponseWaiter::new(
+            WeakAddr::detached(),
+            &config,
+            5000,
+            "request-1".to_string(),
+            "node-2".to_string(),
+            "users.get".to_string(),
+            tx,
+        ));
+        clock.wait_for_sleeps(1).await;
+
+        clock.advance(Duration::from_millis(4999));
+        assert!(rx.try_recv().is_err());
+
+        clock.advance(Duration::from_millis(1));
+        let result = tokio::time::timeout(Duration::from_secs(5), rx)
+            .await
+            .expect("the request should time out")
+            .unwrap();
+
+        match result {
+            Err(Error::ActionError(error)) => {
+                assert_eq!(error.name, "RequestTimeoutError");
+                assert_eq!(error.code, 504);
+            }
+            other => panic!("expected a request timeout, got {:?}", other),
+        }
     }
 }
@@ -0,0 +1,229 @@
+/*!
+Time source of the broker, used by request timeouts, heartbeats and node expiry.
+
+The broker uses [SystemClock] unless another [Clock] is set with
+[`ConfigBuilder::clock()`][crate::config::ConfigBuilder::clock()]. Tests can use a
+[ManualClock] to move time forward instantly instead of waiting:
+
+```rust, ignore
+let clock = Arc::new(ManualClock::new());
+
+let config = ConfigBuilder::default()
+    .request_timeout(5000)
+    .clock(clock.clone())
+    .build();
+
+// ...make a call that never gets a response
+
+clock.advance(Duration::from_secs(5));
+// the call now fails with a request timeout
+```
+*/
+
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use act_zero::timer::Tick;
+use act_zero::*;
+use tokio::sync::oneshot;
+
+/// Future returned by [`Clock::sleep()`].
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A source of time.
+pub trait Clock: Debug + Send + Sync {
+    /// The current time.
+    fn now(&self) -> Instant;
+
+    /// Completes once `duration` has passed according to [`now()`][Self::now()].
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// The system's monotonic clock, the default [Clock].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// A [Clock] that only moves forward when [`advance()`][Self::advance()] is called.
+#[derive(Debug)]
+pub struct ManualClock {
+    state: Mutex<ManualClockState>,
+}
+
+#[derive(Debug)]
+struct ManualClockState {
+    now: Instant,
+    sleepers: Vec<(Instant, oneshot::Sender<()>)>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(ManualClockState {
+                now: Instant::now(),
+                sleepers: vec![],
+            }),
+        }
+    }
+
+    /// Move the clock forward, completing the sleeps that end within `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.lock();
+        state.now += duration;
+
+        let now = state.now;
+        let (done, sleeping) = state
+            .sleepers
+            .drain(..)
+            .partition(|(deadline, _)| *deadline <= now);
+        state.sleepers = sleeping;
+
+        for (_, sleeper) in done {
+            let _ = sleeper.send(());
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ManualClockState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.lock().now
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let (tx, rx) = oneshot::channel();
+
+        if duration == Duration::ZERO {
+            let _ = tx.send(());
+        } else {
+            let mut state = self.lock();
+            let deadline = state.now + duration;
+            state.sleepers.push((deadline, tx));
+        }
+
+        Box::pin(async move {
+            let _ = rx.await;
+        })
+    }
+}
+
+/// Calls [`Tick::tick()`] on an actor once a timeout on the [Clock] passes, like the timer of
+/// `act_zero`. Ticks from a timeout that was replaced are ignored by [`tick()`][Self::tick()].
+pub(crate) struct Timer {
+    clock: Arc<dyn Clock>,
+    deadline: Option<Instant>,
+}
+
+impl Timer {
+    pub(crate) fn new(clock: &Arc<dyn Clock>) -> Self {
+        Self {
+            clock: Arc::clone(clock),
+            deadline: None,
+        }
+    }
+
+    /// Returns `true` when the timeout has passed, call it at the start of `tick()`
+    pub(crate) fn tick(&mut self) -> bool {
+        match self.deadline {
+            Some(deadline) if deadline <= self.clock.now() => {
+                self.deadline = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn set_timeout_for_weak<T: Tick>(&mut self, addr: WeakAddr<T>, duration: Duration) {
+        let sleep = self.start(duration);
+
+        tokio::spawn(async move {
+            sleep.await;
+            send!(addr.tick());
+        });
+    }
+
+    // keeps the actor alive until the timeout passes
+    pub(crate) fn set_timeout_for_strong<T: Tick>(&mut self, addr: Addr<T>, duration: Duration) {
+        let sleep = self.start(duration);
+
+        tokio::spawn(async move {
+            sleep.await;
+            send!(addr.tick());
+        });
+    }
+
+    fn start(&mut self, duration: Duration) -> Sleep {
+        self.deadline = Some(self.clock.now() + duration);
+        self.clock.sleep(duration)
+    }
+}
+
+#[cfg(test)]
+impl ManualClock {
+    /// Waits until `count` sleeps are pending, so the clock is only moved forward once the
+    /// timers a test expects were set
+    pub(crate) async fn wait_for_sleeps(&self, count: usize) {
+        let wait = async {
+            while self.lock().sleepers.len() < count {
+                tokio::task::yield_now().await;
+            }
+        };
+
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .expect("the timers should be set");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sleeps_end_when_the_clock_passes_them() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+
+        let short = tokio::spawn(clock.sleep(Duration::from_secs(1)));
+        let long = tokio::spawn(clock.sleep(Duration::from_secs(10)));
+
+        clock.advance(Duration::from_secs(5));
+        tokio::time::timeout(Duration::from_secs(5), short)
+            .await
+            .expect("the short sleep should end")
+            .unwrap();
+        assert!(!long.is_finished());
+        assert_eq!(clock.now() - start, Duration::from_secs(5));
+
+        clock.advance(Duration::from_secs(5));
+        tokio::time::timeout(Duration::from_secs(5), long)
+            .await
+            .expect("the long sleep should end")
+            .unwrap();
+    }
+}
@@ -1,6 +1,8 @@
 use std::cmp::Eq;
 use std::collections::{HashSet, VecDeque};
 use std::hash::Hash;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub(crate) struct QueueSet<T> {
@@ -84,3 +86,116 @@ mod macros {
     };
 }
 }
+
+// larger buffers are dropped instead of pooled, a rare huge packet shouldn't stay allocated
+const MAX_POOLED_BUFFER_CAPACITY: usize = 1024 * 1024;
+
+/// Serialization buffers kept for reuse once their packet is published, up to `size` of them
+#[derive(Debug, Default)]
+pub(crate) struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    size: usize,
+}
+
+impl BufferPool {
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::with_capacity(size)),
+            size,
+        }
+    }
+
+    /// An empty buffer, from the pool when one is available
+    pub(crate) fn take(&self) -> Vec<u8> {
+        if self.size == 0 {
+            return Vec::new();
+        }
+
+        self.buffers
+            .lock()
+            .expect("buffer pool lock poisoned")
+            .pop()
+            .unwrap_or_default()
+    }
+
+    /// Give back a buffer once its packet is published, it is dropped when the pool is full
+    pub(crate) fn put(&self, mut buffer: Vec<u8>) {
+        if self.size == 0 || buffer.capacity() > MAX_POOLED_BUFFER_CAPACITY {
+            return;
+        }
+
+        let mut buffers = self.buffers.lock().expect("buffer pool lock poisoned");
+
+        if buffers.len() < self.size {
+            buffer.clear();
+            buffers.push(buffer);
+        }
+    }
+}
+
+/// Ids seen in the last `ttl`, up to `max_ids` of them: the oldest ones are forgotten first
+#[derive(Debug)]
+pub(crate) struct SeenIds {
+    ttl: Duration,
+    max_ids: usize,
+    ids: Mutex<SeenIdsState>,
+}
+
+#[derive(Debug, Default)]
+struct SeenIdsState {
+    set: HashSet<String>,
+    // in the order they were seen
+    queue: VecDeque<(Instant, String)>,
+}
+
+impl SeenIds {
+    pub(crate) fn new(ttl: Duration, max_ids: usize) -> Self {
+        Self {
+            ttl,
+            max_ids: max_ids.max(1),
+            ids: Mutex::default(),
+        }
+    }
+
+    fn lock(&self, now: Instant) -> MutexGuard<'_, SeenIdsState> {
+        let mut ids = self
+            .ids
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        while let Some((seen_at, _)) = ids.queue.front() {
+            if now.saturating_duration_since(*seen_at) < self.ttl {
+                break;
+            }
+
+            if let Some((_, id)) = ids.queue.pop_front() {
+                ids.set.remove(&id);
+            }
+        }
+
+        ids
+    }
+
+    pub(crate) fn contains(&self, id: &str, now: Instant) -> bool {
+        self.lock(now).set.contains(id)
+    }
+
+    /// Returns `false` if the id was already seen
+    pub(crate) fn insert(&self, id: &str, now: Instant) -> bool {
+        let mut ids = self.lock(now);
+
+        if !ids.set.insert(id.to_string()) {
+            return false;
+        }
+
+        ids.queue.push_back((now, id.to_string()));
+
+        if ids.queue.len() > self.max_ids {
+            if let Some((_, id)) = ids.queue.pop_front() {
+                ids.set.remove(&id);
+            }
+        }
+
+        true
+    }
+}
@@ -0,0 +1,196 @@
+//! Errors sent between nodes in `RES` packets.
+//!
+//! [MoleculerError] serializes with the same `name`, `code` and `type` as the built-in errors of
+//! [Moleculer JS](https://moleculer.services/docs/0.14/errors.html), so JS callers receive the
+//! matching error class. Return one from an action callback to send it to the caller:
+//!
+//! ```rust
+//! use std::error::Error;
+//! use moleculer::{errors::MoleculerError, ActionContext};
+//!
+//! fn get_user(ctx: ActionContext) -> Result<(), Box<dyn Error>> {
+//!     if ctx.params.get("id").is_none() {
+//!         return Err(MoleculerError::validation("Missing 'id' param", serde_json::json!({})).into());
+//!     }
+//!
+//!     ctx.reply(serde_json::json!({"id": 1}));
+//!     Ok(())
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+
+/// An error as sent in the `error` field of a `RES` packet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MoleculerError {
+    pub name: String,
+    pub message: String,
+    pub code: u16,
+    #[serde(rename = "type", default)]
+    pub type_: String,
+    #[serde(default)]
+    pub data: Value,
+    #[serde(default)]
+    pub retryable: bool,
+    #[serde(rename = "nodeID", default, skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<String>,
+}
+
+impl MoleculerError {
+    /// Create a custom error, not retryable by default.
+    pub fn new<S, M, T>(name: S, message: M, code: u16, type_: T) -> Self
+    where
+        S: Into<String>,
+        M: Into<String>,
+        T: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            message: message.into(),
+            code,
+            type_: type_.into(),
+            data: Value::Null,
+            retryable: false,
+            node_id: None,
+        }
+    }
+
+    /// `ServiceNotFoundError` (404): the action is not registered on any (or the given) node.
+    pub fn service_not_found(action: &str, node_id: Option<&str>) -> Self {
+        let message = match node_id {
+            Some(node_id) => format!("Service '{}' is not found on '{}' node.", action, node_id),
+            None => format!("Service '{}' is not found.", action),
+        };
+
+        Self::new("ServiceNotFoundError", message, 404, "SERVICE_NOT_FOUND")
+            .with_data(json!({ "action": action, "nodeID": node_id }))
+            .retryable(true)
+    }
+
+    /// `ServiceNotAvailableError` (404): the action is registered but can't currently be called.
+    pub fn service_not_available(action: &str, node_id: Option<&str>) -> Self {
+        let message = match node_id {
+            Some(node_id) => format!(
+                "Service '{}' is not available on '{}' node.",
+                action, node_id
+            ),
+            None => format!("Service '{}' is not available.", action),
+        };
+
+        Self::new(
+            "ServiceNotAvailableError",
+            message,
+            404,
+            "SERVICE_NOT_AVAILABLE",
+        )
+        .with_data(json!({ "action": action, "nodeID": node_id }))
+        .retryable(true)
+    }
+
+    /// `RequestTimeoutError` (504): no response was received before the request timeout.
+    pub fn request_timeout(action: &str, node_id: &str) -> Self {
+        Self::new(
+            "RequestTimeoutError",
+            format!(
+                "Request is timed out when call '{}' action on '{}' node.",
+                action, node_id
+            ),
+            504,
+            "REQUEST_TIMEOUT",
+        )
+        .with_data(json!({ "action": action, "nodeID": node_id }))
+        .retryable(true)
+    }
+
+    /// `RequestSkippedError` (514): the request's timeout was reached before its callback ran.
+    pub fn request_skipped(action: &str, node_id: &str) -> Self {
+        Self::new(
+            "RequestSkippedError",
+            format!(
+                "Calling '{}' is skipped because timeout reached on '{}' node.",
+                action, node_id
+            ),
+            514,
+            "REQUEST_SKIPPED",
+        )
+        .with_data(json!({ "action": action, "nodeID": node_id }))
+    }
+
+    /// `QueueIsFullError` (429): the action's worker pool and its queue are full.
+    pub fn queue_full(action: &str, node_id: &str) -> Self {
+        Self::new(
+            "QueueIsFullError",
+            format!(
+                "Queue is full. Request '{}' action on '{}' node is rejected.",
+                action, node_id
+            ),
+            429,
+            "QUEUE_FULL",
+        )
+        .with_data(json!({ "action": action, "nodeID": node_id }))
+        .retryable(true)
+    }
+
+    /// `GracefulStopTimeoutError` (503): the node stopped before the action's callback
+    /// finished, see `shutdown_timeout` in [Tracking][crate::config::Tracking].
+    pub fn node_shutting_down(action: &str, node_id: &str) -> Self {
+        Self::new(
+            "GracefulStopTimeoutError",
+            format!(
+                "Request '{}' action is aborted, '{}' node is shutting down.",
+                action, node_id
+            ),
+            503,
+            "GRACEFUL_STOP_TIMEOUT",
+        )
+        .with_data(json!({ "action": action, "nodeID": node_id }))
+    }
+
+    /// `ValidationError` (422): the params sent to the action are invalid.
+    pub fn validation<M: Into<String>>(message: M, data: Value) -> Self {
+        Self::new("ValidationError", message, 422, "VALIDATION_ERROR").with_data(data)
+    }
+
+    /// `MaxCallLevelError` (500): the nested call level reached `max_call_level`.
+    pub fn max_call_level(level: i32, node_id: &str) -> Self {
+        Self::new(
+            "MaxCallLevelError",
+            format!(
+                "Request level is reached the limit ({}) on '{}' node.",
+                level, node_id
+            ),
+            500,
+            "MAX_CALL_LEVEL",
+        )
+        .with_data(json!({ "level": level, "nodeID": node_id }))
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = data;
+        self
+    }
+
+    pub fn retryable(mut self, retryable: bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    /// Wraps any error returned from an action callback, errors that are already a
+    /// [MoleculerError] are passed through unchanged.
+    pub(crate) fn from_callback_error(err: &(dyn std::error::Error + 'static)) -> Self {
+        match err.downcast_ref::<MoleculerError>() {
+            Some(moleculer_error) => moleculer_error.clone(),
+            None => Self::new("MoleculerError", err.to_string(), 500, ""),
+        }
+    }
+}
+
+impl fmt::Display for MoleculerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.name, self.code, self.message)
+    }
+}
+
+impl std::error::Error for MoleculerError {}
@@ -0,0 +1,97 @@
+/*!
+Liveness and readiness endpoints for container orchestrators, enabled with the `health` feature.
+
+- `GET /live` returns `200` as long as the process is running
+- `GET /ready` returns `200` once the transporter is connected and this node has sent its
+  `DISCOVER` packet, `503` otherwise (including while the transporter is reconnecting)
+
+```rust, ignore
+let service_broker = ServiceBroker::new(config).add_service(greeter_service);
+
+tokio::spawn(moleculer::health::serve(service_broker.clone(), "0.0.0.0:3001"));
+
+service_broker.start().await;
+```
+*/
+
+use std::io;
+
+use log::{debug, info};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::ServiceBroker;
+
+/// Serve the health endpoints on `addr` until the process exits.
+pub async fn serve<A: ToSocketAddrs>(broker: ServiceBroker, addr: A) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Health checks listening on {}", listener.local_addr()?);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let broker = broker.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(broker, stream).await {
+                debug!("Unable to respond to health check: {}", err);
+            }
+        });
+    }
+}
+
+async fn handle_connection(broker: ServiceBroker, stream: TcpStream) -> io::Result<()> {
+    let request = read_request_head(&stream).await?;
+    let path = request
+        .lines()
+        .next()
+        .and_then(|request_line| request_line.split_whitespace().nth(1))
+        .unwrap_or_default();
+
+    let (status, body) = match path {
+        "/live" => ("200 OK", "OK"),
+        "/ready" if broker.is_ready().await => ("200 OK", "OK"),
+        "/ready" => ("503 Service Unavailable", "NOT READY"),
+        _ => ("404 Not Found", "NOT FOUND"),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+
+    write_all(&stream, response.as_bytes()).await
+}
+
+// only the request line is needed, stop reading at the end of the headers
+async fn read_request_head(stream: &TcpStream) -> io::Result<String> {
+    let mut request = Vec::with_capacity(1024);
+    let mut buf = [0; 1024];
+
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < 8192 {
+        stream.readable().await?;
+
+        match stream.try_read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => request.extend_from_slice(&buf[..read]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+async fn write_all(stream: &TcpStream, mut bytes: &[u8]) -> io::Result<()> {
+    while !bytes.is_empty() {
+        stream.writable().await?;
+
+        match stream.try_write(bytes) {
+            Ok(written) => bytes = &bytes[written..],
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
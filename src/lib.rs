@@ -99,20 +99,62 @@ struct ActionMessage {
 mod data_structures;
 mod util;
 
+pub mod buffer;
+pub mod cache;
+pub mod clock;
 pub mod config;
+pub mod errors;
+#[cfg(feature = "health")]
+pub mod health;
+pub mod metrics;
 pub mod service;
+pub mod stream;
 
 mod broker;
 mod channels;
 mod nats;
 
+/// Entry points of the fuzz targets in `fuzz/`, enabled with the `fuzzing` feature. Not part of
+/// the public API.
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing {
+    use std::sync::OnceLock;
+
+    use crate::config::{Channel, Config, ConfigBuilder};
+
+    /// Parses `data` as an inbound packet of `channel`, malformed packets must be rejected
+    /// without panicking
+    pub fn parse_packet(channel: &Channel, data: &[u8]) {
+        static CONFIG: OnceLock<Config> = OnceLock::new();
+
+        let config = CONFIG.get_or_init(|| ConfigBuilder::default().build());
+
+        crate::channels::fuzz_packet(config, channel, data);
+    }
+}
+
 use act_zero::runtimes::tokio::spawn_actor;
 use act_zero::*;
-use config::Config;
-use serde_json::Value;
+use broker::EventHeaders;
+use config::{Channel, Config, ConfigBuilder};
+use errors::MoleculerError;
+use futures_core::Stream;
+use serde::Serialize;
+use serde_json::{json, Value};
 use service::Service;
+use std::{
+    collections::HashMap,
+    future::{poll_fn, Future},
+    sync::Arc,
+    task::Poll,
+    time::{Duration, Instant, SystemTime},
+};
 use thiserror::Error;
-use tokio::sync::oneshot::{self, error};
+use tokio::sync::{
+    mpsc,
+    oneshot::{self, error},
+};
 
 #[doc(hidden)]
 #[derive(Error, Debug)]
@@ -120,10 +162,293 @@ pub enum Error {
     #[error("Timeout reached waiting for response")]
     ReceiveError(#[from] error::RecvError),
 
+    #[error("Action returned an error: {0}")]
+    ActionError(#[from] MoleculerError),
+
+    #[error("Too many pending requests, limit is {0}")]
+    TooManyPendingRequests(usize),
+
+    #[error("Node '{0}' not found")]
+    NodeNotFound(String),
+
+    #[error("Unable to publish: {0}")]
+    PublishFailed(String),
+
+    #[error("Publish to {0} timed out, see `publish_timeout`")]
+    PublishTimeout(String),
+
+    #[error("No listener for event '{0}'")]
+    NoEventListeners(String),
+
+    #[error("The broker is stopped")]
+    BrokerStopped,
+
     #[error("Unknown error")]
     UnknownError,
 }
 
+/// A change to the nodes and services known to the broker, received from
+/// [`ServiceBroker::registry_events()`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistryChange {
+    /// A node sent its first `INFO` packet
+    NodeConnected { node_id: String },
+    /// A known node sent an updated `INFO` packet, `diff` is what changed since its previous one
+    NodeUpdated { node_id: String, diff: NodeDiff },
+    /// A node sent a `DISCONNECT` packet, or stopped sending heartbeats when `unexpected`
+    NodeDisconnected { node_id: String, unexpected: bool },
+    /// A service became available on a node
+    ServiceAdded { node_id: String, service: String },
+    /// A service is no longer available on a node, because it was removed or the node
+    /// disconnected
+    ServiceRemoved { node_id: String, service: String },
+}
+
+/// The state of the connection of a broker to its transporter and to the other nodes, see
+/// [`ServiceBroker::connection_state()`]. A broker goes from `Connecting` to `Connected` to
+/// `Discovered`, then from `Reconnecting` back to `Connected` and `Discovered` each time the
+/// transporter reconnects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The broker isn't started yet, or is connecting to the transporter
+    Connecting,
+    /// The transporter is connected, this node hasn't announced itself and asked the other
+    /// nodes for their services yet. Calls and events aren't sent yet, or are still held in
+    /// the send buffer after a reconnect
+    Connected,
+    /// This node sent its `INFO` and `DISCOVER` packets: it is ready, see
+    /// [`ServiceBroker::is_ready()`]. Remote nodes are added to the registry as their `INFO`
+    /// packets arrive
+    Discovered,
+    /// The connection to the transporter was lost and the client is reconnecting, see
+    /// `send_buffer_size` in [Config] for what happens to the calls and events sent meanwhile
+    Reconnecting,
+    /// The broker couldn't connect, or gave up reconnecting, see
+    /// [ReconnectPolicy][config::ReconnectPolicy]
+    Failed,
+    /// The broker was [stopped][ServiceBroker::stop()]
+    Stopped,
+}
+
+impl ConnectionState {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            0 => ConnectionState::Connecting,
+            1 => ConnectionState::Connected,
+            2 => ConnectionState::Discovered,
+            3 => ConnectionState::Reconnecting,
+            4 => ConnectionState::Failed,
+            _ => ConnectionState::Stopped,
+        }
+    }
+}
+
+/// The changes between two `INFO` packets of a node, carried by
+/// [`RegistryChange::NodeUpdated`] and the `diff` of the `$node.updated` event so routes can be
+/// updated incrementally. Names are sorted. In the event:
+/// ```json
+/// {
+///     "nodeID": "node-1",
+///     "diff": {
+///         "addedActions": ["users.create"],
+///         "removedActions": [],
+///         "addedEvents": [],
+///         "removedEvents": ["user.created"],
+///         "changedMetadata": { "region": "eu-west-1" },
+///         "removedMetadata": ["zone"]
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeDiff {
+    pub added_actions: Vec<String>,
+    pub removed_actions: Vec<String>,
+    pub added_events: Vec<String>,
+    pub removed_events: Vec<String>,
+    /// Metadata keys added or with a new value, with their new value
+    pub changed_metadata: serde_json::Map<String, Value>,
+    pub removed_metadata: Vec<String>,
+}
+
+impl NodeDiff {
+    /// Whether the node's actions, events and metadata are unchanged
+    pub fn is_empty(&self) -> bool {
+        self == &NodeDiff::default()
+    }
+}
+
+/// A call waiting for its response, see [`ServiceBroker::pending_requests()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingInfo {
+    /// Id of the request packet
+    pub id: String,
+    pub action: String,
+    /// Node the request was sent to
+    pub node_id: String,
+    /// Time since the request was sent
+    pub elapsed: Duration,
+    /// When the call times out
+    pub deadline: Instant,
+}
+
+/// Options of [`ServiceBroker::call_with_options()`], calls made from a
+/// [Context][service::Context] also carry the context's ids and `meta` in them.
+#[derive(Debug, Default, Clone)]
+pub struct CallOptions {
+    /// Fully-qualified name of the action making the call, `None` for top-level calls
+    pub(crate) caller: Option<String>,
+    /// Level of the calling context, `0` for top-level calls
+    pub(crate) level: i32,
+    /// Id of the calling context, sent as the request's `parentID`
+    pub(crate) parent_id: Option<String>,
+    /// Id of the first request in the chain, a new one is generated for top-level calls
+    pub(crate) request_id: Option<String>,
+    pub(crate) meta: Value,
+    /// The params are sent as a stream of chunks after the request, see [stream]
+    pub(crate) stream: bool,
+    /// Nodes tried in order instead of balancing the call, see
+    /// [`fallback_nodes()`][Self::fallback_nodes()]
+    pub(crate) fallback_nodes: Vec<String>,
+    /// Send the request to this node instead of balancing it
+    pub(crate) node_id: Option<String>,
+    /// Milliseconds to wait for the response, `request_timeout` of [Config] when `None`
+    pub(crate) timeout: Option<i32>,
+    /// Retries of the call, see [`retries()`][Self::retries()]
+    pub(crate) retries: Option<u32>,
+}
+
+impl CallOptions {
+    /// Call the action on these nodes, in order: the next node is called when one fails with a
+    /// retryable error (it doesn't have the action, is draining or timed out...). Returns the
+    /// first response, or the error of the last node called.
+    ///
+    /// All the attempts share the timeout of the call, see [`timeout()`][Self::timeout()]: each
+    /// node gets the time left, and no other node is called once it has passed.
+    /// ```rust, ignore
+    /// let options = CallOptions::default().fallback_nodes(vec!["billing-1", "billing-2"]);
+    /// let invoice = broker.call_with_options("billing.invoice", params, options).await?;
+    /// ```
+    pub fn fallback_nodes<S: Into<String>>(mut self, node_ids: Vec<S>) -> Self {
+        self.fallback_nodes = node_ids.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Retry the call this many times when it fails with a retryable error, each attempt
+    /// balanced again and given the whole timeout. Overrides the `retryPolicy` of the action's
+    /// schema, which overrides the [RetryPolicy][config::RetryPolicy] of [Config]. `0` disables
+    /// the retries, ignored with [`fallback_nodes()`][Self::fallback_nodes()].
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// The meta of the request, its fields win over the `default_meta` of [Config].
+    pub fn meta(mut self, meta: Value) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    /// Milliseconds to wait for the response, instead of the `request_timeout` of [Config]. It
+    /// is sent with the request, the handling node's
+    /// [`Context::deadline`][service::Context::deadline] is that far from when it received it.
+    /// ```rust, ignore
+    /// let options = CallOptions::default().timeout(500);
+    /// let quote = broker.call_with_options("pricing.quote", params, options).await?;
+    /// ```
+    pub fn timeout(mut self, timeout: i32) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+/// Options of [`ServiceBroker::emit_with_options()`] and
+/// [`ServiceBroker::broadcast_with_options()`].
+#[derive(Debug, Default, Clone)]
+pub struct EventOptions {
+    node_id: Option<String>,
+    flush: bool,
+    ttl: Option<Duration>,
+    meta: Value,
+    /// Fully-qualified name of the action emitting the event, `None` for top-level events
+    pub(crate) caller: Option<String>,
+    /// Level of the emitting context, `0` for top-level events
+    pub(crate) level: i32,
+    /// Id of the emitting context, sent as the event's `parentID`
+    pub(crate) parent_id: Option<String>,
+    /// Id of the first request in the chain
+    pub(crate) request_id: Option<String>,
+}
+
+impl EventOptions {
+    /// Send the event to this node only, ex: to invalidate its cache. The node is picked
+    /// explicitly so the event isn't balanced between the nodes listening to it.
+    pub fn node_id<S: Into<String>>(mut self, node_id: S) -> Self {
+        self.node_id = Some(node_id.into());
+        self
+    }
+
+    /// Wait until the event was flushed to the transporter, see [`ServiceBroker::flush()`].
+    pub fn flush(mut self) -> Self {
+        self.flush = true;
+        self
+    }
+
+    /// How long the event is worth handling, ex: a price update superseded by the next one.
+    /// Sent in the meta of the event as `$ttl` (milliseconds) with the time it was emitted as
+    /// `$emittedAt` (milliseconds since the unix epoch), listeners check it with
+    /// [`Context::is_expired()`][service::Context::is_expired()].
+    ///
+    /// The broker still delivers expired events, skipping them is up to the listeners.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// The meta of the event, its fields win over the `default_meta` of [Config].
+    pub fn meta(mut self, meta: Value) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    // the meta of the event with its ttl, and its place in the request chain
+    fn event_headers(self) -> EventHeaders {
+        let mut meta = match &self.meta {
+            Value::Object(meta) => meta.clone(),
+            _ => serde_json::Map::new(),
+        };
+
+        if let Some(ttl) = self.ttl {
+            let emitted_at = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+
+            meta.insert("$ttl".to_string(), json!(ttl.as_millis() as u64));
+            meta.insert(
+                "$emittedAt".to_string(),
+                json!(emitted_at.as_millis() as u64),
+            );
+        }
+
+        EventHeaders {
+            meta: Value::Object(meta),
+            level: self.level + 1,
+            caller: self.caller,
+            parent_id: self.parent_id,
+            request_id: self.request_id,
+        }
+    }
+}
+
+// aborting the task drops the stream sender, which ends the handler's stream
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
 #[allow(dead_code)]
 pub(crate) mod built_info {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
@@ -141,9 +466,19 @@ pub(crate) mod built_info {
 /// // call an action
 /// let result = broker.call("math.add", json!{"a": 1, "b": c}).await?;
 /// ```
+///
+/// The broker runs on the [tokio](https://docs.rs/tokio) runtime, it must be created from within
+/// a tokio runtime. Lifecycle:
+/// 1. [`new()`][Self::new()] or [`builder()`][Self::builder()] spawns the broker, which connects
+///    to the transporter and announces itself to the other nodes
+/// 2. services added with [`add_service()`][Self::add_service()] or removed with
+///    [`remove_service()`][Self::remove_service()] are announced with updated `INFO` packets
+/// 3. [`start()`][Self::start()] waits until the broker is stopped, with
+///    [`stop()`][Self::stop()] or on `SIGTERM`/`SIGINT`
 #[derive(Clone)]
 pub struct ServiceBroker {
     addr: Addr<broker::ServiceBroker>,
+    connection: Arc<broker::ConnectionStatus>,
 }
 
 /// An alias to [service::Context\<service::Event>][service::Context].
@@ -158,12 +493,26 @@ pub type ActionContext = service::Context<service::Action>;
 impl ServiceBroker {
     /// Create new service broker, takes [Config] struct.
     pub fn new(config: Config) -> ServiceBroker {
+        let connection = Arc::new(broker::ConnectionStatus::new());
+
         ServiceBroker {
-            addr: spawn_actor(broker::ServiceBroker::new(config)),
+            addr: spawn_actor(broker::ServiceBroker::new(config, Arc::clone(&connection))),
+            connection,
         }
     }
 
-    /// Add a service to the service broker.
+    /// Create a [ServiceBrokerBuilder] to set the config and services before spawning the broker.
+    pub fn builder() -> ServiceBrokerBuilder {
+        ServiceBrokerBuilder::default()
+    }
+
+    /// Add a service to the service broker, before or after it has started.
+    ///
+    /// The service's [started hook][Service::set_started()] runs first, then the `seq` is bumped
+    /// and an updated `INFO` packet is sent. Its actions can be called on this node right away,
+    /// but other nodes only route to them once they received the `INFO` packet: until then
+    /// their calls fail with `SERVICE_NOT_FOUND`, callers can retry or wait for a
+    /// [`RegistryChange::ServiceAdded`] event.
     pub fn add_service(self, service: Service) -> Self {
         send!(self.addr.add_service(service));
         self
@@ -176,37 +525,539 @@ impl ServiceBroker {
         self
     }
 
-    /// Starts the service, this will run forever until your application exits.
+    /// Remove a service from the running broker, returns `false` if it had no service with
+    /// that name.
+    ///
+    /// The other nodes are sent an updated `INFO` packet so they stop routing to the service.
+    /// Requests for its actions that are already running complete, new ones are answered with
+    /// a `SERVICE_NOT_FOUND` error.
+    pub async fn remove_service<S: Into<String>>(&self, name: S) -> bool {
+        call!(self.addr.remove_service(name.into()))
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Starts the service, this will run forever until your application exits
+    /// or [`stop()`][Self::stop()] is called.
     pub async fn start(self) {
         self.addr.termination().await
     }
 
+    /// Stops taking new work without disconnecting, for graceful shutdowns.
+    ///
+    /// The broker sends an `INFO` packet without its actions so other nodes stop routing calls
+    /// to it, and answers requests that arrive in the meantime with a retryable
+    /// `SERVICE_NOT_AVAILABLE` error. Events are still handled. Returns once the callbacks of the
+    /// requests already received have finished, call [`stop()`][Self::stop()] afterwards to
+    /// disconnect.
+    /// ```rust, ignore
+    /// broker.drain().await;
+    /// broker.stop().await;
+    /// ```
+    pub async fn drain(&self) {
+        if let Ok(in_flight) = call!(self.addr.drain()).await {
+            in_flight.idle().await;
+        }
+    }
+
+    /// Sends a `DISCONNECT` packet to the other nodes, closes the transporter connection
+    /// and stops the broker.
+    ///
+    /// With [Tracking][config::Tracking] enabled the broker is drained first, requests still
+    /// running after its `shutdown_timeout` are answered with an error and abandoned.
+    pub async fn stop(&self) {
+        if let Ok(Some((in_flight, mut grace_period))) = call!(self.addr.start_shutdown()).await {
+            let mut idle = Box::pin(in_flight.idle());
+
+            let finished = poll_fn(|cx| match idle.as_mut().poll(cx) {
+                Poll::Ready(()) => Poll::Ready(true),
+                Poll::Pending => grace_period.as_mut().poll(cx).map(|()| false),
+            })
+            .await;
+
+            if !finished {
+                let _ = call!(self.addr.abort_in_flight()).await;
+            }
+        }
+
+        let _ = call!(self.addr.stop()).await;
+    }
+
     /// Request/Response style call
     /// Call an action directly with params serialized into
     /// [serde_json::Value](https://docs.rs/serde_json/1.0.64/serde_json/value/index.html) and `await` on the result
     pub async fn call<S: Into<String>>(self, action: S, params: Value) -> Result<Value, Error> {
+        self.call_with_options(action, params, CallOptions::default())
+            .await
+    }
+
+    /// Call an action with a stream of byte chunks as params, ex: to upload a file. The handler
+    /// reads them from [`Context::stream`][service::Context::stream], see [stream].
+    ///
+    /// Each chunk is read from `chunks` once the previous one was handed to the transporter.
+    /// Dropping the returned future aborts the call, the handler's stream then ends with an
+    /// error. Sending stops once the handler replies, even if `chunks` has more.
+    /// ```rust, ignore
+    /// let file = tokio_util::io::ReaderStream::new(File::open("avatar.png").await?)
+    ///     .map(|chunk| chunk.unwrap().to_vec());
+    ///
+    /// let result = broker.call_stream("users.uploadAvatar", Box::pin(file)).await?;
+    /// ```
+    pub async fn call_stream<S, C>(&self, action: S, chunks: C) -> Result<Value, Error>
+    where
+        S: Into<String>,
+        C: Stream<Item = Vec<u8>> + Send + Unpin + 'static,
+    {
         let (tx, rx) = oneshot::channel();
 
-        send!(self.addr.call(action.into(), params, tx));
-        let response_value = rx.await?;
+        let options = CallOptions {
+            stream: true,
+            ..CallOptions::default()
+        };
+
+        // the call failed to start when there is no sender, the error is sent to `rx`
+        let upload = match call!(self.addr.call_stream(action.into(), options, tx)).await {
+            Ok(sender) => Some(AbortOnDrop(tokio::spawn(sender.send_all(chunks)))),
+            Err(_) => None,
+        };
+
+        let result = rx.await?;
+        drop(upload);
+
+        result
+    }
+
+    /// Call an action that takes no params, sends `params: {}` like Moleculer JS handlers expect.
+    pub async fn call_no_params<S: Into<String>>(self, action: S) -> Result<Value, Error> {
+        self.call(action, Value::Object(Default::default())).await
+    }
+
+    /// Call an action with [CallOptions].
+    pub async fn call_with_options<S: Into<String>>(
+        self,
+        action: S,
+        params: Value,
+        options: CallOptions,
+    ) -> Result<Value, Error> {
+        let (tx, rx) = oneshot::channel();
+
+        send!(self.addr.call(action.into(), params, options, tx));
+
+        rx.await?
+    }
+
+    /// Returns `true` once the transporter is connected and this node has sent its `DISCOVER` packet.
+    /// Returns `false` while the transporter is reconnecting. The same as a
+    /// [`connection_state()`][Self::connection_state()] of [`ConnectionState::Discovered`].
+    pub async fn is_ready(&self) -> bool {
+        self.connection_state() == ConnectionState::Discovered
+    }
+
+    /// The state of the connection to the transporter and the cluster. It is read from an
+    /// atomic shared by the handles of the broker, without waiting for the broker, so it can be
+    /// polled often, ex: by a gateway checking the brokers of its pool before each call.
+    pub fn connection_state(&self) -> ConnectionState {
+        self.connection.get()
+    }
+
+    /// Receive the changes of [`connection_state()`][Self::connection_state()], ex: to take a
+    /// broker out of a pool while it reconnects. Only the changes after this call are received.
+    /// ```rust, ignore
+    /// let mut states = broker.connection_states();
+    ///
+    /// while let Some(state) = states.recv().await {
+    ///     pool.set_available(&broker_id, state == ConnectionState::Discovered);
+    /// }
+    /// ```
+    pub fn connection_states(&self) -> mpsc::UnboundedReceiver<ConnectionState> {
+        self.connection.subscribe()
+    }
+
+    /// The NATS subjects this node is subscribed to, sorted, ex: `MOL.REQ.node-1`, to check
+    /// that it listens where calls are sent. Wildcard subjects are listed as subscribed, and
+    /// subscriptions stay listed while the client restores them after a reconnect.
+    pub async fn active_subscriptions(&self) -> Vec<String> {
+        call!(self.addr.active_subscriptions())
+            .await
+            .unwrap_or_default()
+    }
+
+    /// The calls waiting for a response, oldest first, ex: to find which actions or nodes a
+    /// stuck gateway is waiting for. Their number is capped by `max_pending_requests` in
+    /// [Config]. The requests still pending when the broker [stops][Self::stop()] are logged.
+    pub async fn pending_requests(&self) -> Vec<PendingInfo> {
+        call!(self.addr.pending_requests())
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Number of requests waiting for a worker, by action, for the actions with a
+    /// [WorkerPool][service::WorkerPool].
+    pub async fn action_queue_depths(&self) -> HashMap<String, usize> {
+        call!(self.addr.action_queue_depths())
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Number of events waiting for their listener, by event, for the events with a
+    /// [ListenerConcurrency][service::ListenerConcurrency] queue.
+    pub async fn event_queue_depths(&self) -> HashMap<String, usize> {
+        call!(self.addr.event_queue_depths())
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Describe the actions of the local services, and of the remote services when
+    /// `include_remote` is set, ex: to generate API gateway documentation. Actions are sorted by
+    /// name so the output is the same between runs.
+    /// ```rust, ignore
+    /// let actions = broker.describe_actions(false).await;
+    /// std::fs::write("actions.json", serde_json::to_string_pretty(&actions)?)?;
+    /// ```
+    pub async fn describe_actions(&self, include_remote: bool) -> Vec<service::ActionDescription> {
+        call!(self.addr.describe_actions(include_remote))
+            .await
+            .unwrap_or_default()
+    }
+
+    /// The nodes the action can be called on, this one included, sorted by node id: a snapshot of
+    /// the registry, ex: to build the routing table of an API gateway. Nodes that stopped sending
+    /// heartbeats aren't listed, the ones failing [liveness checks][config::LivenessChecks] are
+    /// listed as not [available][service::Endpoint::available].
+    /// ```rust, ignore
+    /// let nodes: Vec<String> = broker
+    ///     .action_endpoints("users.get")
+    ///     .await
+    ///     .into_iter()
+    ///     .filter(|endpoint| endpoint.available)
+    ///     .map(|endpoint| endpoint.node_id)
+    ///     .collect();
+    /// ```
+    pub async fn action_endpoints<S: Into<String>>(&self, action: S) -> Vec<service::Endpoint> {
+        call!(self.addr.action_endpoints(action.into()))
+            .await
+            .unwrap_or_default()
+    }
+
+    /// The services of every node, this one included, sorted by name then node id: a snapshot
+    /// of the registry, see [`action_endpoints()`][Self::action_endpoints()].
+    pub async fn service_list(&self) -> Vec<service::ServiceInfo> {
+        call!(self.addr.service_list()).await.unwrap_or_default()
+    }
+
+    /// Number of requests and events held until the transporter reconnects, see
+    /// `send_buffer_size` in [Config].
+    pub async fn send_buffer_depth(&self) -> usize {
+        call!(self.addr.send_buffer_depth())
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Number of inbound packets dropped per channel because they couldn't be parsed.
+    pub async fn malformed_packets(&self) -> HashMap<Channel, u64> {
+        call!(self.addr.malformed_packets())
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Average latency measured to each node, only measured with [`Strategy::Latency`][config::Strategy::Latency].
+    pub async fn node_latencies(&self) -> HashMap<String, Duration> {
+        call!(self.addr.node_latencies()).await.unwrap_or_default()
+    }
+
+    /// Nodes of the [liveness checks][config::LivenessChecks] that stopped answering `PING`s,
+    /// sorted.
+    pub async fn degraded_nodes(&self) -> Vec<String> {
+        call!(self.addr.degraded_nodes()).await.unwrap_or_default()
+    }
 
-        Ok(response_value)
+    /// Receive the changes to the nodes and services known to the broker, ex: to rebuild the
+    /// routes of an API gateway. The same changes are emitted to local services as the
+    /// `$node.connected`, `$node.updated`, `$node.disconnected` and `$services.changed` events,
+    /// `$node.updated` carries the [NodeDiff] of the node.
+    ///
+    /// Changes are sent as they're processed without deduplication: a node that repeatedly
+    /// misses heartbeats and comes back produces a `NodeDisconnected` then a `NodeConnected`
+    /// every time.
+    /// ```rust, ignore
+    /// let mut registry_events = broker.registry_events();
+    ///
+    /// while let Some(change) = registry_events.recv().await {
+    ///     rebuild_routes(change);
+    /// }
+    /// ```
+    pub fn registry_events(&self) -> mpsc::UnboundedReceiver<RegistryChange> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        send!(self.addr.subscribe_registry_events(tx));
+        rx
     }
 
-    /// Emits a balanced event to one of the nodes.
+    /// Emits a balanced event to one node of each group listening to it, a group being the
+    /// listeners of the same service.
+    ///
+    /// The group of a service on this node listening to the event is delivered to it directly,
+    /// without going through the transporter, the other nodes of that group don't receive it.
+    /// The other groups are sent the event on one of their nodes. An event no node listens to
+    /// is handled according to `unhandled_events` in [Config].
     pub fn emit<S: Into<String>>(&self, event: S, params: Value) {
-        send!(self.addr.emit(event.into(), params))
+        self.emit_detached(event.into(), params, EventOptions::default())
+    }
+
+    // emits without waiting for the broker, which logs the errors
+    pub(crate) fn emit_detached(&self, event: String, params: Value, options: EventOptions) {
+        send!(self.addr.emit(event, params, options.event_headers(), None))
+    }
+
+    /// Emits an event with [EventOptions], returns [`Error::NodeNotFound`] when the target
+    /// `node_id` isn't in the registry and [`Error::PublishFailed`] when the event couldn't be
+    /// flushed. Returns [`Error::NoEventListeners`] when no node listens to the event and
+    /// `unhandled_events` in [Config] is [`UnhandledEvents::Error`][config::UnhandledEvents::Error].
+    /// ```rust, ignore
+    /// let options = EventOptions::default().node_id("node-2");
+    /// broker.emit_with_options("cache.clean", json!({}), options).await?;
+    /// ```
+    pub async fn emit_with_options<S: Into<String>>(
+        &self,
+        event: S,
+        params: Value,
+        options: EventOptions,
+    ) -> Result<(), Error> {
+        let flush = options.flush;
+        let node_id = options.node_id.clone();
+        let headers = options.event_headers();
+
+        match node_id {
+            Some(node_id) => {
+                self.emit_to_node(node_id, event.into(), params, headers, false)
+                    .await?
+            }
+            None => {
+                let (tx, rx) = oneshot::channel();
+                send!(self.addr.emit(event.into(), params, headers, Some(tx)));
+                rx.await??
+            }
+        }
+
+        if flush {
+            self.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Broadcasts an event with [EventOptions], see
+    /// [`emit_with_options()`][Self::emit_with_options()].
+    pub async fn broadcast_with_options<S: Into<String>>(
+        &self,
+        event: S,
+        params: Value,
+        options: EventOptions,
+    ) -> Result<(), Error> {
+        let flush = options.flush;
+        let node_id = options.node_id.clone();
+        let headers = options.event_headers();
+
+        match node_id {
+            Some(node_id) => {
+                self.emit_to_node(node_id, event.into(), params, headers, true)
+                    .await?
+            }
+            None => send!(self.addr.broadcast(event.into(), params, headers)),
+        }
+
+        if flush {
+            self.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits until the packets sent so far (ex: by [`emit()`][Self::emit()]) were flushed to the
+    /// transporter, to apply back-pressure when sending many events.
+    ///
+    /// Returns [`Error::PublishTimeout`] if the transporter doesn't confirm within the
+    /// `publish_timeout` of [Config], [`Error::PublishFailed`] if the flush fails or while it is
+    /// disconnected and packets are held in the send buffer.
+    /// ```rust, ignore
+    /// for user in users {
+    ///     broker.emit("user.updated", json!({ "id": user.id }));
+    /// }
+    ///
+    /// broker.flush().await?;
+    /// ```
+    pub async fn flush(&self) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+
+        send!(self.addr.flush(tx));
+
+        rx.await?
+    }
+
+    async fn emit_to_node(
+        &self,
+        node_id: String,
+        event: String,
+        params: Value,
+        headers: EventHeaders,
+        broadcast: bool,
+    ) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+
+        send!(self
+            .addr
+            .emit_to_node(node_id, event, params, headers, broadcast, tx));
+
+        rx.await?
     }
 
     /// Emits an event to all the nodes that can handle the event.
+    ///
+    /// Listeners on this node are called directly, the other nodes are sent the event through
+    /// the transporter. Each node receives it once.
     pub fn broadcast<S: Into<String>>(&self, event: S, params: Value) {
-        send!(self.addr.broadcast(event.into(), params))
+        self.broadcast_detached(event.into(), params, EventOptions::default())
+    }
+
+    pub(crate) fn broadcast_detached(&self, event: String, params: Value, options: EventOptions) {
+        send!(self.addr.broadcast(event, params, options.event_headers()))
+    }
+
+    /// A [BrokerHandle] to call actions and emit events from the tasks of the application,
+    /// while this broker keeps the control of its lifecycle.
+    pub fn handle(&self) -> BrokerHandle {
+        BrokerHandle {
+            addr: self.addr.downgrade(),
+        }
     }
 }
 
-#[doc(hidden)]
-impl From<Addr<broker::ServiceBroker>> for ServiceBroker {
-    fn from(addr: Addr<broker::ServiceBroker>) -> Self {
-        Self { addr }
+/// A cheap to clone handle to a [ServiceBroker], created with [`ServiceBroker::handle()`], to
+/// call actions and emit events from many tasks, ex: the request handlers of a web server.
+///
+/// The handle doesn't keep the broker running, it can't [start][ServiceBroker::start()] or
+/// [stop][ServiceBroker::stop()] it either. Once the broker stopped its methods return
+/// [`Error::BrokerStopped`].
+/// ```rust, ignore
+/// let handle = broker.handle();
+///
+/// tokio::spawn(async move {
+///     let user = handle.call("users.get", json!({ "id": 1 })).await?;
+/// });
+///
+/// broker.start().await;
+/// ```
+#[derive(Clone, Debug)]
+pub struct BrokerHandle {
+    addr: WeakAddr<broker::ServiceBroker>,
+}
+
+impl BrokerHandle {
+    /// Calls an action, see [`ServiceBroker::call()`].
+    pub async fn call<S: Into<String>>(&self, action: S, params: Value) -> Result<Value, Error> {
+        self.call_with_options(action, params, CallOptions::default())
+            .await
+    }
+
+    /// Calls an action with [CallOptions], see [`ServiceBroker::call_with_options()`].
+    pub async fn call_with_options<S: Into<String>>(
+        &self,
+        action: S,
+        params: Value,
+        options: CallOptions,
+    ) -> Result<Value, Error> {
+        let (tx, rx) = oneshot::channel();
+
+        send!(self.addr.call(action.into(), params, options, tx));
+
+        match rx.await {
+            Ok(result) => result,
+            Err(err) => Err(self.stopped_or(err.into()).await),
+        }
+    }
+
+    /// Emits a balanced event, see [`ServiceBroker::emit()`].
+    pub async fn emit<S: Into<String>>(&self, event: S, params: Value) -> Result<(), Error> {
+        // a failed emit was logged by the broker, like with `ServiceBroker::emit()`
+        if call!(self
+            .addr
+            .emit(event.into(), params, EventHeaders::default(), None))
+        .await
+        .is_err()
+        {
+            return self.check_running().await;
+        }
+
+        Ok(())
+    }
+
+    /// Emits an event to all the nodes that can handle it, see [`ServiceBroker::broadcast()`].
+    pub async fn broadcast<S: Into<String>>(&self, event: S, params: Value) -> Result<(), Error> {
+        if call!(self
+            .addr
+            .broadcast(event.into(), params, EventHeaders::default()))
+        .await
+        .is_err()
+        {
+            return self.check_running().await;
+        }
+
+        Ok(())
+    }
+
+    // a method of a stopped broker is canceled, a method that failed only drops its result
+    async fn check_running(&self) -> Result<(), Error> {
+        call!(self.addr.is_ready())
+            .await
+            .map(|_| ())
+            .map_err(|_| Error::BrokerStopped)
+    }
+
+    async fn stopped_or(&self, error: Error) -> Error {
+        match self.check_running().await {
+            Ok(()) => error,
+            Err(stopped) => stopped,
+        }
+    }
+}
+
+/// Builder for a [ServiceBroker], created with [`ServiceBroker::builder()`].
+/// ```rust, ignore
+/// let broker = ServiceBroker::builder()
+///     .config(config)
+///     .add_service(greeter_service)
+///     .build();
+///
+/// broker.start().await;
+/// ```
+#[derive(Default)]
+pub struct ServiceBrokerBuilder {
+    config: Option<Config>,
+    services: Vec<Service>,
+}
+
+impl ServiceBrokerBuilder {
+    /// Set the broker config, defaults to [`ConfigBuilder::default()`].
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn add_service(mut self, service: Service) -> Self {
+        self.services.push(service);
+        self
+    }
+
+    pub fn add_services(mut self, services: Vec<Service>) -> Self {
+        self.services.extend(services);
+        self
+    }
+
+    /// Spawn the broker, must be called from within a tokio runtime.
+    pub fn build(self) -> ServiceBroker {
+        let config = self
+            .config
+            .unwrap_or_else(|| ConfigBuilder::default().build());
+
+        ServiceBroker::new(config).add_services(self.services)
     }
 }
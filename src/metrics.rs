@@ -0,0 +1,290 @@
+/*!
+Hooks to ship the broker's metrics to any backend, ex: StatsD, Datadog or your own store.
+
+Implement [MetricsRecorder] and set it with
+[`ConfigBuilder::metrics_recorder()`][crate::config::ConfigBuilder::metrics_recorder()], every
+method has a no-op default so only the metrics you need have to be implemented. The broker
+calls the recorder from the request path: methods must be cheap and must not block, ex: update
+an atomic counter or push to a channel drained by another task.
+
+```rust, ignore
+#[derive(Debug)]
+struct StatsdRecorder(UdpSocket);
+
+impl MetricsRecorder for StatsdRecorder {
+    fn record_request(&self, action: &str, duration: Duration, success: bool) {
+        let metric = format!("moleculer.request.{}:{}|ms", action, duration.as_millis());
+        let _ = self.0.send(metric.as_bytes());
+    }
+}
+
+let config = ConfigBuilder::default()
+    .metrics_recorder(Arc::new(StatsdRecorder(socket)))
+    .build();
+```
+
+With the `prometheus` feature, `PrometheusRecorder` keeps the metrics in memory and renders them
+in the Prometheus text format.
+*/
+
+use std::{fmt::Debug, time::Duration};
+
+/// Receives the metrics of the broker, see the [module docs][self].
+pub trait MetricsRecorder: Debug + Send + Sync {
+    /// A request handled by an action of this node: how long its callback ran, and whether it
+    /// returned `Ok`.
+    fn record_request(&self, _action: &str, _duration: Duration, _success: bool) {}
+
+    /// A call made by this node is retried after a retryable error, see
+    /// [RetryPolicy][crate::config::RetryPolicy].
+    fn record_retry(&self, _action: &str) {}
+
+    /// The circuit breaker of an action opened. Not called yet, the broker doesn't trip
+    /// circuits, see [CircuitBreaker][crate::config::CircuitBreaker].
+    fn record_circuit_open(&self, _action: &str) {}
+
+    /// A packet sent by this node: its type (ex: `REQ`) and its size in bytes once serialized.
+    /// See `payload_size_warning` in the [Transit][crate::config::Transit] options.
+    fn record_payload_size(&self, _packet_type: &str, _size: usize) {}
+
+    /// An event delivered again to a listener of this node was skipped, see
+    /// [EventDedup][crate::service::EventDedup].
+    fn record_duplicate_event(&self, _event: &str) {}
+}
+
+/// Drops every metric, the default [MetricsRecorder].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopRecorder;
+
+impl MetricsRecorder for NoopRecorder {}
+
+#[cfg(feature = "prometheus")]
+pub use prometheus::PrometheusRecorder;
+
+#[cfg(feature = "prometheus")]
+mod prometheus {
+    use std::{
+        collections::BTreeMap,
+        fmt::Write,
+        sync::{Mutex, MutexGuard},
+        time::Duration,
+    };
+
+    use super::MetricsRecorder;
+
+    /// A [MetricsRecorder] rendering the metrics in the Prometheus text format, enabled with
+    /// the `prometheus` feature. Serve [`render()`][Self::render()] on the endpoint your
+    /// Prometheus scrapes.
+    ///
+    /// - `moleculer_request_total{action, success}`: requests handled by this node
+    /// - `moleculer_request_duration_seconds_sum{action}` and `_count`: time spent in callbacks
+    /// - `moleculer_request_retry_total{action}`: retries of calls made by this node
+    /// - `moleculer_circuit_breaker_opened_total{action}`: circuits opened
+    /// - `moleculer_packet_size_bytes{type}`: histogram of the sizes of the packets sent
+    /// - `moleculer_event_duplicate_total{event}`: events skipped as already handled
+    #[derive(Debug, Default)]
+    pub struct PrometheusRecorder {
+        metrics: Mutex<Metrics>,
+    }
+
+    // sorted so the output is stable between scrapes
+    #[derive(Debug, Default)]
+    struct Metrics {
+        requests: BTreeMap<(String, bool), u64>,
+        durations: BTreeMap<String, (f64, u64)>,
+        retries: BTreeMap<String, u64>,
+        circuits_opened: BTreeMap<String, u64>,
+        packet_sizes: BTreeMap<String, Histogram>,
+        duplicate_events: BTreeMap<String, u64>,
+    }
+
+    // upper bounds in bytes of the packet size buckets, from 1 KiB to 4 MiB
+    const PACKET_SIZE_BUCKETS: [usize; 7] = [
+        1024,
+        16 * 1024,
+        64 * 1024,
+        256 * 1024,
+        512 * 1024,
+        1024 * 1024,
+        4 * 1024 * 1024,
+    ];
+
+    #[derive(Debug, Default)]
+    struct Histogram {
+        // not cumulative, summed up when rendered
+        buckets: [u64; PACKET_SIZE_BUCKETS.len()],
+        sum: u64,
+        count: u64,
+    }
+
+    impl PrometheusRecorder {
+        fn lock(&self) -> MutexGuard<'_, Metrics> {
+            self.metrics
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+
+        /// The metrics in the Prometheus text exposition format
+        pub fn render(&self) -> String {
+            let metrics = self.lock();
+            let mut output = String::new();
+
+            output.push_str("# TYPE moleculer_request_total counter\n");
+            for ((action, success), count) in &metrics.requests {
+                let _ = writeln!(
+                    output,
+                    "moleculer_request_total{{action=\"{}\",success=\"{}\"}} {}",
+                    escape(action),
+                    success,
+                    count
+                );
+            }
+
+            output.push_str("# TYPE moleculer_request_duration_seconds summary\n");
+            for (action, (sum, count)) in &metrics.durations {
+                let action = escape(action);
+                let _ = writeln!(
+                    output,
+                    "moleculer_request_duration_seconds_sum{{action=\"{}\"}} {}",
+                    action, sum
+                );
+                let _ = writeln!(
+                    output,
+                    "moleculer_request_duration_seconds_count{{action=\"{}\"}} {}",
+                    action, count
+                );
+            }
+
+            render_counter(
+                &mut output,
+                "moleculer_request_retry_total",
+                "action",
+                &metrics.retries,
+            );
+            render_counter(
+                &mut output,
+                "moleculer_circuit_breaker_opened_total",
+                "action",
+                &metrics.circuits_opened,
+            );
+
+            output.push_str("# TYPE moleculer_packet_size_bytes histogram\n");
+            for (packet_type, histogram) in &metrics.packet_sizes {
+                let packet_type = escape(packet_type);
+                let mut cumulative = 0;
+
+                for (bound, count) in PACKET_SIZE_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                    cumulative += count;
+                    let _ = writeln!(
+                        output,
+                        "moleculer_packet_size_bytes_bucket{{type=\"{}\",le=\"{}\"}} {}",
+                        packet_type, bound, cumulative
+                    );
+                }
+
+                let _ = writeln!(
+                    output,
+                    "moleculer_packet_size_bytes_bucket{{type=\"{}\",le=\"+Inf\"}} {}",
+                    packet_type, histogram.count
+                );
+                let _ = writeln!(
+                    output,
+                    "moleculer_packet_size_bytes_sum{{type=\"{}\"}} {}",
+                    packet_type, histogram.sum
+                );
+                let _ = writeln!(
+                    output,
+                    "moleculer_packet_size_bytes_count{{type=\"{}\"}} {}",
+                    packet_type, histogram.count
+                );
+            }
+
+            render_counter(
+                &mut output,
+                "moleculer_event_duplicate_total",
+                "event",
+                &metrics.duplicate_events,
+            );
+
+            output
+        }
+    }
+
+    impl MetricsRecorder for PrometheusRecorder {
+        fn record_request(&self, action: &str, duration: Duration, success: bool) {
+            let mut metrics = self.lock();
+
+            *metrics
+                .requests
+                .entry((action.to_string(), success))
+                .or_default() += 1;
+
+            let (sum, count) = metrics.durations.entry(action.to_string()).or_default();
+            *sum += duration.as_secs_f64();
+            *count += 1;
+        }
+
+        fn record_retry(&self, action: &str) {
+            *self.lock().retries.entry(action.to_string()).or_default() += 1;
+        }
+
+        fn record_circuit_open(&self, action: &str) {
+            *self
+                .lock()
+                .circuits_opened
+                .entry(action.to_string())
+                .or_default() += 1;
+        }
+
+        fn record_payload_size(&self, packet_type: &str, size: usize) {
+            let mut metrics = self.lock();
+            let histogram = metrics
+                .packet_sizes
+                .entry(packet_type.to_string())
+                .or_default();
+
+            if let Some(bucket) = PACKET_SIZE_BUCKETS.iter().position(|bound| size <= *bound) {
+                histogram.buckets[bucket] += 1;
+            }
+
+            histogram.sum += size as u64;
+            histogram.count += 1;
+        }
+
+        fn record_duplicate_event(&self, event: &str) {
+            *self
+                .lock()
+                .duplicate_events
+                .entry(event.to_string())
+                .or_default() += 1;
+        }
+    }
+
+    fn render_counter(
+        output: &mut String,
+        name: &str,
+        label: &str,
+        counts: &BTreeMap<String, u64>,
+    ) {
+        let _ = writeln!(output, "# TYPE {} counter", name);
+
+        for (value, count) in counts {
+            let _ = writeln!(
+                output,
+                "{}{{{}=\"{}\"}} {}",
+                name,
+                label,
+                escape(value),
+                count
+            );
+        }
+    }
+
+    // label values escape backslashes, quotes and line feeds
+    fn escape(value: &str) -> String {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    }
+}
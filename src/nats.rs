@@ -1,6 +1,19 @@
-use async_nats::{Connection, Subscription};
-use log::{error, warn};
+use crate::config::{NatsTransporter, ReconnectPolicy};
+
+use async_nats::{Connection, Headers, Message};
+use log::{error, info, warn};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
 use thiserror::Error;
+use tokio::sync::mpsc::UnboundedSender;
 
 type Result<T> = std::result::Result<T, self::Error>;
 
@@ -10,49 +23,318 @@ pub(crate) enum Error {
     UnableToConnect(std::io::Error),
     #[error("Unable to subscribe to channel ({0}): {1}")]
     UnableToSubscribe(String, std::io::Error),
+    #[error("Unable to close NATS connection: {0}")]
+    CloseFailed(std::io::Error),
+    #[error("Unable to flush NATS connection: {0}")]
+    FlushFailed(std::io::Error),
+    #[error("JetStream request to {0} failed: {1}")]
+    JetStreamRequestFailed(String, std::io::Error),
+    #[error("JetStream returned an error: {0}")]
+    JetStream(Value),
+    #[error("JetStream didn't acknowledge the message in time")]
+    AckTimeout,
+    #[error("Publish to {0} didn't complete in {1}ms")]
+    PublishTimeout(String, u128),
+}
+
+/// Connection state changes reported by the NATS client
+#[derive(Debug)]
+pub(crate) enum ConnectionEvent {
+    Disconnected,
+    /// Connected again after `attempts` attempts
+    Reconnected {
+        attempts: usize,
+    },
+    /// The connection is closed, by [`Conn::close()`] or once the last reconnect attempt failed
+    Closed {
+        attempts: usize,
+    },
 }
 
 #[derive(Clone)]
 pub(crate) struct Conn {
     pub(crate) conn: Connection,
+    subscriptions: Arc<ActiveSubscriptions>,
+}
+
+/// Subjects of the subscriptions that weren't unsubscribed or dropped, by subscription
+#[derive(Default)]
+struct ActiveSubscriptions {
+    subjects: Mutex<BTreeMap<u64, String>>,
+    next_id: AtomicU64,
+}
+
+impl ActiveSubscriptions {
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, String>> {
+        self.subjects
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn track(self: &Arc<Self>, subject: &str, inner: async_nats::Subscription) -> Subscription {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.lock().insert(id, subject.to_string());
+
+        Subscription {
+            inner,
+            id,
+            active: Arc::clone(self),
+        }
+    }
+}
+
+/// A NATS subscription, listed by [`Conn::subscriptions()`] until it is unsubscribed or dropped
+pub(crate) struct Subscription {
+    inner: async_nats::Subscription,
+    id: u64,
+    active: Arc<ActiveSubscriptions>,
+}
+
+impl Subscription {
+    /// The next message, `None` once unsubscribed or the connection is closed
+    pub(crate) async fn next(&self) -> Option<Message> {
+        self.inner.next().await
+    }
+
+    pub(crate) async fn unsubscribe(&self) -> io::Result<()> {
+        self.active.lock().remove(&self.id);
+        self.inner.unsubscribe().await
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.active.lock().remove(&self.id);
+    }
 }
 
 impl Conn {
-    pub(crate) async fn new(nats_address: &str) -> Result<Conn> {
-        let conn = async_nats::connect(nats_address)
+    /// Connects to one of the servers, the client fails over to the other servers (and the
+    /// ones the cluster advertises) when the connection is lost. Without a `reconnect_policy`
+    /// each server is only tried once after the connection is lost
+    pub(crate) async fn new(
+        transporter: &NatsTransporter,
+        name: &str,
+        reconnect_policy: Option<ReconnectPolicy>,
+        events: UnboundedSender<ConnectionEvent>,
+    ) -> Result<Conn> {
+        // the delay callback is also called for the first connection, only reconnects are counted
+        let reconnecting = Arc::new(AtomicBool::new(false));
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let servers = transporter.servers();
+        let options = transporter.client_options();
+
+        let options = match reconnect_policy {
+            Some(policy) => {
+                let reconnecting = Arc::clone(&reconnecting);
+                let attempts = Arc::clone(&attempts);
+
+                options
+                    .max_reconnects(policy.max_attempts)
+                    .reconnect_delay_callback(move |attempt| {
+                        let delay = policy.next_delay(attempt, &mut rand::thread_rng());
+
+                        if reconnecting.load(Ordering::SeqCst) {
+                            attempts.fetch_add(1, Ordering::SeqCst);
+                            let max_attempts = policy
+                                .max_attempts
+                                .map_or_else(|| "unlimited".to_string(), |max| max.to_string());
+
+                            info!(
+                                "Reconnecting to NATS in {}ms (attempt {} of {} on the server)",
+                                delay.as_millis(),
+                                attempt + 1,
+                                max_attempts
+                            );
+                        }
+
+                        delay
+                    })
+            }
+            // the attempts are reset once connected, one attempt is left for a reconnect
+            None => {
+                let attempts = Arc::clone(&attempts);
+
+                options
+                    .max_reconnects(1)
+                    .reconnect_delay_callback(move |_| {
+                        attempts.fetch_add(1, Ordering::SeqCst);
+                        Duration::ZERO
+                    })
+            }
+        };
+
+        let disconnect_events = events.clone();
+        let disconnect_reconnecting = Arc::clone(&reconnecting);
+        let disconnect_attempts = Arc::clone(&attempts);
+
+        let reconnect_events = events.clone();
+        let reconnect_attempts = Arc::clone(&attempts);
+
+        let conn = options
+            .with_name(name)
+            .disconnect_callback(move || {
+                disconnect_attempts.store(0, Ordering::SeqCst);
+                disconnect_reconnecting.store(true, Ordering::SeqCst);
+                let _ = disconnect_events.send(ConnectionEvent::Disconnected);
+            })
+            .reconnect_callback(move || {
+                reconnecting.store(false, Ordering::SeqCst);
+                let attempts = reconnect_attempts.load(Ordering::SeqCst);
+                let _ = reconnect_events.send(ConnectionEvent::Reconnected { attempts });
+            })
+            .close_callback(move || {
+                let attempts = attempts.load(Ordering::SeqCst);
+                let _ = events.send(ConnectionEvent::Closed { attempts });
+            })
+            .connect(&servers.join(","))
             .await
             .map_err(Error::UnableToConnect)?;
 
-        Ok(Conn { conn })
+        info!("Connected to NATS, servers: {}", servers.join(", "));
+
+        Ok(Conn {
+            conn,
+            subscriptions: Arc::default(),
+        })
     }
 
-    pub(crate) async fn send(&self, channel: &str, message: Vec<u8>) -> Result<()> {
-        let mut retries: i8 = 0;
-        let mut result = self.conn.publish(channel, &message).await;
+    /// Publishes the message, retried until it is handed to the client or `timeout` passed
+    pub(crate) async fn send(
+        &self,
+        channel: &str,
+        message: &[u8],
+        timeout: Duration,
+    ) -> Result<()> {
+        let send = async {
+            let mut retries: u32 = 0;
+            let mut result = self.conn.publish(channel, message).await;
+
+            // keep retrying if publish fails
+            while result.is_err() {
+                retries += 1;
+                let error_message = format!("Failed to send message, failed {} times", retries);
 
-        // keep retrying if publish fails
-        while result.is_err() {
-            retries += 1;
-            let error_message = format!("Failed to send message, failed {} times", retries);
+                // before 5 retries log as warning, as error after
+                if retries < 5 {
+                    warn!("{}", &error_message)
+                } else {
+                    error!("{}", &error_message)
+                }
 
-            // before 5 retries log as warning, as error after
-            if retries < 5 {
-                warn!("{}", &error_message)
-            } else {
-                error!("{}", &error_message)
+                result = self.conn.publish(channel, message).await;
             }
+        };
 
-            result = self.conn.publish(channel, &message).await;
-        }
+        tokio::time::timeout(timeout, send)
+            .await
+            .map_err(|_| Error::PublishTimeout(channel.to_string(), timeout.as_millis()))
+    }
+
+    /// Waits until the server received the messages published so far
+    pub(crate) async fn flush(&self, timeout: Duration) -> Result<()> {
+        self.conn
+            .flush_timeout(timeout)
+            .await
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::TimedOut => {
+                    Error::PublishTimeout("the transporter".to_string(), timeout.as_millis())
+                }
+                _ => Error::FlushFailed(err),
+            })
+    }
+
+    /// Sends a request to the JetStream API, ex: `$JS.API.STREAM.CREATE.<stream>`, and returns
+    /// its response
+    pub(crate) async fn jetstream_request(
+        &self,
+        subject: &str,
+        payload: &Value,
+        timeout: Duration,
+    ) -> Result<Value> {
+        let response = self
+            .conn
+            .request_timeout(subject, payload.to_string(), timeout)
+            .await
+            .map_err(|err| Error::JetStreamRequestFailed(subject.to_string(), err))?;
+
+        parse_jetstream_response(&response.data)
+    }
+
+    /// Publishes a message to a JetStream stream and waits until the stream stored it.
+    /// JetStream drops a message with the `msg_id` of one it stored in its duplicate window
+    pub(crate) async fn publish_with_ack(
+        &self,
+        subject: &str,
+        msg_id: &str,
+        message: &[u8],
+        timeout: Duration,
+    ) -> Result<()> {
+        let inbox = self.conn.new_inbox();
+        let acks = self.subscribe(&inbox).await?;
+        let headers: Headers = [("Nats-Msg-Id", msg_id)].iter().collect();
+
+        let published = self
+            .conn
+            .publish_with_reply_or_headers(subject, Some(&inbox), Some(&headers), message)
+            .await
+            .map_err(|err| Error::JetStreamRequestFailed(subject.to_string(), err));
+
+        let ack = match published {
+            Ok(()) => tokio::time::timeout(timeout, acks.next())
+                .await
+                .map_err(|_| Error::AckTimeout)
+                .and_then(|ack| ack.ok_or(Error::AckTimeout))
+                .and_then(|ack| parse_jetstream_response(&ack.data).map(|_| ())),
+            Err(err) => Err(err),
+        };
+
+        let _ = acks.unsubscribe().await;
+        ack
+    }
+
+    /// Flushes pending messages and closes the connection
+    pub(crate) async fn close(&self) -> Result<()> {
+        self.conn.close().await.map_err(Error::CloseFailed)
+    }
+
+    /// Subscribes as a member of `group`, each message is received by one member only
+    pub(crate) async fn queue_subscribe(&self, channel: &str, group: &str) -> Result<Subscription> {
+        let subscription = self
+            .conn
+            .queue_subscribe(channel, group)
+            .await
+            .map_err(|e| Error::UnableToSubscribe(channel.to_string(), e))?;
 
-        Ok(())
+        Ok(self.subscriptions.track(channel, subscription))
     }
 
     pub(crate) async fn subscribe(&self, channel: &str) -> Result<Subscription> {
-        Ok(self
+        let subscription = self
             .conn
             .subscribe(channel)
             .await
-            .map_err(|e| Error::UnableToSubscribe(channel.to_string(), e))?)
+            .map_err(|e| Error::UnableToSubscribe(channel.to_string(), e))?;
+
+        Ok(self.subscriptions.track(channel, subscription))
+    }
+
+    /// Subjects this connection is subscribed to, sorted. The client subscribes to them again
+    /// after a reconnect, they stay listed meanwhile
+    pub(crate) fn subscriptions(&self) -> Vec<String> {
+        let mut subjects: Vec<String> = self.subscriptions.lock().values().cloned().collect();
+        subjects.sort();
+        subjects
+    }
+}
+
+// JetStream API responses and acknowledgements report failures in an `error` field
+fn parse_jetstream_response(data: &[u8]) -> Result<Value> {
+    let response: Value = serde_json::from_slice(data)
+        .map_err(|err| Error::JetStream(format!("invalid response: {}", err).into()))?;
+
+    match response.get("error") {
+        Some(error) => Err(Error::JetStream(error.clone())),
+        None => Ok(response),
     }
 }
@@ -47,16 +47,43 @@
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{collections::HashMap, marker::PhantomData};
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
 use crate::{
-    channels::messages::incoming::{EventMessage, RequestMessage},
-    Error, ServiceBroker,
+    channels::messages::incoming::{PacketEvent, PacketRequest},
+    clock::{Clock, SystemClock},
+    config::ResponseTransformer,
+    data_structures::SeenIds,
+    errors::MoleculerError,
+    stream::RequestStream,
+    CallOptions, Error, EventOptions, ServiceBroker,
 };
 
 /// Function that is called when an [Event] or [Action] is received.
 pub type Callback<T> = fn(Context<T>) -> Result<(), Box<dyn std::error::Error>>;
 
+/// Hook called with the context of a request before its action's callback, see [ActionHooks].
+pub type BeforeHook = fn(&mut Context<Action>) -> Result<(), MoleculerError>;
+
+/// Hook called with the reply of an action before it is sent, see [ActionHooks].
+pub type AfterHook = fn(&Context<Action>, Value) -> Result<Value, MoleculerError>;
+
+/// Hook called with the error of an action before it is sent, see [ActionHooks].
+pub type ErrorHook = fn(&Context<Action>, MoleculerError) -> Result<Value, MoleculerError>;
+
+/// Function that is called with the broker when a [Service] is added to it, see
+/// [`Service::set_started()`].
+pub type StartedHook = fn(ServiceBroker) -> Result<(), Box<dyn std::error::Error>>;
+
 /// Build using [ActionBuilder].
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Action {
@@ -65,6 +92,486 @@ pub struct Action {
     params: Option<Value>,
     #[serde(skip)]
     pub(crate) callback: Option<Callback<Action>>,
+    #[serde(skip)]
+    pub(crate) execution: Execution,
+    #[serde(skip)]
+    pub(crate) queue: Option<Arc<ActionQueue>>,
+    #[serde(skip)]
+    default_params: Option<Value>,
+    #[serde(skip)]
+    params_merge: ParamsMerge,
+    #[serde(skip)]
+    pub(crate) hooks: ActionHooks,
+    #[serde(
+        default,
+        rename = "retryPolicy",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) retry_policy: Option<ActionRetryPolicy>,
+    #[serde(default)]
+    pub(crate) visibility: Visibility,
+}
+
+impl Action {
+    // fields the caller left out are filled in from the action's default params
+    pub(crate) fn apply_default_params(&self, params: &mut Value) {
+        if let Some(defaults) = &self.default_params {
+            merge_default_params(params, defaults, self.params_merge);
+        }
+    }
+}
+
+/// The `retryPolicy` of an action's schema, set with [`ActionBuilder::retries()`]. Other fields
+/// of a JS schema's policy (`delay`, `factor`...) are ignored, the caller's
+/// [RetryPolicy][crate::config::RetryPolicy] spaces the retries.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ActionRetryPolicy {
+    #[serde(default = "retries_enabled")]
+    enabled: bool,
+    #[serde(default)]
+    retries: u32,
+}
+
+fn retries_enabled() -> bool {
+    true
+}
+
+impl ActionRetryPolicy {
+    pub(crate) fn retries(&self) -> u32 {
+        if self.enabled {
+            self.retries
+        } else {
+            0
+        }
+    }
+}
+
+/// How the default params of an [Action] are merged into the params of a request, set with
+/// [`ActionBuilder::params_merge()`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ParamsMerge {
+    /// Only the top level fields missing from the params are added
+    #[default]
+    Shallow,
+    /// Objects present in both the params and the defaults are merged field by field
+    Deep,
+}
+
+/// Hooks run around the callback of one [Action], set with [`ActionBuilder::hooks()`].
+///
+/// For each request, in order:
+/// 1. the [interceptors][crate::config::Interceptor] of the node, which apply to every action
+/// 2. `before`, which can change the params and meta of the request or reject it with an error
+/// 3. the action's callback
+/// 4. `after`, called with the value passed to [`Context::reply()`], returns the reply that is
+///    sent
+/// 5. or `error`, called with the error of `before`, `after` or the callback (returned or sent
+///    with [`Context::reply_error()`]), returns the error that is sent or a value to recover
+///    with. A recovered value is sent as is, `after` isn't called with it.
+/// 6. the [response transformer][crate::config::ConfigBuilder::response_transformer()] of the
+///    node, on every reply that isn't an error
+///
+/// Requests rejected by an interceptor don't reach the hooks. The hooks run where the reply is
+/// sent from: on the broker for `before`, then with the callback or the task it replies from.
+/// ```rust, ignore
+/// fn not_found_as_null(_ctx: &ActionContext, error: MoleculerError) -> Result<Value, MoleculerError> {
+///     match error.code {
+///         404 => Ok(Value::Null),
+///         _ => Err(error),
+///     }
+/// }
+///
+/// let get_user = ActionBuilder::new("getUser")
+///     .add_callback(get_user)
+///     .hooks(ActionHooks::default().error(not_found_as_null))
+///     .build();
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ActionHooks {
+    pub(crate) before: Option<BeforeHook>,
+    after: Option<AfterHook>,
+    error: Option<ErrorHook>,
+}
+
+impl ActionHooks {
+    pub fn before(mut self, before: BeforeHook) -> Self {
+        self.before = Some(before);
+        self
+    }
+
+    pub fn after(mut self, after: AfterHook) -> Self {
+        self.after = Some(after);
+        self
+    }
+
+    pub fn error(mut self, error: ErrorHook) -> Self {
+        self.error = Some(error);
+        self
+    }
+}
+
+fn merge_default_params(params: &mut Value, defaults: &Value, merge: ParamsMerge) {
+    match (params, defaults) {
+        (params @ Value::Null, defaults) => *params = defaults.clone(),
+        (Value::Object(params), Value::Object(defaults)) => {
+            for (key, default) in defaults {
+                match params.get_mut(key) {
+                    None => {
+                        params.insert(key.clone(), default.clone());
+                    }
+                    Some(param @ Value::Object(_)) if merge == ParamsMerge::Deep => {
+                        merge_default_params(param, default, merge)
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        // params that aren't an object are the caller's choice, ex: a single value
+        _ => {}
+    }
+}
+
+/// Who can call an [Action], set with [`ActionBuilder::visibility()`]. Sent in the action's
+/// `visibility` in `INFO` packets, with the values of Moleculer JS, so API gateways on other
+/// nodes, JS ones included, only expose `Published` actions.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Callable from any node, and exposed by API gateways
+    #[default]
+    Published,
+    /// Callable from any node, API gateways don't expose it
+    Public,
+    /// Only callable from its own node: other nodes don't add it to their registry, and requests
+    /// for it sent by another node fail with a `ServiceNotFoundError`
+    Protected,
+    /// Only callable from its own service in Moleculer JS. Calls can't be traced back to a
+    /// service here, it is handled like `Protected`
+    Private,
+}
+
+impl Visibility {
+    /// Whether API gateways expose the action
+    pub fn is_published(self) -> bool {
+        self == Visibility::Published
+    }
+
+    /// Whether nodes other than the action's can call it
+    pub(crate) fn is_remote(self) -> bool {
+        matches!(self, Visibility::Published | Visibility::Public)
+    }
+}
+
+/// Where the callback of an [Action] runs, set with [`ActionBuilder::execution()`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Execution {
+    /// On the broker, handling of other packets waits until the callback returns
+    #[default]
+    Inline,
+    /// On a new tokio task
+    Task,
+    /// On tokio's blocking thread pool, for CPU-heavy or blocking callbacks
+    Blocking,
+}
+
+/// A bounded pool of workers for one [Action], set with [`ActionBuilder::worker_pool()`], so a
+/// heavy action can't starve the others.
+///
+/// Up to `concurrency` requests of the action run at once, the next ones wait in the pool's
+/// queue (first in, first out) and requests arriving while `max_queue_size` requests are already
+/// waiting are rejected with a retryable [`MoleculerError::queue_full()`] error. The number of
+/// waiting requests is returned by
+/// [`ServiceBroker::action_queue_depths()`][crate::ServiceBroker::action_queue_depths()].
+///
+/// Pooled actions run on tokio tasks, or on the blocking thread pool with
+/// [`Execution::Blocking`]; [`Execution::Inline`] is ignored. Each pool only limits its own action.
+/// The node-wide `bulkhead` options in [Config][crate::config::Config] aren't enforced by the
+/// broker, so the worker pools are the only limit applied to requests.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorkerPool {
+    concurrency: usize,
+    max_queue_size: usize,
+}
+
+impl WorkerPool {
+    /// Number of requests running at once, defaults to `10`.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Number of requests waiting for a worker before new ones are rejected, defaults to `100`.
+    pub fn max_queue_size(mut self, max_queue_size: usize) -> Self {
+        self.max_queue_size = max_queue_size;
+        self
+    }
+}
+
+impl Default for WorkerPool {
+    fn default() -> Self {
+        Self {
+            concurrency: 10,
+            max_queue_size: 100,
+        }
+    }
+}
+
+/// What happens to an event arriving while its listener runs as many callbacks as its
+/// [ListenerConcurrency] allows.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OverflowPolicy {
+    /// Wait in the listener's queue, events arriving while the queue is full are dropped
+    #[default]
+    Queue,
+    /// Drop the event right away
+    Drop,
+}
+
+/// Limits the callbacks of one [Event] running at once, set with
+/// [`EventBuilder::concurrency()`], so a burst of events to a slow listener can't pile up
+/// invocations. It is the event-side analog of the [WorkerPool] of an action.
+///
+/// Up to `concurrency` callbacks run at once on tokio tasks, the next events are queued or
+/// dropped following the `overflow` policy. Dropped events are logged, emitters aren't told as
+/// events have no response. The number of queued events is returned by
+/// [`ServiceBroker::event_queue_depths()`][crate::ServiceBroker::event_queue_depths()].
+///
+/// Only live events are limited, [durable events][DurableConsumer] are already handled one at a
+/// time.
+/// ```rust, ignore
+/// let resize = EventBuilder::new("image.uploaded")
+///     .add_callback(resize_image)
+///     .concurrency(ListenerConcurrency::default().concurrency(2).overflow(OverflowPolicy::Drop))
+///     .build();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ListenerConcurrency {
+    concurrency: usize,
+    max_queue_size: usize,
+    overflow: OverflowPolicy,
+}
+
+impl ListenerConcurrency {
+    /// Number of callbacks running at once, defaults to `10`.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Number of events waiting with [`OverflowPolicy::Queue`] before new ones are dropped,
+    /// defaults to `100`.
+    pub fn max_queue_size(mut self, max_queue_size: usize) -> Self {
+        self.max_queue_size = max_queue_size;
+        self
+    }
+
+    /// What happens to the events arriving once `concurrency` callbacks are running, defaults
+    /// to [`OverflowPolicy::Queue`].
+    pub fn overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    fn max_queued(&self) -> usize {
+        match self.overflow {
+            OverflowPolicy::Queue => self.max_queue_size,
+            OverflowPolicy::Drop => 0,
+        }
+    }
+}
+
+impl Default for ListenerConcurrency {
+    fn default() -> Self {
+        Self {
+            concurrency: 10,
+            max_queue_size: 100,
+            overflow: OverflowPolicy::Queue,
+        }
+    }
+}
+
+/// Skips the events a listener already handled, set with [`EventBuilder::dedup()`]. Events
+/// can be delivered twice: a [durable event][DurableConsumer] whose acknowledgement was lost is
+/// delivered again, and so is a packet sent again after a reconnect. Each event keeps the id
+/// its emitter gave it, so a second delivery within `ttl` of the first is dropped, and counted
+/// with [`MetricsRecorder::record_duplicate_event()`][crate::metrics::MetricsRecorder::record_duplicate_event()].
+///
+/// This is best-effort, not exactly-once delivery: ids are only remembered on this node, in
+/// memory, for `ttl` and up to `max_ids` of them, so a duplicate arriving later, after a
+/// restart or at another instance of the service is handled again. Handlers that must not run
+/// twice should still be idempotent. A durable event is only remembered once its callback
+/// succeeded, the redelivery of a failed one is handled. Events without an id (ex: from a
+/// client that doesn't set one) are never skipped.
+/// ```rust, ignore
+/// let order_created = EventBuilder::new("order.created")
+///     .add_callback(charge_order)
+///     .dedup(EventDedup::default().ttl(5 * 60 * 1000))
+///     .build();
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct EventDedup {
+    ttl: u32,
+    max_ids: usize,
+}
+
+impl EventDedup {
+    /// Milliseconds an id is remembered, defaults to `60000`
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Ids remembered at most, the oldest are forgotten first. Defaults to `10000`, which
+    /// bounds the memory used by a busy listener
+    pub fn max_ids(mut self, max_ids: usize) -> Self {
+        self.max_ids = max_ids;
+        self
+    }
+}
+
+impl Default for EventDedup {
+    fn default() -> Self {
+        Self {
+            ttl: 60_000,
+            max_ids: 10_000,
+        }
+    }
+}
+
+/// The workers and queue of a [WorkerPool] or a [ListenerConcurrency], shared by the clones of
+/// its [Action] or [Event]
+#[derive(Debug)]
+pub(crate) struct ActionQueue {
+    workers: Arc<Semaphore>,
+    max_queue_size: usize,
+    queued: AtomicUsize,
+}
+
+impl ActionQueue {
+    fn new(concurrency: usize, max_queue_size: usize) -> Self {
+        Self {
+            workers: Arc::new(Semaphore::new(concurrency)),
+            max_queue_size,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Runs `execute` once a worker is free, returns `false` without running it when the
+    /// queue is full
+    pub(crate) fn submit<F>(self: &Arc<Self>, execution: Execution, execute: F) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // the semaphore hands released workers to waiting requests first,
+        // so this only succeeds when nothing is queued
+        if let Ok(worker) = Arc::clone(&self.workers).try_acquire_owned() {
+            run_on_worker(worker, execution, execute);
+            return true;
+        }
+
+        let reserved = self
+            .queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                (queued < self.max_queue_size).then(|| queued + 1)
+            });
+
+        if reserved.is_err() {
+            return false;
+        }
+
+        let queue = Arc::clone(self);
+        tokio::spawn(async move {
+            let worker = Arc::clone(&queue.workers)
+                .acquire_owned()
+                .await
+                .expect("the semaphore is never closed");
+            queue.queued.fetch_sub(1, Ordering::SeqCst);
+
+            run_on_worker(worker, execution, execute);
+        });
+
+        true
+    }
+
+    /// Number of requests waiting for a worker
+    pub(crate) fn depth(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+}
+
+// the worker is released once `execute` returns
+fn run_on_worker<F>(worker: OwnedSemaphorePermit, execution: Execution, execute: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    match execution {
+        Execution::Blocking => {
+            tokio::task::spawn_blocking(move || {
+                let _worker = worker;
+                execute()
+            });
+        }
+        Execution::Inline | Execution::Task => {
+            tokio::spawn(async move {
+                let _worker = worker;
+                execute()
+            });
+        }
+    }
+}
+
+/// A durable JetStream consumer of the [durable events][crate::config::DurableEvents] a
+/// service listens to: the events are read from their stream instead of being delivered live, so
+/// the events sent while the service was down are replayed once it is back.
+///
+/// The consumer (one per event, named `<name>-<event>`) keeps track of the last event the
+/// service acknowledged. An event is acknowledged once its callback returns `Ok`, a callback
+/// that fails or panics sends a negative acknowledgement and the event is delivered again,
+/// up to `max_deliver` times in total. Events are delivered once per consumer name: instances of
+/// the service sharing the name split the events between them like emitted events, give each
+/// node its own name (ex: with its node id) for every node to receive every event.
+/// ```rust, ignore
+/// let billing = Service::new("billing")
+///     .add_event(order_created)
+///     .set_durable_consumer(DurableConsumer::default().max_deliver(3));
+/// ```
+#[derive(Debug, Clone)]
+pub struct DurableConsumer {
+    pub(crate) name: Option<String>,
+    pub(crate) max_deliver: u32,
+    pub(crate) ack_wait: u32,
+}
+
+impl DurableConsumer {
+    /// Name of the consumer, defaults to the name of the service.
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Times an event is delivered before it is given up, defaults to `5`. A message that
+    /// always fails (ex: invalid data) is dropped after that instead of being redelivered forever.
+    pub fn max_deliver(mut self, max_deliver: u32) -> Self {
+        self.max_deliver = max_deliver;
+        self
+    }
+
+    /// Milliseconds JetStream waits for an acknowledgement before it delivers the event again,
+    /// defaults to `30000`. It should be longer than the event's callback takes.
+    pub fn ack_wait(mut self, ack_wait: u32) -> Self {
+        self.ack_wait = ack_wait;
+        self
+    }
+}
+
+impl Default for DurableConsumer {
+    fn default() -> Self {
+        Self {
+            name: None,
+            max_deliver: 5,
+            ack_wait: 30_000,
+        }
+    }
 }
 
 /// Builder for [Event].
@@ -73,6 +580,8 @@ pub struct EventBuilder {
     name: String,
     params: Option<Value>,
     callback: Option<Callback<Event>>,
+    concurrency: Option<ListenerConcurrency>,
+    dedup: Option<EventDedup>,
 }
 
 /// Build using [EventBuilder]
@@ -81,8 +590,15 @@ pub struct Event {
     name: String,
     #[serde(default)]
     params: Option<Value>,
+    // the group an emitted event is balanced within, the name of the service when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) group: Option<String>,
     #[serde(skip)]
     pub(crate) callback: Option<Callback<Event>>,
+    #[serde(skip)]
+    pub(crate) queue: Option<Arc<ActionQueue>>,
+    #[serde(skip)]
+    pub(crate) seen_ids: Option<Arc<SeenIds>>,
 }
 
 impl EventBuilder {
@@ -103,11 +619,38 @@ impl EventBuilder {
         self
     }
 
+    /// Limit the callbacks of the event running at once, see [ListenerConcurrency]. Without it
+    /// the callback runs on the broker for every event.
+    pub fn concurrency(mut self, concurrency: ListenerConcurrency) -> Self {
+        self.concurrency = Some(concurrency);
+        self
+    }
+
+    /// Skip events already handled by this listener, see [EventDedup]. Off by default, it
+    /// keeps the ids of the recent events.
+    pub fn dedup(mut self, dedup: EventDedup) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
     pub fn build(self) -> Event {
         Event {
             name: self.name,
             params: self.params,
+            group: None,
             callback: self.callback,
+            queue: self.concurrency.map(|concurrency| {
+                Arc::new(ActionQueue::new(
+                    concurrency.concurrency,
+                    concurrency.max_queued(),
+                ))
+            }),
+            seen_ids: self.dedup.map(|dedup| {
+                Arc::new(SeenIds::new(
+                    Duration::from_millis(dedup.ttl as u64),
+                    dedup.max_ids,
+                ))
+            }),
         }
     }
 }
@@ -118,6 +661,13 @@ pub struct ActionBuilder {
     name: String,
     params: Option<Value>,
     callback: Option<Callback<Action>>,
+    execution: Execution,
+    worker_pool: Option<WorkerPool>,
+    default_params: Option<Value>,
+    params_merge: ParamsMerge,
+    hooks: ActionHooks,
+    retries: Option<u32>,
+    visibility: Visibility,
 }
 
 impl ActionBuilder {
@@ -138,21 +688,116 @@ impl ActionBuilder {
         self
     }
 
+    /// Run the callback off the broker, defaults to [`Execution::Inline`].
+    /// ```rust, ignore
+    /// let resize_image = ActionBuilder::new("resizeImage")
+    ///     .add_callback(resize_image)
+    ///     .execution(Execution::Blocking)
+    ///     .build();
+    /// ```
+    pub fn execution(mut self, execution: Execution) -> Self {
+        self.execution = execution;
+        self
+    }
+
+    /// Run the action's requests on its own [WorkerPool].
+    /// ```rust, ignore
+    /// let resize_image = ActionBuilder::new("resizeImage")
+    ///     .add_callback(resize_image)
+    ///     .execution(Execution::Blocking)
+    ///     .worker_pool(WorkerPool::default().concurrency(2).max_queue_size(20))
+    ///     .build();
+    /// ```
+    pub fn worker_pool(mut self, worker_pool: WorkerPool) -> Self {
+        self.worker_pool = Some(worker_pool);
+        self
+    }
+
+    /// Values of the params the caller left out, merged into the params of each request before
+    /// the interceptors and the callback see them. Params provided by the caller always win,
+    /// including an explicit `null`; the defaults are used as is when the request has no params.
+    /// ```rust, ignore
+    /// let search = ActionBuilder::new("search")
+    ///     .add_callback(search)
+    ///     .default_params(json!({ "limit": 20, "sort": { "field": "date", "order": "desc" } }))
+    ///     .params_merge(ParamsMerge::Deep)
+    ///     .build();
+    /// ```
+    pub fn default_params(mut self, default_params: Value) -> Self {
+        self.default_params = Some(default_params);
+        self
+    }
+
+    /// How the default params are merged, defaults to [`ParamsMerge::Shallow`]. With the
+    /// example above, params `{ "sort": { "field": "price" } }` become
+    /// `{ "limit": 20, "sort": { "field": "price" } }`, or with [`ParamsMerge::Deep`]
+    /// `{ "limit": 20, "sort": { "field": "price", "order": "desc" } }`.
+    pub fn params_merge(mut self, params_merge: ParamsMerge) -> Self {
+        self.params_merge = params_merge;
+        self
+    }
+
+    /// Run [ActionHooks] around the callback.
+    pub fn hooks(mut self, hooks: ActionHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Times a call to the action is retried when it fails with a retryable error, overrides the
+    /// `retries` of the caller's [RetryPolicy][crate::config::RetryPolicy], `0` disables them.
+    /// Sent in the action's `retryPolicy`, so callers on other nodes, JS ones included, apply it
+    /// too. A call's own [`CallOptions::retries()`][crate::CallOptions::retries()] wins.
+    /// ```rust, ignore
+    /// let charge = ActionBuilder::new("charge")
+    ///     .add_callback(charge)
+    ///     .retries(0)
+    ///     .build();
+    /// ```
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Who can call the action, defaults to [`Visibility::Published`]. A `Protected` action is
+    /// only called by the services of this node, and never exposed by an API gateway.
+    /// ```rust, ignore
+    /// let reindex = ActionBuilder::new("reindex")
+    ///     .add_callback(reindex)
+    ///     .visibility(Visibility::Protected)
+    ///     .build();
+    /// ```
+    pub fn visibility(mut self, visibility: Visibility) -> Self {
+        self.visibility = visibility;
+        self
+    }
+
     pub fn build(self) -> Action {
         Action {
             name: self.name,
             params: self.params,
             callback: self.callback,
+            execution: self.execution,
+            queue: self
+                .worker_pool
+                .map(|pool| Arc::new(ActionQueue::new(pool.concurrency, pool.max_queue_size))),
+            default_params: self.default_params,
+            params_merge: self.params_merge,
+            hooks: self.hooks,
+            retry_policy: self.retries.map(|retries| ActionRetryPolicy {
+                enabled: true,
+                retries,
+            }),
+            visibility: self.visibility,
         }
     }
 }
 
 /// A Moleculer service containing [Events][Event] and [Actions][Action]
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Service {
-    name: String,
-    version: Option<i32>,
+    pub(crate) name: String,
+    pub(crate) version: Option<i32>,
 
     #[serde(default)]
     #[serde(skip_deserializing)]
@@ -162,6 +807,12 @@ pub struct Service {
 
     pub(crate) actions: HashMap<String, Action>,
     pub(crate) events: HashMap<String, Event>,
+
+    #[serde(skip)]
+    pub(crate) started: Option<StartedHook>,
+
+    #[serde(skip)]
+    pub(crate) durable_consumer: Option<DurableConsumer>,
 }
 
 impl Service {
@@ -177,6 +828,20 @@ impl Service {
         self
     }
 
+    /// Set a hook that runs when the service is added to the broker, before its actions are
+    /// advertised to the other nodes. The service isn't added if the hook returns an error.
+    pub fn set_started(mut self, started: StartedHook) -> Self {
+        self.started = Some(started);
+        self
+    }
+
+    /// Receive the [durable events][crate::config::DurableEvents] the service listens to from
+    /// their JetStream stream, see [DurableConsumer].
+    pub fn set_durable_consumer(mut self, durable_consumer: DurableConsumer) -> Self {
+        self.durable_consumer = Some(durable_consumer);
+        self
+    }
+
     pub fn add_action(mut self, action: Action) -> Self {
         self.actions.insert(action.name.clone(), action);
         self
@@ -188,6 +853,102 @@ impl Service {
     }
 }
 
+/// Description of an action, returned by
+/// [`ServiceBroker::describe_actions()`][crate::ServiceBroker::describe_actions()].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActionDescription {
+    pub name: String,
+    pub service: String,
+    pub version: Option<i32>,
+    /// The params schema set with [`ActionBuilder::add_params()`], as is
+    pub params: Option<Value>,
+    /// Only `Published` actions should be exposed by an API gateway
+    pub visibility: Visibility,
+    /// Nodes that have the action, sorted. Other nodes only list their `Protected` and
+    /// `Private` actions to themselves
+    pub nodes: Vec<String>,
+}
+
+impl ActionDescription {
+    pub(crate) fn new(service: &Service, action: &Action) -> Self {
+        Self {
+            name: action.name.clone(),
+            service: service.name.clone(),
+            version: service.version,
+            params: action.params.clone(),
+            visibility: action.visibility,
+            nodes: vec![],
+        }
+    }
+}
+
+/// A node an action can be called on, returned by
+/// [`ServiceBroker::action_endpoints()`][crate::ServiceBroker::action_endpoints()].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Endpoint {
+    #[serde(rename = "nodeID")]
+    pub node_id: String,
+    pub action: String,
+    pub service: String,
+    pub version: Option<i32>,
+    /// Only `Published` actions should be exposed by an API gateway, the `Protected` and
+    /// `Private` actions of other nodes aren't endpoints
+    pub visibility: Visibility,
+    /// Whether the node is this one
+    pub local: bool,
+    /// `false` while the node misses the `PING`s of the
+    /// [liveness checks][crate::config::LivenessChecks], or while this node drains. Nodes that
+    /// stop sending heartbeats aren't endpoints anymore
+    pub available: bool,
+    /// The metadata of the node, see `meta_data` in the [Config][crate::config::Config]
+    pub node_metadata: Value,
+}
+
+/// A service on a node, returned by
+/// [`ServiceBroker::service_list()`][crate::ServiceBroker::service_list()].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceInfo {
+    pub name: String,
+    pub version: Option<i32>,
+    #[serde(rename = "nodeID")]
+    pub node_id: String,
+    /// Whether the node is this one
+    pub local: bool,
+    /// See [`Endpoint::available`]
+    pub available: bool,
+    /// The metadata of the service as advertised by its node, ex: by a Moleculer JS service
+    /// with a `metadata` property
+    pub metadata: Option<Value>,
+    /// Names of the actions of the service, sorted
+    pub actions: Vec<String>,
+    /// Names of the events the service listens to, sorted
+    pub events: Vec<String>,
+}
+
+impl ServiceInfo {
+    pub(crate) fn new(node_id: &str, local: bool, available: bool, service: &Service) -> Self {
+        let sorted = |names: Vec<&String>| {
+            let mut names: Vec<String> = names.into_iter().cloned().collect();
+            names.sort();
+            names
+        };
+
+        Self {
+            name: service.name.clone(),
+            version: service.version,
+            node_id: node_id.to_string(),
+            local,
+            available,
+            metadata: service.metadata.clone(),
+            actions: sorted(service.actions.keys().collect()),
+            events: sorted(service.events.keys().collect()),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum EventType {
@@ -200,7 +961,8 @@ pub enum EventType {
 /// In an [action][Action] context you can send a response to the request using [`reply()`][Self::reply()]
 ///
 /// In all contexts [`emit()`][Self::emit()], [`broadcast()`][Self::broadcast()] and
-/// [`call()`][Self::call()] are available
+/// [`call()`][Self::call()] are available. They go through the [ServiceBroker] the context was
+/// created by, so nodes are picked from its registry like calls made on the broker itself.
 pub struct Context<T> {
     phantom: PhantomData<T>,
 
@@ -213,6 +975,8 @@ pub struct Context<T> {
     pub event_type: Option<EventType>,
     pub event_groups: Vec<String>,
 
+    /// Fully-qualified name of the action that made this call or emitted this event,
+    /// `None` when it originated outside of any action.
     pub caller: Option<String>,
     pub request_id: Option<String>,
     pub parent_id: Option<String>,
@@ -222,10 +986,37 @@ pub struct Context<T> {
     pub locals: Option<Value>,
 
     pub level: i32,
+
+    /// When the caller stops waiting for the response, from the `timeout` of the request. A
+    /// request whose deadline passed before its callback started, ex: while queued in a
+    /// [WorkerPool], is skipped. Callbacks replying later, ex: from a spawned task, can stop
+    /// their work once it passed, the reply would be dropped by the caller. `None` in events
+    /// and for calls without a timeout.
+    ///
+    /// The protocol sends the timeout as a duration, not as a point in time: the deadline is
+    /// measured from when this node received the request, on its own monotonic clock, so it
+    /// isn't affected by clock skew between the nodes. The time the request spent in transit
+    /// isn't counted, the deadline is slightly later than the caller's. With
+    /// `cap_request_timeouts` in the [Config][crate::config::Config], it is at most
+    /// `request_timeout` away.
+    pub deadline: Option<Instant>,
+
+    // when the ttl of an event passes, see `expires_at()`
+    pub(crate) expires_at: Option<Instant>,
+    // the clock of the broker, `expires_at` is on it
+    pub(crate) clock: Arc<dyn Clock>,
+
+    /// The chunks of a call made with
+    /// [`ServiceBroker::call_stream()`][crate::ServiceBroker::call_stream()], see
+    /// [stream][crate::stream]. `None` for other calls and events.
+    pub stream: Option<RequestStream>,
+
+    pub(crate) hooks: ActionHooks,
+    pub(crate) response_transformer: Option<ResponseTransformer>,
 }
 
 impl Context<Event> {
-    pub(crate) fn new(event_message: EventMessage, service_broker: ServiceBroker) -> Self {
+    pub(crate) fn new(event_message: PacketEvent, service_broker: ServiceBroker) -> Self {
         let event_type = if event_message.broadcast.unwrap_or(false) {
             EventType::Broadcast
         } else {
@@ -243,28 +1034,55 @@ impl Context<Event> {
 
             event_type: Some(event_type),
             event_name: Some(event_message.event),
-            event_groups: vec![],
+            event_groups: event_message.groups.unwrap_or_default(),
 
-            node_id: event_message.sender,
+            node_id: event_message.envelope.sender,
             caller: event_message.caller,
             parent_id: event_message.parent_id,
             request_id: event_message.request_id,
 
             meta: event_message.meta,
             level: event_message.level,
+            deadline: None,
+            expires_at: None,
+            clock: Arc::new(SystemClock),
 
             locals: None,
+            stream: None,
+
+            hooks: ActionHooks::default(),
+            response_transformer: None,
         }
     }
 }
 
+/// When an event with `meta` received at `received_at` expires, from its `$ttl` and
+/// `$emittedAt`. `now` is the wall clock of this node, see [`Context::expires_at()`]
+pub(crate) fn event_expiry(
+    meta: &Value,
+    received_at: Instant,
+    now: SystemTime,
+    skew_tolerance: Duration,
+) -> Option<Instant> {
+    let ttl = Duration::from_millis(meta.get("$ttl")?.as_u64()?);
+    let emitted_at = Duration::from_millis(meta.get("$emittedAt")?.as_u64()?);
+
+    // zero if the emitting node's clock is ahead of this one
+    let elapsed = now
+        .duration_since(SystemTime::UNIX_EPOCH.checked_add(emitted_at)?)
+        .unwrap_or_default();
+    let late = elapsed.saturating_sub(skew_tolerance);
+
+    received_at.checked_add(ttl.saturating_sub(late))
+}
+
 impl Context<Action> {
-    pub(crate) fn new(request_message: RequestMessage, service_broker: ServiceBroker) -> Self {
+    pub(crate) fn new(request_message: PacketRequest, service_broker: ServiceBroker) -> Self {
         Self {
             phantom: PhantomData,
 
             broker: service_broker,
-            id: request_message.request_id.clone(),
+            id: request_message.id,
             params: request_message.params,
 
             action: Some(request_message.action),
@@ -273,36 +1091,364 @@ impl Context<Action> {
             event_name: None,
             event_groups: vec![],
 
-            node_id: request_message.sender,
+            node_id: request_message.envelope.sender,
             caller: request_message.caller,
             parent_id: request_message.parent_id,
             request_id: Some(request_message.request_id),
 
             meta: request_message.meta,
-            level: 1,
+            level: request_message.level,
+            deadline: None,
+            expires_at: None,
+            clock: Arc::new(SystemClock),
 
             locals: None,
+            stream: None,
+
+            hooks: ActionHooks::default(),
+            response_transformer: None,
         }
     }
 
+    /// Send the response to the request, through the action's `after` hook if it has one, see
+    /// [ActionHooks]
     pub fn reply(&self, params: Value) {
+        let reply = match self.hooks.after {
+            Some(after) => after(self, params),
+            None => Ok(params),
+        };
+
+        match reply {
+            Ok(reply) => self.send_reply(reply),
+            Err(error) => self.reply_error(error),
+        }
+    }
+
+    /// Send an error response to the request, the caller receives it as
+    /// [`Error::ActionError`][crate::Error::ActionError]. Goes through the action's `error` hook
+    /// if it has one, see [ActionHooks]
+    pub fn reply_error(&self, error: MoleculerError) {
+        let reply = match self.hooks.error {
+            Some(hook) => hook(self, error),
+            None => Err(error),
+        };
+
+        match reply {
+            Ok(reply) => self.send_reply(reply),
+            Err(error) => act_zero::send!(self.broker.addr.reply_error(
+                self.node_id.clone(),
+                self.id.clone(),
+                error
+            )),
+        }
+    }
+
+    fn send_reply(&self, reply: Value) {
+        let reply = match self.response_transformer {
+            Some(transform) => transform(self, reply),
+            None => reply,
+        };
+
         act_zero::send!(self
             .broker
             .addr
-            .reply(self.node_id.clone(), self.id.clone(), params));
+            .reply(self.node_id.clone(), self.id.clone(), reply));
+    }
+
+    // the callback takes its context, the error hook gets a copy when the callback fails
+    pub(crate) fn error_hook_context(&self) -> Option<Self> {
+        self.hooks.error?;
+
+        Some(Self {
+            phantom: PhantomData,
+
+            id: self.id.clone(),
+            broker: self.broker.clone(),
+            node_id: self.node_id.clone(),
+            action: self.action.clone(),
+
+            event_name: None,
+            event_type: None,
+            event_groups: vec![],
+
+            caller: self.caller.clone(),
+            request_id: self.request_id.clone(),
+            parent_id: self.parent_id.clone(),
+
+            params: self.params.clone(),
+            meta: self.meta.clone(),
+            locals: self.locals.clone(),
+
+            level: self.level,
+            deadline: self.deadline,
+            expires_at: None,
+            clock: Arc::clone(&self.clock),
+
+            stream: None,
+            hooks: self.hooks,
+            response_transformer: self.response_transformer,
+        })
     }
 }
 
 impl<T> Context<T> {
+    pub fn params(&self) -> &Value {
+        &self.params
+    }
+
+    /// Deserialize the params into `T` without copying them, unlike
+    /// `serde_json::from_value(ctx.params.clone())`. Strings can be borrowed with `&str` fields
+    /// (or `Cow<str>` with `#[serde(borrow)]`), which matters for large bodies:
+    /// ```rust, ignore
+    /// #[derive(Deserialize)]
+    /// struct Forward<'a> {
+    ///     path: &'a str,
+    ///     body: &'a str,
+    /// }
+    ///
+    /// let forward: Forward = ctx.params_borrowed()?;
+    /// ```
+    ///
+    /// The result borrows the context: it can't be kept once the context is dropped, or moved
+    /// into a spawned task, use owned types for those. The params are still copied once out of
+    /// the packet when it is received, the packet's buffer is released before the handler runs.
+    pub fn params_borrowed<'a, D: Deserialize<'a>>(&'a self) -> Result<D, serde_json::Error> {
+        D::deserialize(&self.params)
+    }
+
+    pub fn meta(&self) -> &Value {
+        &self.meta
+    }
+
+    /// When the event stops being worth handling, from the ttl it was emitted with, see
+    /// [`EventOptions::ttl()`][crate::EventOptions::ttl()]. `None` for events without a ttl, or
+    /// emitted by nodes that don't send one, and for calls.
+    ///
+    /// The ttl is counted from when the event was received, less the time since it was emitted
+    /// by the wall clocks of both nodes: an event that waited in a stream or a queue expires
+    /// sooner. Up to `event_ttl_skew_tolerance` in the [Config][crate::config::Config] of that
+    /// time isn't counted, so a receiving clock slightly ahead doesn't expire fresh events, and
+    /// an event "emitted in the future" by a node whose clock is ahead gets its whole ttl.
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.expires_at
+    }
+
+    /// Whether the ttl of the event passed, see [`expires_at()`][Self::expires_at()]. `false`
+    /// without a ttl. The broker delivers expired events, listeners skip them if they want to:
+    /// ```rust, ignore
+    /// fn on_price_updated(ctx: EventContext) -> Result<(), Box<dyn Error>> {
+    ///     if ctx.is_expired() {
+    ///         return Ok(());
+    ///     }
+    ///
+    ///     update_price(ctx.params)
+    /// }
+    /// ```
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| self.clock.now() >= expires_at)
+    }
+
+    /// Emit an event, see [`ServiceBroker::emit()`]. Like [`call()`][Self::call()], the event
+    /// inherits this context's `meta` and `request_id`, its `caller` is this context's action
+    /// and its level is one more than this context's.
     pub fn emit<S: Into<String>>(&self, event: S, params: Value) {
-        self.broker.emit(event, params)
+        let options = self.event_options(EventOptions::default());
+        self.broker.emit_detached(event.into(), params, options)
     }
 
+    /// Broadcast an event, it inherits this context like [`emit()`][Self::emit()].
     pub fn broadcast<S: Into<String>>(&self, event: S, params: Value) {
-        self.broker.broadcast(event, params)
+        let options = self.event_options(EventOptions::default());
+        self.broker
+            .broadcast_detached(event.into(), params, options)
     }
 
+    /// Emit an event with [EventOptions], see [`ServiceBroker::emit_with_options()`]. It
+    /// inherits this context like [`emit()`][Self::emit()], the fields of the options' meta win
+    /// over the ones of this context's.
+    pub async fn emit_with_options<S: Into<String>>(
+        &self,
+        event: S,
+        params: Value,
+        options: EventOptions,
+    ) -> Result<(), Error> {
+        let options = self.event_options(options);
+        self.broker.emit_with_options(event, params, options).await
+    }
+
+    /// Broadcast an event with [EventOptions], see
+    /// [`emit_with_options()`][Self::emit_with_options()].
+    pub async fn broadcast_with_options<S: Into<String>>(
+        &self,
+        event: S,
+        params: Value,
+        options: EventOptions,
+    ) -> Result<(), Error> {
+        let options = self.event_options(options);
+        self.broker
+            .broadcast_with_options(event, params, options)
+            .await
+    }
+
+    // the options of an event emitted from this context
+    pub(crate) fn event_options(&self, options: EventOptions) -> EventOptions {
+        let mut meta = match &self.meta {
+            Value::Object(meta) => meta.clone(),
+            _ => serde_json::Map::new(),
+        };
+
+        if let Value::Object(options_meta) = options.meta {
+            meta.extend(options_meta);
+        }
+
+        EventOptions {
+            meta: Value::Object(meta),
+            caller: self.action.clone(),
+            level: self.level,
+            parent_id: Some(self.id.clone()),
+            request_id: self.request_id.clone(),
+            ..options
+        }
+    }
+
+    /// Call an action, the request's `caller` is set to this context's action (`None` in
+    /// event handlers) so nested calls can be traced back to their immediate parent.
+    ///
+    /// The request inherits this context's `meta` and `request_id`, and its level is one more
+    /// than this context's. Calls beyond `max_call_level` in [Config][crate::config::Config]
+    /// fail with a [`MoleculerError::max_call_level()`] error.
     pub async fn call<S: Into<String>>(self, action: S, params: Value) -> Result<Value, Error> {
-        self.broker.call(action, params).await
+        let options = CallOptions {
+            caller: self.action,
+            level: self.level,
+            parent_id: Some(self.id),
+            request_id: self.request_id,
+            meta: self.meta,
+            ..CallOptions::default()
+        };
+
+        self.broker.call_with_options(action, params, options).await
+    }
+
+    /// Call an action that takes no params, see [`ServiceBroker::call_no_params()`].
+    pub async fn call_no_params<S: Into<String>>(self, action: S) -> Result<Value, Error> {
+        self.call(action, Value::Object(Default::default())).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn search(params_merge: ParamsMerge) -> Action {
+        ActionBuilder::new("search")
+            .default_params(json!({ "limit": 20, "sort": { "field": "date", "order": "desc" } }))
+            .params_merge(params_merge)
+            .build()
+    }
+
+    #[test]
+    fn partial_params_are_merged_with_the_defaults() {
+        let mut params = json!({ "query": "rust", "limit": 5 });
+        search(ParamsMerge::Shallow).apply_default_params(&mut params);
+
+        assert_eq!(
+            params,
+            json!({
+                "query": "rust",
+                "limit": 5,
+                "sort": { "field": "date", "order": "desc" },
+            })
+        );
+    }
+
+    #[test]
+    fn nested_params_are_merged_only_when_deep() {
+        let mut shallow = json!({ "sort": { "field": "price" } });
+        search(ParamsMerge::Shallow).apply_default_params(&mut shallow);
+        assert_eq!(
+            shallow,
+            json!({ "limit": 20, "sort": { "field": "price" } })
+        );
+
+        let mut deep = json!({ "sort": { "field": "price" } });
+        search(ParamsMerge::Deep).apply_default_params(&mut deep);
+        assert_eq!(
+            deep,
+            json!({ "limit": 20, "sort": { "field": "price", "order": "desc" } })
+        );
+    }
+
+    #[test]
+    fn params_provided_by_the_caller_win() {
+        let mut params = json!({ "limit": null });
+        search(ParamsMerge::Deep).apply_default_params(&mut params);
+        assert_eq!(params["limit"], Value::Null);
+
+        let mut missing = Value::Null;
+        search(ParamsMerge::Shallow).apply_default_params(&mut missing);
+        assert_eq!(missing["limit"], 20);
+
+        let mut single_value = json!("rust");
+        search(ParamsMerge::Shallow).apply_default_params(&mut single_value);
+        assert_eq!(single_value, json!("rust"));
+    }
+
+    // the expiry of an event with a 10s ttl, emitted `emitted_ago` ago by the clock of this
+    // node (negative for a node whose clock is ahead), received now
+    fn expiry_after(emitted_ago: i64) -> Duration {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let emitted_at = if emitted_ago >= 0 {
+            now - Duration::from_millis(emitted_ago as u64)
+        } else {
+            now + Duration::from_millis(emitted_ago.unsigned_abs())
+        };
+        let emitted_at = emitted_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        let meta = json!({ "$ttl": 10_000, "$emittedAt": emitted_at });
+        let received_at = Instant::now();
+
+        event_expiry(&meta, received_at, now, Duration::from_secs(1))
+            .unwrap()
+            .duration_since(received_at)
+    }
+
+    #[test]
+    fn event_expiry_counts_the_time_since_the_event_was_emitted() {
+        assert_eq!(expiry_after(0), Duration::from_secs(10));
+        // the skew tolerance isn't counted
+        assert_eq!(expiry_after(5000), Duration::from_secs(6));
+        assert_eq!(expiry_after(20_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn event_expiry_tolerates_clock_skew() {
+        assert_eq!(expiry_after(800), Duration::from_secs(10));
+        // emitted by a node whose clock is ahead
+        assert_eq!(expiry_after(-60_000), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn events_without_a_ttl_dont_expire() {
+        let now = SystemTime::now();
+
+        assert_eq!(
+            event_expiry(&json!({}), Instant::now(), now, Duration::ZERO),
+            None
+        );
+        assert_eq!(
+            event_expiry(
+                &json!({ "$ttl": 1000 }),
+                Instant::now(),
+                now,
+                Duration::ZERO
+            ),
+            None
+        );
     }
 }
@@ -0,0 +1,302 @@
+/*!
+Streamed params of a call, to upload a large payload to an action in chunks.
+
+The caller sends a stream of byte chunks with
+[`ServiceBroker::call_stream()`][crate::ServiceBroker::call_stream()], the action's handler reads
+them from the [RequestStream] in its [Context][crate::service::Context]:
+
+```rust, ignore
+fn upload(mut ctx: ActionContext) -> Result<(), Box<dyn Error>> {
+    let mut stream = ctx.stream.take().ok_or("expected a stream")?;
+
+    tokio::spawn(async move {
+        let mut size = 0;
+
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(bytes) => size += bytes.len(),
+                Err(err) => return ctx.reply_error(err),
+            }
+        }
+
+        ctx.reply(json!({ "size": size }));
+    });
+
+    Ok(())
+}
+```
+
+Chunks use the streaming protocol of Moleculer JS: a `REQ` packet with `stream: true` and
+`seq: 0` starts the call, each chunk follows in its own packet with the next `seq`, and a last
+packet with `stream: false` ends it. Chunks are sent as Node.js serializes a `Buffer` to JSON
+(`{"type": "Buffer", "data": [...]}`, see [crate::buffer]), so JS handlers can read them as a
+Node stream. Chunks larger than `max_chunk_size` in the [Transit][crate::config::Transit]
+options are split.
+
+The caller reads the next chunk from its stream only once the previous one was handed to the
+transporter. The handler's node buffers the chunks it hasn't read yet, the protocol has no way
+to slow down the caller. If the caller drops the call, or its node disconnects, the handler's
+stream ends with an error.
+*/
+
+use std::{
+    collections::BTreeMap,
+    future::poll_fn,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use act_zero::*;
+use futures_core::Stream;
+use log::error;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::{
+    buffer,
+    channels::{
+        messages::{outgoing, PacketType},
+        ChannelSupervisor,
+    },
+    config::{self, Config, PacketDirection, SerializeError, Serializer},
+    errors::MoleculerError,
+};
+
+/// The chunks of a streamed call, in the order they were sent.
+///
+/// Ends with `None` once the caller sent its last chunk, or with an `Err` if the caller aborted
+/// the call or sent an error.
+#[derive(Debug)]
+pub struct RequestStream {
+    chunks: mpsc::UnboundedReceiver<Result<Vec<u8>, MoleculerError>>,
+}
+
+impl RequestStream {
+    pub(crate) fn new(sender: String) -> (Self, RequestStreamWriter) {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let writer = RequestStreamWriter {
+            sender,
+            chunks: tx,
+            next_seq: 1,
+            pending: BTreeMap::new(),
+        };
+
+        (Self { chunks: rx }, writer)
+    }
+
+    /// The next chunk, `None` once the stream ended.
+    pub async fn next(&mut self) -> Option<Result<Vec<u8>, MoleculerError>> {
+        self.chunks.recv().await
+    }
+}
+
+impl Stream for RequestStream {
+    type Item = Result<Vec<u8>, MoleculerError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.chunks.poll_recv(cx)
+    }
+}
+
+/// A packet of a streamed call after the one that started it
+pub(crate) enum Chunk {
+    Data(Vec<u8>),
+    End,
+    Error(MoleculerError),
+}
+
+impl Chunk {
+    pub(crate) fn new(stream: Option<bool>, params: Value, meta: &Value) -> Self {
+        if stream == Some(true) {
+            return Chunk::Data(decode_chunk(params));
+        }
+
+        match meta.get("$streamError") {
+            Some(error) => Chunk::Error(
+                serde_json::from_value(error.clone())
+                    .unwrap_or_else(|_| stream_aborted("the caller sent an invalid stream error")),
+            ),
+            None => Chunk::End,
+        }
+    }
+}
+
+/// Feeds the chunks received for a streamed call to the handler's [RequestStream]
+pub(crate) struct RequestStreamWriter {
+    pub(crate) sender: String,
+    chunks: mpsc::UnboundedSender<Result<Vec<u8>, MoleculerError>>,
+    next_seq: i32,
+    // chunks that arrived before the ones preceding them
+    pending: BTreeMap<i32, Chunk>,
+}
+
+impl RequestStreamWriter {
+    /// Returns `false` once the stream is over: it ended, or the handler dropped its stream
+    pub(crate) fn push(&mut self, seq: i32, chunk: Chunk) -> bool {
+        self.pending.insert(seq, chunk);
+
+        while let Some(chunk) = self.pending.remove(&self.next_seq) {
+            self.next_seq += 1;
+
+            match chunk {
+                Chunk::Data(bytes) => {
+                    if self.chunks.send(Ok(bytes)).is_err() {
+                        return false;
+                    }
+                }
+                Chunk::End => return false,
+                Chunk::Error(error) => {
+                    let _ = self.chunks.send(Err(error));
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    pub(crate) fn abort(&self, reason: &str) {
+        let _ = self.chunks.send(Err(stream_aborted(reason)));
+    }
+}
+
+/// Sends the chunks of a streamed call, the call was started by the broker with the packet
+/// `seq: 0`
+pub(crate) struct RequestStreamSender {
+    pub(crate) config: Arc<Config>,
+    pub(crate) channel_supervisor: Addr<ChannelSupervisor>,
+    pub(crate) channel: String,
+    pub(crate) serializer: Serializer,
+
+    pub(crate) action: String,
+    pub(crate) id: String,
+    pub(crate) request_id: String,
+    pub(crate) level: i32,
+    pub(crate) caller: Option<String>,
+    pub(crate) parent_id: Option<String>,
+
+    pub(crate) seq: i32,
+    pub(crate) finished: bool,
+}
+
+impl RequestStreamSender {
+    pub(crate) async fn send_all<C>(mut self, mut chunks: C)
+    where
+        C: Stream<Item = Vec<u8>> + Unpin,
+    {
+        let max_chunk_size = self.config.transit.max_chunk_size.max(1) as usize;
+
+        while let Some(chunk) = poll_fn(|cx| Pin::new(&mut chunks).poll_next(cx)).await {
+            for part in chunk.chunks(max_chunk_size) {
+                if let Err(err) = self.send(buffer::to_value(part), true, json!({})).await {
+                    // dropping the sender aborts the stream
+                    error!(
+                        "Unable to send chunk of '{}' request stream: {}",
+                        self.action, err
+                    );
+                    return;
+                }
+            }
+        }
+
+        if let Err(err) = self.send(Value::Null, false, json!({})).await {
+            error!("Unable to end '{}' request stream: {}", self.action, err);
+            return;
+        }
+
+        self.finished = true;
+    }
+
+    // waits until the transporter took the chunk before the next one is read
+    async fn send(
+        &mut self,
+        params: Value,
+        stream: bool,
+        meta: Value,
+    ) -> Result<(), SerializeError> {
+        self.seq += 1;
+        let message = self.serialize(params, stream, meta)?;
+
+        let _ = call!(self
+            .channel_supervisor
+            .publish_to_channel(self.channel.clone(), message))
+        .await;
+
+        Ok(())
+    }
+
+    fn serialize(
+        &self,
+        params: Value,
+        stream: bool,
+        meta: Value,
+    ) -> Result<Vec<u8>, SerializeError> {
+        let packet = outgoing::PacketRequest {
+            envelope: outgoing::Envelope::new(&self.config),
+            id: self.id.clone(),
+            action: &self.action,
+            params,
+            meta,
+            timeout: self.config.request_timeout as f32,
+            level: self.level,
+            tracing: None,
+            parent_id: self.parent_id.as_deref(),
+            request_id: self.request_id.clone(),
+            caller: self.caller.as_deref(),
+            stream: Some(stream),
+            seq: Some(self.seq),
+        };
+
+        config::log_packet(
+            &self.config,
+            PacketDirection::Outbound,
+            PacketType::Request,
+            &packet,
+        );
+        self.config
+            .serialize_with(&self.serializer, PacketType::Request, packet)
+    }
+}
+
+// the caller dropped the call before the last chunk, end the handler's stream with an error
+impl Drop for RequestStreamSender {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        self.seq += 1;
+        let error = stream_aborted("the caller aborted the call");
+
+        match self.serialize(Value::Null, false, json!({ "$streamError": error })) {
+            Ok(message) => send!(self
+                .channel_supervisor
+                .publish_to_channel(self.channel.clone(), message)),
+            Err(err) => error!("Unable to abort '{}' request stream: {}", self.action, err),
+        }
+    }
+}
+
+fn stream_aborted(reason: &str) -> MoleculerError {
+    MoleculerError::new(
+        "StreamAbortedError",
+        format!("Request stream aborted: {}", reason),
+        500,
+        "STREAM_ABORTED",
+    )
+}
+
+// chunks from JS callers are serialized Buffers, or any value in object mode
+fn decode_chunk(params: Value) -> Vec<u8> {
+    if let Some(bytes) = buffer::from_value(&params) {
+        return bytes;
+    }
+
+    match params {
+        Value::String(string) => string.into_bytes(),
+        Value::Null => vec![],
+        value => serde_json::to_vec(&value).unwrap_or_default(),
+    }
+}
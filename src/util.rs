@@ -1,7 +1,10 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, time::SystemTime};
 
+use log::warn;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use serde_json::Value;
+use uuid::Uuid;
 
 fn random_string_iter(take: usize) -> impl Iterator<Item = char> {
     thread_rng()
@@ -29,18 +32,83 @@ pub(crate) fn gen_node_id() -> String {
     node_id.to_lowercase()
 }
 
+// the same seed on the same host always gives the same node id
+pub(crate) fn seeded_node_id(seed: &str, hostname: &str) -> String {
+    format!("{}.{}", hostname, seed).to_lowercase()
+}
+
 pub(crate) fn hostname() -> Cow<'static, str> {
     hostname::get()
         .map(|s| Cow::Owned(s.to_string_lossy().to_string().to_lowercase()))
         .unwrap_or_else(|_| Cow::Borrowed("unknown_host_name"))
 }
 
+// nodes connecting to each other directly (ex: with a TCP transporter) can't reach a node
+// advertising no address, `ip_list` in the config is the way out
 pub(crate) fn ip_list() -> Vec<String> {
-    get_if_addrs::get_if_addrs()
-        .unwrap_or_default()
+    let interfaces = match get_if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(err) => {
+            warn!(
+                "Unable to list the network interfaces, no IP address is advertised (set `ip_list` in the config): {}",
+                err
+            );
+            return vec![];
+        }
+    };
+
+    let ip_list: Vec<String> = interfaces
         .iter()
         .map(|interface| interface.addr.ip())
         .filter(|ip| ip.is_ipv4() && !ip.is_loopback())
         .map(|ip| ip.to_string())
+        .collect();
+
+    if ip_list.is_empty() {
+        warn!("No IPv4 address found on the network interfaces, set `ip_list` in the config to advertise one");
+    }
+
+    ip_list
+}
+
+/// UUID version 7: 48 bits of unix time in milliseconds followed by random bits
+pub(crate) fn uuid_v7() -> Uuid {
+    let millis = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("now should always be after unix epoch")
+        .as_millis() as u64;
+
+    let mut bytes: [u8; 16] = thread_rng().gen();
+    bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+    bytes[6] = (bytes[6] & 0x0f) | 0x70; // version 7
+    bytes[8] = (bytes[8] & 0x3f) | 0x80; // RFC 4122 variant
+
+    Uuid::from_bytes(bytes)
+}
+
+/// 21 characters from the url-safe alphabet, as generated by nanoid's default settings
+pub(crate) fn nanoid() -> String {
+    const ALPHABET: &[u8] = b"_-0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+    let mut rng = thread_rng();
+    (0..21)
+        .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
         .collect()
 }
+
+// FNV-1a, the hash must be the same on every node and across Rust versions
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// a dotted path into objects and arrays, ex: `user.addresses.0.city`
+pub(crate) fn value_at_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(value, |value, segment| match value {
+            Value::Object(map) => map.get(segment),
+            Value::Array(values) => values.get(segment.parse::<usize>().ok()?),
+            _ => None,
+        })
+}